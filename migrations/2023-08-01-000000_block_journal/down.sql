@@ -0,0 +1,29 @@
+DROP FUNCTION rollback_to_height;
+DROP FUNCTION journal_key_columns;
+
+DO $$
+DECLARE
+  t TEXT;
+BEGIN
+  FOREACH t IN ARRAY ARRAY[
+    'access_keys',
+    'accounts',
+    'mb_store_minters',
+    'nft_activities',
+    'nft_approvals',
+    'nft_contracts',
+    'nft_earnings',
+    'nft_external_listings',
+    'nft_listings',
+    'nft_offers',
+    'nft_tokens'
+  ] LOOP
+    EXECUTE format('DROP TRIGGER %I ON %I', t || '_journal', t);
+  END LOOP;
+END $$;
+
+DROP FUNCTION journal_block_write;
+DROP TABLE block_journal;
+DROP TABLE block_hashes;
+ALTER TABLE blocks DROP COLUMN journal_height;
+ALTER TABLE blocks DROP COLUMN synced_hash;
@@ -0,0 +1,140 @@
+-- Hash of the block at `synced_height`, to check that the next block builds
+-- on top of it
+ALTER TABLE blocks ADD COLUMN synced_hash TEXT;
+-- Height of the block that is currently being processed while following
+-- optimistic heads. Writes are only journaled while this is set, and only
+-- those of sessions with `minterop.journal` turned on, i.e. those of the live
+-- indexer and not of backfills or other services running concurrently.
+ALTER TABLE blocks ADD COLUMN journal_height BIGINT;
+
+-- Hashes of journaled blocks, to find the common ancestor on forks
+CREATE TABLE block_hashes (
+  height BIGINT PRIMARY KEY,
+  hash TEXT NOT NULL
+);
+
+-- Undo log of all writes to indexer tables, per block height
+CREATE TABLE block_journal (
+  id BIGSERIAL PRIMARY KEY,
+  height BIGINT NOT NULL,
+  table_name TEXT NOT NULL,
+  operation TEXT NOT NULL,
+  old_row JSONB,
+  new_row JSONB
+);
+CREATE INDEX block_journal_height_idx ON block_journal (height);
+
+CREATE FUNCTION journal_block_write() RETURNS TRIGGER AS $$
+DECLARE
+  current_height BIGINT;
+BEGIN
+  IF current_setting('minterop.journal', true) IS DISTINCT FROM 'on' THEN
+    RETURN NULL;
+  END IF;
+  SELECT journal_height INTO current_height FROM blocks LIMIT 1;
+  IF current_height IS NOT NULL THEN
+    INSERT INTO block_journal (height, table_name, operation, old_row, new_row)
+    VALUES (
+      current_height,
+      TG_TABLE_NAME,
+      TG_OP,
+      CASE WHEN TG_OP IN ('UPDATE', 'DELETE') THEN to_jsonb(OLD) END,
+      CASE WHEN TG_OP IN ('INSERT', 'UPDATE') THEN to_jsonb(NEW) END
+    );
+  END IF;
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DO $$
+DECLARE
+  t TEXT;
+BEGIN
+  FOREACH t IN ARRAY ARRAY[
+    'access_keys',
+    'accounts',
+    'mb_store_minters',
+    'nft_activities',
+    'nft_approvals',
+    'nft_contracts',
+    'nft_earnings',
+    'nft_external_listings',
+    'nft_listings',
+    'nft_offers',
+    'nft_tokens'
+  ] LOOP
+    EXECUTE format(
+      'CREATE TRIGGER %I AFTER INSERT OR UPDATE OR DELETE ON %I '
+      'FOR EACH ROW EXECUTE FUNCTION journal_block_write()',
+      t || '_journal',
+      t
+    );
+  END LOOP;
+END $$;
+
+-- Primary key columns of a table, comma-separated and quoted, or NULL if it
+-- has none
+CREATE FUNCTION journal_key_columns(t TEXT) RETURNS TEXT AS $$
+  SELECT string_agg(
+    quote_ident(a.attname),
+    ', ' ORDER BY array_position(i.indkey::INT2[], a.attnum)
+  )
+  FROM pg_index i
+  JOIN pg_attribute a
+    ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey::INT2[])
+  WHERE i.indrelid = t::regclass AND i.indisprimary;
+$$ LANGUAGE sql STABLE;
+
+-- Reverts all journaled writes of blocks above `target` in reverse order, and
+-- resets the cursor to `target`. Returns the number of reverted writes.
+CREATE FUNCTION rollback_to_height(target BIGINT) RETURNS BIGINT AS $$
+DECLARE
+  entry block_journal;
+  key_columns TEXT;
+  reverted BIGINT := 0;
+BEGIN
+  -- the rollback itself must not be journaled
+  UPDATE blocks SET journal_height = NULL;
+
+  FOR entry IN
+    SELECT * FROM block_journal WHERE height > target ORDER BY id DESC
+  LOOP
+    IF entry.new_row IS NOT NULL THEN
+      key_columns := journal_key_columns(entry.table_name);
+      IF key_columns IS NOT NULL THEN
+        -- uses the primary key index
+        EXECUTE format(
+          'DELETE FROM %I WHERE (%s) = '
+          '(SELECT %s FROM jsonb_populate_record(NULL::%I, $1))',
+          entry.table_name,
+          key_columns,
+          key_columns,
+          entry.table_name
+        ) USING entry.new_row;
+      ELSE
+        EXECUTE format(
+          'DELETE FROM %I WHERE ctid = '
+          '(SELECT ctid FROM %I t WHERE to_jsonb(t) = $1 LIMIT 1)',
+          entry.table_name,
+          entry.table_name
+        ) USING entry.new_row;
+      END IF;
+    END IF;
+    IF entry.old_row IS NOT NULL THEN
+      EXECUTE format(
+        'INSERT INTO %I SELECT * FROM jsonb_populate_record(NULL::%I, $1)',
+        entry.table_name,
+        entry.table_name
+      ) USING entry.old_row;
+    END IF;
+    reverted := reverted + 1;
+  END LOOP;
+
+  DELETE FROM block_journal WHERE height > target;
+  DELETE FROM block_hashes WHERE height > target;
+  UPDATE blocks SET
+    synced_height = target,
+    synced_hash = (SELECT hash FROM block_hashes WHERE height = target);
+  RETURN reverted;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,2 @@
+DROP INDEX nft_activities_timestamp_idx;
+DROP TABLE backfill_jobs;
@@ -0,0 +1,18 @@
+-- Contract-scoped backfills, which run next to the live indexer and thus keep
+-- their progress here instead of in `blocks.synced_height`
+CREATE TABLE backfill_jobs (
+  job_id TEXT PRIMARY KEY,
+  contracts TEXT[] NOT NULL,
+  start_height BIGINT NOT NULL,
+  stop_height BIGINT NOT NULL,
+  synced_height BIGINT,
+  -- timestamps of the first and the last processed block, which bound the
+  -- activities written by the job
+  first_timestamp TIMESTAMP,
+  synced_timestamp TIMESTAMP,
+  started_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  finished_at TIMESTAMP
+);
+
+-- to find the tokens with activities in a reindexed range
+CREATE INDEX nft_activities_timestamp_idx ON nft_activities (timestamp);
@@ -0,0 +1,3 @@
+DROP VIEW nft_token_ownerships;
+DROP TRIGGER nft_ownership_changes_journal ON nft_ownership_changes;
+DROP TABLE nft_ownership_changes;
@@ -0,0 +1,86 @@
+-- Every mint, transfer and burn of a token, with the owner it left the token
+-- with (NULL once burned). Rows don't depend on each other, such that they
+-- can be written in any order. `block_height` is NULL for rows seeded from
+-- activities that were indexed before this table existed. A receipt may
+-- change the owner of a token several times, which `log_index` (position of
+-- the event log among the logs of the receipt) and `event_index` (position of
+-- the event within the data of its log) tell apart and order.
+CREATE TABLE nft_ownership_changes (
+  nft_contract_id TEXT NOT NULL,
+  token_id TEXT NOT NULL,
+  receipt_id TEXT NOT NULL,
+  log_index INT NOT NULL,
+  event_index INT NOT NULL,
+  block_height BIGINT,
+  timestamp TIMESTAMP NOT NULL,
+  owner TEXT,
+  PRIMARY KEY (
+    nft_contract_id, token_id, receipt_id, log_index, event_index
+  )
+);
+CREATE INDEX nft_ownership_changes_height_idx
+  ON nft_ownership_changes (nft_contract_id, block_height);
+CREATE INDEX nft_ownership_changes_owner_idx
+  ON nft_ownership_changes (owner);
+
+-- Activities don't record their block height, hence ordering by timestamp.
+-- They also keep a single activity per kind, receipt and token, so there is
+-- only one change per receipt to seed.
+INSERT INTO nft_ownership_changes (
+  nft_contract_id,
+  token_id,
+  receipt_id,
+  log_index,
+  event_index,
+  block_height,
+  timestamp,
+  owner
+)
+SELECT
+  nft_contract_id, token_id, receipt_id, 0, 0, NULL, timestamp, action_receiver
+FROM nft_activities
+WHERE kind IN ('mint', 'transfer', 'burn')
+ON CONFLICT DO NOTHING;
+
+CREATE TRIGGER nft_ownership_changes_journal
+  AFTER INSERT OR UPDATE OR DELETE ON nft_ownership_changes
+  FOR EACH ROW EXECUTE FUNCTION journal_block_write();
+
+-- Who held a token from which block until which block (exclusive), a NULL
+-- `to_receipt_id` meaning that the owner still holds it. As long as H is
+-- not below an indexed change after the latest seeded one, seeded changes
+-- precede H, and holders of a collection at height H are:
+--
+--   SELECT * FROM nft_token_ownerships
+--   WHERE nft_contract_id = 'x'
+--     AND (from_height IS NULL OR from_height <= H)
+--     AND (to_receipt_id IS NULL OR to_height > H)
+CREATE VIEW nft_token_ownerships AS
+SELECT
+  nft_contract_id,
+  token_id,
+  owner,
+  from_height,
+  from_timestamp,
+  from_receipt_id,
+  to_height,
+  to_timestamp,
+  to_receipt_id
+FROM (
+  SELECT
+    nft_contract_id,
+    token_id,
+    owner,
+    block_height AS from_height,
+    timestamp AS from_timestamp,
+    receipt_id AS from_receipt_id,
+    lead(block_height) OVER w AS to_height,
+    lead(timestamp) OVER w AS to_timestamp,
+    lead(receipt_id) OVER w AS to_receipt_id
+  FROM nft_ownership_changes
+  WINDOW w AS (
+    PARTITION BY nft_contract_id, token_id
+    ORDER BY timestamp, receipt_id, log_index, event_index
+  )
+) changes
+WHERE owner IS NOT NULL;
@@ -0,0 +1,21 @@
+DROP FUNCTION add_nft_owner_stats(TEXT, TEXT[], TEXT[], TEXT[], TIMESTAMP);
+DROP FUNCTION add_nft_sale_stats(TEXT);
+DROP FUNCTION add_nft_stats(
+  TEXT, TEXT, BIGINT, TEXT, NUMERIC, BIGINT, TIMESTAMP
+);
+DROP FUNCTION refresh_nft_stats(TEXT, TIMESTAMP, TEXT[]);
+DROP FUNCTION nft_owner_stats(TEXT);
+DROP FUNCTION nft_sale_stats(TEXT, TIMESTAMP);
+DROP FUNCTION nft_listing_stats(TEXT);
+DROP VIEW nft_metadata_volumes;
+DROP VIEW nft_collection_volumes;
+DROP VIEW nft_counted_sales;
+DROP INDEX nft_tokens_contract_owner_idx;
+DROP TRIGGER nft_stats_sales_journal ON nft_stats_sales;
+DROP TABLE nft_stats_sales;
+DROP TRIGGER nft_metadata_stats_journal ON nft_metadata_stats;
+DROP TRIGGER nft_collection_stats_journal ON nft_collection_stats;
+DROP TABLE nft_metadata_stats;
+DROP TABLE nft_collection_stats;
+DROP INDEX nft_activities_sales_idx;
+DROP FUNCTION nft_sale_kind();
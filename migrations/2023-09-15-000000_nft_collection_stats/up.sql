@@ -0,0 +1,524 @@
+-- Kind of sale activities (`NFT_ACTIVITY_KIND_SOLD` of minterop-data), which
+-- the indexer checks after migrating
+CREATE FUNCTION nft_sale_kind() RETURNS TEXT AS $$
+  SELECT 'sale'::TEXT
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE INDEX nft_activities_sales_idx
+  ON nft_activities (nft_contract_id, timestamp)
+  WHERE kind = nft_sale_kind();
+
+-- Market statistics per contract, and per `metadata_id` of a contract. Prices
+-- and volumes are JSON objects by currency. `updated_at` is the timestamp of
+-- the block in which the statistics last changed. Volumes over the last 24
+-- hours and 7 days depend on the time of the query, and are thus in the
+-- `nft_collection_volumes` and `nft_metadata_volumes` views instead.
+CREATE TABLE nft_collection_stats (
+  nft_contract_id TEXT PRIMARY KEY,
+  floor_prices JSONB NOT NULL,
+  listings_count BIGINT NOT NULL,
+  sales_count BIGINT NOT NULL,
+  volume_total JSONB NOT NULL,
+  owners_count BIGINT NOT NULL,
+  updated_at TIMESTAMP NOT NULL
+);
+
+CREATE TABLE nft_metadata_stats (
+  nft_contract_id TEXT NOT NULL,
+  metadata_id TEXT NOT NULL,
+  floor_prices JSONB NOT NULL,
+  listings_count BIGINT NOT NULL,
+  sales_count BIGINT NOT NULL,
+  volume_total JSONB NOT NULL,
+  owners_count BIGINT NOT NULL,
+  updated_at TIMESTAMP NOT NULL,
+  PRIMARY KEY (nft_contract_id, metadata_id)
+);
+
+CREATE TRIGGER nft_collection_stats_journal
+  AFTER INSERT OR UPDATE OR DELETE ON nft_collection_stats
+  FOR EACH ROW EXECUTE FUNCTION journal_block_write();
+CREATE TRIGGER nft_metadata_stats_journal
+  AFTER INSERT OR UPDATE OR DELETE ON nft_metadata_stats
+  FOR EACH ROW EXECUTE FUNCTION journal_block_write();
+
+-- Sales that count towards the statistics, with the `metadata_id` of their
+-- token if it is known
+CREATE VIEW nft_counted_sales AS
+SELECT
+  a.nft_contract_id,
+  t.metadata_id,
+  a.receipt_id,
+  a.token_id,
+  a.currency,
+  a.price,
+  a.timestamp
+FROM nft_activities a
+LEFT JOIN nft_tokens t
+  ON t.nft_contract_id = a.nft_contract_id AND t.token_id = a.token_id
+WHERE a.kind = nft_sale_kind() AND a.price IS NOT NULL;
+
+-- Volumes over the last 24 hours and 7 days before the query. Contracts
+-- without sales in the last 7 days have no row.
+CREATE VIEW nft_collection_volumes AS
+SELECT
+  nft_contract_id,
+  coalesce(
+    jsonb_object_agg(currency, d1) FILTER (WHERE d1 > 0),
+    '{}'
+  ) AS volume_24h,
+  jsonb_object_agg(currency, d7) AS volume_7d
+FROM (
+  SELECT
+    nft_contract_id,
+    currency,
+    coalesce(
+      sum(price) FILTER (
+        WHERE timestamp > (now() AT TIME ZONE 'utc') - INTERVAL '24 hours'
+      ),
+      0
+    ) AS d1,
+    sum(price) AS d7
+  FROM nft_counted_sales
+  WHERE timestamp > (now() AT TIME ZONE 'utc') - INTERVAL '7 days'
+  GROUP BY nft_contract_id, currency
+) v
+GROUP BY nft_contract_id;
+
+CREATE VIEW nft_metadata_volumes AS
+SELECT
+  nft_contract_id,
+  metadata_id,
+  coalesce(
+    jsonb_object_agg(currency, d1) FILTER (WHERE d1 > 0),
+    '{}'
+  ) AS volume_24h,
+  jsonb_object_agg(currency, d7) AS volume_7d
+FROM (
+  SELECT
+    nft_contract_id,
+    metadata_id,
+    currency,
+    coalesce(
+      sum(price) FILTER (
+        WHERE timestamp > (now() AT TIME ZONE 'utc') - INTERVAL '24 hours'
+      ),
+      0
+    ) AS d1,
+    sum(price) AS d7
+  FROM nft_counted_sales
+  WHERE timestamp > (now() AT TIME ZONE 'utc') - INTERVAL '7 days'
+    AND metadata_id IS NOT NULL
+  GROUP BY nft_contract_id, metadata_id, currency
+) v
+GROUP BY nft_contract_id, metadata_id;
+
+-- Sales that count towards the statistics, such that adding them to the
+-- statistics is idempotent
+CREATE TABLE nft_stats_sales (
+  receipt_id TEXT NOT NULL,
+  nft_contract_id TEXT NOT NULL,
+  token_id TEXT NOT NULL,
+  PRIMARY KEY (receipt_id, nft_contract_id, token_id)
+);
+CREATE INDEX nft_stats_sales_contract_idx
+  ON nft_stats_sales (nft_contract_id);
+
+CREATE TRIGGER nft_stats_sales_journal
+  AFTER INSERT OR UPDATE OR DELETE ON nft_stats_sales
+  FOR EACH ROW EXECUTE FUNCTION journal_block_write();
+
+-- to count the tokens of an owner
+CREATE INDEX nft_tokens_contract_owner_idx
+  ON nft_tokens (nft_contract_id, owner);
+
+-- The statistics are made up of three parts, which are computed separately,
+-- such that a full recompute can be limited to the parts that changed. The row with a NULL
+-- `metadata_id` is the one for the whole contract, to which every row counts,
+-- and to its metadata if that is known.
+
+-- Floor prices and number of active listings
+CREATE FUNCTION nft_listing_stats(contract TEXT)
+RETURNS TABLE (
+  metadata_id TEXT,
+  floor_prices JSONB,
+  listings_count BIGINT
+) AS $$
+  WITH listings AS (
+    SELECT l.metadata_id, l.currency, l.price
+    FROM nft_listings l
+    WHERE l.nft_contract_id = contract
+      AND l.price IS NOT NULL
+      AND l.accepted_at IS NULL
+      AND l.unlisted_at IS NULL
+      AND l.invalidated_at IS NULL
+  ),
+  scoped_listings AS (
+    SELECT NULL::TEXT AS metadata_id, currency, price FROM listings
+    UNION ALL
+    SELECT metadata_id, currency, price FROM listings
+    WHERE metadata_id IS NOT NULL
+  )
+  SELECT
+    metadata_id,
+    jsonb_object_agg(currency, price),
+    sum(n)::BIGINT
+  FROM (
+    SELECT metadata_id, currency, min(price) AS price, count(*) AS n
+    FROM scoped_listings
+    GROUP BY metadata_id, currency
+  ) f
+  GROUP BY metadata_id
+$$ LANGUAGE sql STABLE;
+
+-- Number and all-time volume of sales up to `as_of`
+CREATE FUNCTION nft_sale_stats(contract TEXT, as_of TIMESTAMP)
+RETURNS TABLE (
+  metadata_id TEXT,
+  sales_count BIGINT,
+  volume_total JSONB
+) AS $$
+  WITH sales AS (
+    SELECT s.metadata_id, s.currency, s.price
+    FROM nft_counted_sales s
+    WHERE s.nft_contract_id = contract AND s.timestamp <= as_of
+  ),
+  scoped_sales AS (
+    SELECT NULL::TEXT AS metadata_id, currency, price FROM sales
+    UNION ALL
+    SELECT metadata_id, currency, price FROM sales
+    WHERE metadata_id IS NOT NULL
+  )
+  SELECT
+    metadata_id,
+    sum(n)::BIGINT,
+    jsonb_object_agg(currency, total)
+  FROM (
+    SELECT metadata_id, currency, count(*) AS n, sum(price) AS total
+    FROM scoped_sales
+    GROUP BY metadata_id, currency
+  ) v
+  GROUP BY metadata_id
+$$ LANGUAGE sql STABLE;
+
+-- Number of distinct owners of unburned tokens
+CREATE FUNCTION nft_owner_stats(contract TEXT)
+RETURNS TABLE (
+  metadata_id TEXT,
+  owners_count BIGINT
+) AS $$
+  WITH owners AS (
+    SELECT t.metadata_id, t.owner
+    FROM nft_tokens t
+    WHERE t.nft_contract_id = contract AND t.burned_timestamp IS NULL
+  ),
+  scoped_owners AS (
+    SELECT NULL::TEXT AS metadata_id, owner FROM owners
+    UNION ALL
+    SELECT metadata_id, owner FROM owners WHERE metadata_id IS NOT NULL
+  )
+  SELECT metadata_id, count(DISTINCT owner)
+  FROM scoped_owners
+  GROUP BY metadata_id
+$$ LANGUAGE sql STABLE;
+
+-- Brings the given parts (`listings`, `sales` and `owners`) of the statistics
+-- of a contract up to date, keeping the others as they are. Rows are only
+-- written if their statistics changed, such that refreshing is cheap to
+-- journal, and metadata rows without listings, sales and owners are removed.
+-- Returns the number of written rows.
+CREATE FUNCTION refresh_nft_stats(
+  contract TEXT,
+  as_of TIMESTAMP,
+  parts TEXT[] DEFAULT '{listings,sales,owners}'
+)
+RETURNS BIGINT AS $$
+DECLARE
+  r RECORD;
+  metadata_ids TEXT[] := '{}';
+  written BIGINT := 0;
+  n BIGINT;
+BEGIN
+  IF 'sales' = ANY (parts) THEN
+    -- the recomputed statistics count all sales up to `as_of`
+    DELETE FROM nft_stats_sales WHERE nft_contract_id = contract;
+    INSERT INTO nft_stats_sales
+    SELECT receipt_id, nft_contract_id, token_id
+    FROM nft_counted_sales
+    WHERE nft_contract_id = contract AND timestamp <= as_of;
+  END IF;
+
+  FOR r IN
+    WITH current AS (
+      SELECT
+        NULL::TEXT AS metadata_id,
+        c.floor_prices,
+        c.listings_count,
+        c.sales_count,
+        c.volume_total,
+        c.owners_count
+      FROM nft_collection_stats c
+      WHERE c.nft_contract_id = contract
+      UNION ALL
+      SELECT
+        m.metadata_id,
+        m.floor_prices,
+        m.listings_count,
+        m.sales_count,
+        m.volume_total,
+        m.owners_count
+      FROM nft_metadata_stats m
+      WHERE m.nft_contract_id = contract
+    ),
+    listings AS (
+      SELECT * FROM nft_listing_stats(contract)
+      WHERE 'listings' = ANY (parts)
+    ),
+    sales AS (
+      SELECT * FROM nft_sale_stats(contract, as_of)
+      WHERE 'sales' = ANY (parts)
+    ),
+    owners AS (
+      SELECT * FROM nft_owner_stats(contract)
+      WHERE 'owners' = ANY (parts)
+    ),
+    scopes AS (
+      SELECT metadata_id FROM current
+      UNION SELECT metadata_id FROM listings
+      UNION SELECT metadata_id FROM sales
+      UNION SELECT metadata_id FROM owners
+    )
+    SELECT
+      s.metadata_id,
+      CASE WHEN 'listings' = ANY (parts)
+        THEN coalesce(l.floor_prices, '{}')
+        ELSE coalesce(c.floor_prices, '{}')
+      END AS floor_prices,
+      CASE WHEN 'listings' = ANY (parts)
+        THEN coalesce(l.listings_count, 0)
+        ELSE coalesce(c.listings_count, 0)
+      END AS listings_count,
+      CASE WHEN 'sales' = ANY (parts)
+        THEN coalesce(v.sales_count, 0)
+        ELSE coalesce(c.sales_count, 0)
+      END AS sales_count,
+      CASE WHEN 'sales' = ANY (parts)
+        THEN coalesce(v.volume_total, '{}')
+        ELSE coalesce(c.volume_total, '{}')
+      END AS volume_total,
+      CASE WHEN 'owners' = ANY (parts)
+        THEN coalesce(o.owners_count, 0)
+        ELSE coalesce(c.owners_count, 0)
+      END AS owners_count
+    FROM scopes s
+    LEFT JOIN current c ON c.metadata_id IS NOT DISTINCT FROM s.metadata_id
+    LEFT JOIN listings l ON l.metadata_id IS NOT DISTINCT FROM s.metadata_id
+    LEFT JOIN sales v ON v.metadata_id IS NOT DISTINCT FROM s.metadata_id
+    LEFT JOIN owners o ON o.metadata_id IS NOT DISTINCT FROM s.metadata_id
+  LOOP
+    IF r.metadata_id IS NULL THEN
+      INSERT INTO nft_collection_stats AS c VALUES (
+        contract, r.floor_prices, r.listings_count, r.sales_count,
+        r.volume_total, r.owners_count, as_of
+      )
+      ON CONFLICT (nft_contract_id) DO UPDATE SET
+        floor_prices = excluded.floor_prices,
+        listings_count = excluded.listings_count,
+        sales_count = excluded.sales_count,
+        volume_total = excluded.volume_total,
+        owners_count = excluded.owners_count,
+        updated_at = excluded.updated_at
+      WHERE (
+        c.floor_prices, c.listings_count, c.sales_count, c.volume_total,
+        c.owners_count
+      ) IS DISTINCT FROM (
+        excluded.floor_prices, excluded.listings_count, excluded.sales_count,
+        excluded.volume_total, excluded.owners_count
+      );
+    ELSIF r.listings_count + r.sales_count + r.owners_count > 0 THEN
+      metadata_ids := metadata_ids || r.metadata_id;
+      INSERT INTO nft_metadata_stats AS m VALUES (
+        contract, r.metadata_id, r.floor_prices, r.listings_count,
+        r.sales_count, r.volume_total, r.owners_count, as_of
+      )
+      ON CONFLICT (nft_contract_id, metadata_id) DO UPDATE SET
+        floor_prices = excluded.floor_prices,
+        listings_count = excluded.listings_count,
+        sales_count = excluded.sales_count,
+        volume_total = excluded.volume_total,
+        owners_count = excluded.owners_count,
+        updated_at = excluded.updated_at
+      WHERE (
+        m.floor_prices, m.listings_count, m.sales_count, m.volume_total,
+        m.owners_count
+      ) IS DISTINCT FROM (
+        excluded.floor_prices, excluded.listings_count, excluded.sales_count,
+        excluded.volume_total, excluded.owners_count
+      );
+    ELSE
+      CONTINUE;
+    END IF;
+    GET DIAGNOSTICS n = ROW_COUNT;
+    written := written + n;
+  END LOOP;
+
+  DELETE FROM nft_metadata_stats
+  WHERE nft_contract_id = contract AND metadata_id <> ALL (metadata_ids);
+  GET DIAGNOSTICS n = ROW_COUNT;
+  RETURN written + n;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Adds sales, their volume in `currency` (if any) and owners to the
+-- statistics of a contract, or to those of one of its metadata if `metadata`
+-- is not NULL. Metadata rows without listings, sales and owners are removed.
+CREATE FUNCTION add_nft_stats(
+  contract TEXT,
+  metadata TEXT,
+  sales BIGINT,
+  currency TEXT,
+  volume NUMERIC,
+  owners BIGINT,
+  as_of TIMESTAMP
+) RETURNS VOID AS $$
+DECLARE
+  added JSONB := CASE WHEN currency IS NULL THEN '{}'
+    ELSE jsonb_build_object(currency, volume) END;
+BEGIN
+  IF metadata IS NULL THEN
+    INSERT INTO nft_collection_stats AS c VALUES (
+      contract, '{}', 0, sales, added, owners, as_of
+    )
+    ON CONFLICT (nft_contract_id) DO UPDATE SET
+      sales_count = c.sales_count + sales,
+      volume_total = c.volume_total || CASE WHEN currency IS NULL THEN '{}'
+        ELSE jsonb_build_object(
+          currency,
+          coalesce((c.volume_total ->> currency)::NUMERIC, 0) + volume
+        ) END,
+      owners_count = c.owners_count + owners,
+      updated_at = greatest(c.updated_at, as_of);
+  ELSE
+    INSERT INTO nft_metadata_stats AS m VALUES (
+      contract, metadata, '{}', 0, sales, added, owners, as_of
+    )
+    ON CONFLICT (nft_contract_id, metadata_id) DO UPDATE SET
+      sales_count = m.sales_count + sales,
+      volume_total = m.volume_total || CASE WHEN currency IS NULL THEN '{}'
+        ELSE jsonb_build_object(
+          currency,
+          coalesce((m.volume_total ->> currency)::NUMERIC, 0) + volume
+        ) END,
+      owners_count = m.owners_count + owners,
+      updated_at = greatest(m.updated_at, as_of);
+    DELETE FROM nft_metadata_stats m
+    WHERE m.nft_contract_id = contract
+      AND m.metadata_id = metadata
+      AND m.listings_count + m.sales_count + m.owners_count = 0;
+  END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Adds the counted sales of a receipt to the statistics of their contract and
+-- metadata, unless they have been added before. Sales of tokens whose
+-- metadata isn't known yet only count towards the contract until the next
+-- full recompute. Returns the number of added sales.
+CREATE FUNCTION add_nft_sale_stats(receipt TEXT) RETURNS BIGINT AS $$
+DECLARE
+  s RECORD;
+  n BIGINT := 0;
+BEGIN
+  FOR s IN
+    WITH added AS (
+      INSERT INTO nft_stats_sales
+      SELECT receipt_id, nft_contract_id, token_id
+      FROM nft_counted_sales
+      WHERE receipt_id = receipt
+      ON CONFLICT DO NOTHING
+      RETURNING *
+    )
+    SELECT c.*
+    FROM added a
+    JOIN nft_counted_sales c
+      ON c.receipt_id = a.receipt_id
+      AND c.nft_contract_id = a.nft_contract_id
+      AND c.token_id = a.token_id
+  LOOP
+    PERFORM add_nft_stats(
+      s.nft_contract_id, NULL, 1, s.currency, s.price, 0, s.timestamp
+    );
+    IF s.metadata_id IS NOT NULL THEN
+      PERFORM add_nft_stats(
+        s.nft_contract_id, s.metadata_id, 1, s.currency, s.price, 0,
+        s.timestamp
+      );
+    END IF;
+    n := n + 1;
+  END LOOP;
+  RETURN n;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Adds the owner changes of tokens of a contract to its owner counts, given
+-- as the owners before and after each change (NULL for mints and burns), once
+-- the changes have been written to `nft_tokens`. Owners count once they hold a
+-- token, and no longer once they hold none, so only the tokens they hold now
+-- and the net number of tokens they got are needed. Tokens whose metadata
+-- isn't known yet only count towards the contract until the next full
+-- recompute. Returns the number of changed counts.
+CREATE FUNCTION add_nft_owner_stats(
+  contract TEXT,
+  token_ids TEXT[],
+  old_owners TEXT[],
+  new_owners TEXT[],
+  as_of TIMESTAMP
+) RETURNS BIGINT AS $$
+DECLARE
+  r RECORD;
+  changed BIGINT := 0;
+BEGIN
+  FOR r IN
+    WITH nets AS (
+      SELECT scope.metadata_id, x.owner, sum(x.n) AS net
+      FROM unnest(token_ids, old_owners, new_owners)
+        AS c(token_id, old_owner, new_owner)
+      LEFT JOIN nft_tokens t
+        ON t.nft_contract_id = contract AND t.token_id = c.token_id
+      CROSS JOIN LATERAL (
+        VALUES (c.old_owner, -1), (c.new_owner, 1)
+      ) AS x(owner, n)
+      CROSS JOIN LATERAL (
+        SELECT NULL::TEXT
+        UNION ALL
+        SELECT t.metadata_id WHERE t.metadata_id IS NOT NULL
+      ) AS scope(metadata_id)
+      WHERE x.owner IS NOT NULL
+      GROUP BY scope.metadata_id, x.owner
+    ),
+    held AS (
+      SELECT
+        nets.metadata_id,
+        nets.net,
+        (
+          SELECT count(*) FROM nft_tokens h
+          WHERE h.nft_contract_id = contract
+            AND h.owner = nets.owner
+            AND h.burned_timestamp IS NULL
+            AND (nets.metadata_id IS NULL OR h.metadata_id = nets.metadata_id)
+        ) AS tokens
+      FROM nets
+      WHERE nets.net <> 0
+    )
+    SELECT
+      metadata_id,
+      sum((tokens > 0)::INT - (tokens - net > 0)::INT) AS owners
+    FROM held
+    GROUP BY metadata_id
+  LOOP
+    CONTINUE WHEN r.owners = 0;
+    PERFORM add_nft_stats(contract, r.metadata_id, 0, NULL, 0, r.owners, as_of);
+    changed := changed + 1;
+  END LOOP;
+  RETURN changed;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,4 @@
+DROP FUNCTION refresh_nft_candles(TEXT, TIMESTAMP);
+DROP TRIGGER nft_price_candles_journal ON nft_price_candles;
+DROP TABLE nft_price_candles;
+DROP VIEW nft_sales;
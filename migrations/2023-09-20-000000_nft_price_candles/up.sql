@@ -0,0 +1,84 @@
+-- Sales of all markets, including the external ones
+CREATE VIEW nft_sales AS
+SELECT
+  nft_contract_id,
+  token_id,
+  receipt_id,
+  timestamp,
+  currency,
+  price
+FROM nft_activities
+WHERE kind = nft_sale_kind() AND price IS NOT NULL AND currency IS NOT NULL
+UNION ALL
+SELECT
+  nft_contract_id,
+  token_id,
+  sale_receipt_id,
+  sold_at,
+  currency,
+  sale_price
+FROM nft_external_listings
+WHERE sold_at IS NOT NULL AND sale_price IS NOT NULL;
+
+-- Sale prices per contract and currency in hourly and daily buckets. Open and
+-- close are the first and last sale of the bucket, by timestamp and then
+-- receipt ID.
+CREATE TABLE nft_price_candles (
+  nft_contract_id TEXT NOT NULL,
+  currency TEXT NOT NULL,
+  -- 'hour' or 'day'
+  bucket TEXT NOT NULL,
+  bucket_start TIMESTAMP NOT NULL,
+  open NUMERIC NOT NULL,
+  high NUMERIC NOT NULL,
+  low NUMERIC NOT NULL,
+  close NUMERIC NOT NULL,
+  volume NUMERIC NOT NULL,
+  sales_count BIGINT NOT NULL,
+  PRIMARY KEY (nft_contract_id, currency, bucket, bucket_start)
+);
+
+CREATE TRIGGER nft_price_candles_journal
+  AFTER INSERT OR UPDATE OR DELETE ON nft_price_candles
+  FOR EACH ROW EXECUTE FUNCTION journal_block_write();
+
+-- Recomputes the hourly and daily candles of a contract that contain `at`
+-- from `nft_sales`, such that they don't depend on the order in which sales
+-- have been indexed. Returns the number of written candles.
+CREATE FUNCTION refresh_nft_candles(contract TEXT, at TIMESTAMP)
+RETURNS BIGINT AS $$
+DECLARE
+  b TEXT;
+  start TIMESTAMP;
+  written BIGINT := 0;
+  n BIGINT;
+BEGIN
+  FOREACH b IN ARRAY ARRAY['hour', 'day'] LOOP
+    start := date_trunc(b, at);
+
+    DELETE FROM nft_price_candles
+    WHERE nft_contract_id = contract AND bucket = b AND bucket_start = start;
+
+    INSERT INTO nft_price_candles
+    SELECT
+      contract,
+      currency,
+      b,
+      start,
+      (array_agg(price ORDER BY timestamp, receipt_id))[1],
+      max(price),
+      min(price),
+      (array_agg(price ORDER BY timestamp DESC, receipt_id DESC))[1],
+      sum(price),
+      count(*)
+    FROM nft_sales
+    WHERE nft_contract_id = contract
+      AND timestamp >= start
+      AND timestamp < start + ('1 ' || b)::INTERVAL
+    GROUP BY currency;
+    GET DIAGNOSTICS n = ROW_COUNT;
+    written := written + n;
+  END LOOP;
+  RETURN written;
+END;
+$$ LANGUAGE plpgsql;
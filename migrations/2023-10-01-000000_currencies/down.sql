@@ -0,0 +1,18 @@
+DROP TRIGGER currencies_renormalize ON currencies;
+DROP TRIGGER currencies_normalize ON currencies;
+DROP TRIGGER nft_activities_normalize ON nft_activities;
+DROP TRIGGER nft_earnings_normalize ON nft_earnings;
+DROP TRIGGER nft_offers_normalize ON nft_offers;
+DROP TRIGGER nft_listings_normalize ON nft_listings;
+DROP FUNCTION renormalize_currency();
+DROP FUNCTION normalize_row_amount();
+DROP INDEX nft_activities_currency_idx;
+DROP INDEX nft_earnings_currency_idx;
+DROP INDEX nft_offers_currency_idx;
+DROP INDEX nft_listings_currency_idx;
+ALTER TABLE nft_activities DROP COLUMN price_normalized;
+ALTER TABLE nft_earnings DROP COLUMN amount_normalized;
+ALTER TABLE nft_offers DROP COLUMN offer_price_normalized;
+ALTER TABLE nft_listings DROP COLUMN price_normalized;
+DROP FUNCTION normalize_amount(NUMERIC, TEXT);
+DROP TABLE currencies;
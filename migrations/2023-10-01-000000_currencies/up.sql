@@ -0,0 +1,99 @@
+-- Decimals and symbols of the currencies that prices are denominated in.
+-- `source` is 'builtin', 'config' (from the config file, which takes
+-- precedence) or 'ft_metadata' (queried from the FT contract).
+CREATE TABLE currencies (
+  currency TEXT PRIMARY KEY,
+  symbol TEXT NOT NULL,
+  decimals INT NOT NULL,
+  source TEXT NOT NULL,
+  updated_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+INSERT INTO currencies (currency, symbol, decimals, source)
+VALUES ('near', 'NEAR', 24, 'builtin');
+
+-- Raw amount in whole units of the currency, NULL if its decimals are not
+-- known (yet). Shifting by a numeric literal keeps all digits, which
+-- dividing by a power of ten wouldn't.
+CREATE FUNCTION normalize_amount(amount NUMERIC, cur TEXT)
+RETURNS NUMERIC AS $$
+  SELECT trim_scale(amount * ('1e-' || decimals)::NUMERIC)
+  FROM currencies
+  WHERE currency = cur
+$$ LANGUAGE sql STABLE;
+
+ALTER TABLE nft_listings ADD COLUMN price_normalized NUMERIC;
+ALTER TABLE nft_offers ADD COLUMN offer_price_normalized NUMERIC;
+ALTER TABLE nft_earnings ADD COLUMN amount_normalized NUMERIC;
+ALTER TABLE nft_activities ADD COLUMN price_normalized NUMERIC;
+
+-- Fills the normalized column (second argument) from the raw one (first
+-- argument) and the currency of the row.
+CREATE FUNCTION normalize_row_amount() RETURNS TRIGGER AS $$
+BEGIN
+  NEW := jsonb_populate_record(NEW, jsonb_build_object(
+    TG_ARGV[1],
+    normalize_amount((to_jsonb(NEW) ->> TG_ARGV[0])::NUMERIC, NEW.currency)
+  ));
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Currencies that are registered after rows using them have been written
+-- (or whose decimals change) normalize those rows retroactively, which the
+-- indexes on `currency` keep to the rows of the currency.
+CREATE INDEX nft_listings_currency_idx ON nft_listings (currency);
+CREATE INDEX nft_offers_currency_idx ON nft_offers (currency);
+CREATE INDEX nft_earnings_currency_idx ON nft_earnings (currency);
+CREATE INDEX nft_activities_currency_idx ON nft_activities (currency);
+
+CREATE FUNCTION renormalize_currency() RETURNS TRIGGER AS $$
+BEGIN
+  UPDATE nft_listings
+  SET price_normalized = normalize_amount(price, currency)
+  WHERE currency = NEW.currency;
+  UPDATE nft_offers
+  SET offer_price_normalized = normalize_amount(offer_price, currency)
+  WHERE currency = NEW.currency;
+  UPDATE nft_earnings
+  SET amount_normalized = normalize_amount(amount, currency)
+  WHERE currency = NEW.currency;
+  UPDATE nft_activities
+  SET price_normalized = normalize_amount(price, currency)
+  WHERE currency = NEW.currency;
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER nft_listings_normalize
+  BEFORE INSERT OR UPDATE OF price, currency ON nft_listings
+  FOR EACH ROW
+  EXECUTE FUNCTION normalize_row_amount('price', 'price_normalized');
+CREATE TRIGGER nft_offers_normalize
+  BEFORE INSERT OR UPDATE OF offer_price, currency ON nft_offers
+  FOR EACH ROW
+  EXECUTE FUNCTION
+    normalize_row_amount('offer_price', 'offer_price_normalized');
+CREATE TRIGGER nft_earnings_normalize
+  BEFORE INSERT OR UPDATE OF amount, currency ON nft_earnings
+  FOR EACH ROW
+  EXECUTE FUNCTION normalize_row_amount('amount', 'amount_normalized');
+CREATE TRIGGER nft_activities_normalize
+  BEFORE INSERT OR UPDATE OF price, currency ON nft_activities
+  FOR EACH ROW
+  EXECUTE FUNCTION normalize_row_amount('price', 'price_normalized');
+
+CREATE TRIGGER currencies_normalize
+  AFTER INSERT ON currencies
+  FOR EACH ROW EXECUTE FUNCTION renormalize_currency();
+CREATE TRIGGER currencies_renormalize
+  AFTER UPDATE OF decimals ON currencies
+  FOR EACH ROW WHEN (OLD.decimals IS DISTINCT FROM NEW.decimals)
+  EXECUTE FUNCTION renormalize_currency();
+
+UPDATE nft_listings SET price_normalized = normalize_amount(price, currency);
+UPDATE nft_offers
+SET offer_price_normalized = normalize_amount(offer_price, currency);
+UPDATE nft_earnings SET amount_normalized = normalize_amount(amount, currency);
+UPDATE nft_activities
+SET price_normalized = normalize_amount(price, currency);
@@ -0,0 +1,8 @@
+DROP FUNCTION revalue_in_usd(TEXT, TIMESTAMP, TIMESTAMP);
+DROP TRIGGER nft_activities_value ON nft_activities;
+DROP TRIGGER nft_earnings_value ON nft_earnings;
+DROP FUNCTION value_row_in_usd();
+ALTER TABLE nft_activities DROP COLUMN usd_value;
+ALTER TABLE nft_earnings DROP COLUMN usd_value;
+DROP FUNCTION usd_price_at(TEXT, TIMESTAMP);
+DROP TABLE currency_prices;
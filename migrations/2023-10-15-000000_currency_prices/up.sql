@@ -0,0 +1,90 @@
+-- USD prices of currencies over time, as imported from a price feed. `source`
+-- is the file or URL the price has been imported from.
+CREATE TABLE currency_prices (
+  currency TEXT NOT NULL,
+  timestamp TIMESTAMP NOT NULL,
+  usd_price NUMERIC NOT NULL,
+  source TEXT NOT NULL,
+  PRIMARY KEY (currency, timestamp)
+);
+
+-- USD price of a currency at a time, which is the last price at or before it.
+-- Prices older than a day are considered to be gaps in the feed, and yield
+-- NULL.
+CREATE FUNCTION usd_price_at(cur TEXT, at TIMESTAMP) RETURNS NUMERIC AS $$
+  SELECT usd_price
+  FROM currency_prices
+  WHERE currency = cur
+    AND timestamp <= at
+    AND timestamp > at - INTERVAL '1 day'
+  ORDER BY timestamp DESC
+  LIMIT 1
+$$ LANGUAGE sql STABLE;
+
+ALTER TABLE nft_earnings ADD COLUMN usd_value NUMERIC;
+ALTER TABLE nft_activities ADD COLUMN usd_value NUMERIC;
+
+-- Values the normalized amount (argument) of a row in USD at its timestamp.
+-- Runs after the `_normalize` trigger of the table, as triggers fire in
+-- alphabetical order.
+CREATE FUNCTION value_row_in_usd() RETURNS TRIGGER AS $$
+BEGIN
+  NEW.usd_value := trim_scale(
+    (to_jsonb(NEW) ->> TG_ARGV[0])::NUMERIC
+    * usd_price_at(NEW.currency, NEW.timestamp)
+  );
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER nft_earnings_value
+  BEFORE INSERT OR UPDATE OF amount, amount_normalized, currency, timestamp
+  ON nft_earnings
+  FOR EACH ROW EXECUTE FUNCTION value_row_in_usd('amount_normalized');
+CREATE TRIGGER nft_activities_value
+  BEFORE INSERT OR UPDATE OF price, price_normalized, currency, timestamp
+  ON nft_activities
+  FOR EACH ROW WHEN (NEW.kind = nft_sale_kind())
+  EXECUTE FUNCTION value_row_in_usd('price_normalized');
+
+-- Revalues earnings and sales in a currency whose price may have changed by
+-- importing prices from `since` to `until`, i.e. those up to a day after
+-- them. Returns the number of revalued rows.
+CREATE FUNCTION revalue_in_usd(cur TEXT, since TIMESTAMP, until TIMESTAMP)
+RETURNS BIGINT AS $$
+DECLARE
+  revalued BIGINT := 0;
+  n BIGINT;
+BEGIN
+  UPDATE nft_earnings
+  SET usd_value = trim_scale(
+    amount_normalized * usd_price_at(currency, timestamp)
+  )
+  WHERE currency = cur
+    AND timestamp >= since
+    AND timestamp < until + INTERVAL '1 day';
+  GET DIAGNOSTICS n = ROW_COUNT;
+  revalued := revalued + n;
+
+  UPDATE nft_activities
+  SET usd_value = trim_scale(
+    price_normalized * usd_price_at(currency, timestamp)
+  )
+  WHERE kind = nft_sale_kind()
+    AND currency = cur
+    AND timestamp >= since
+    AND timestamp < until + INTERVAL '1 day';
+  GET DIAGNOSTICS n = ROW_COUNT;
+  revalued := revalued + n;
+
+  RETURN revalued;
+END;
+$$ LANGUAGE plpgsql;
+
+UPDATE nft_earnings
+SET usd_value = trim_scale(
+  amount_normalized * usd_price_at(currency, timestamp)
+);
+UPDATE nft_activities
+SET usd_value = trim_scale(price_normalized * usd_price_at(currency, timestamp))
+WHERE kind = nft_sale_kind();
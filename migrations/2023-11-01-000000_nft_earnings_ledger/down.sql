@@ -0,0 +1,11 @@
+DROP INDEX IF EXISTS nft_earnings_receiver_timestamp_idx;
+DROP VIEW nft_earnings_ledger;
+DROP VIEW nft_earnings_roles;
+DROP TRIGGER nft_split_changes_sale_terms ON nft_split_changes;
+DROP FUNCTION rerecord_nft_sale_terms();
+DROP FUNCTION record_nft_sale_terms(TEXT);
+DROP TRIGGER nft_sale_terms_journal ON nft_sale_terms;
+DROP TABLE nft_sale_terms;
+DROP FUNCTION nft_splits_before(TEXT, TEXT, TIMESTAMP, TEXT);
+DROP TRIGGER nft_split_changes_journal ON nft_split_changes;
+DROP TABLE nft_split_changes;
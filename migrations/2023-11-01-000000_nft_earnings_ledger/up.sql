@@ -0,0 +1,238 @@
+-- Royalties and split owners of tokens as they were set: At mint (from the
+-- memo of mintbase token contracts) and by `nft_set_split_owners`. Transfers
+-- clear split owners, which `nft_ownership_changes` tells.
+CREATE TABLE nft_split_changes (
+  nft_contract_id TEXT NOT NULL,
+  token_id TEXT NOT NULL,
+  receipt_id TEXT NOT NULL,
+  timestamp TIMESTAMP NOT NULL,
+  -- only set at mint
+  royalties JSONB,
+  royalties_percent INT,
+  splits JSONB,
+  PRIMARY KEY (nft_contract_id, token_id, receipt_id)
+);
+
+CREATE TRIGGER nft_split_changes_journal
+  AFTER INSERT OR UPDATE OR DELETE ON nft_split_changes
+  FOR EACH ROW EXECUTE FUNCTION journal_block_write();
+
+-- Tokens indexed before this table existed only have their current royalties
+-- and split owners, which are taken to have been set at mint
+INSERT INTO nft_split_changes
+SELECT
+  nft_contract_id,
+  token_id,
+  minted_receipt_id,
+  minted_timestamp,
+  royalties::JSONB,
+  royalties_percent,
+  splits::JSONB
+FROM nft_tokens
+WHERE minted_receipt_id IS NOT NULL
+  AND minted_timestamp IS NOT NULL
+  AND (royalties IS NOT NULL OR splits IS NOT NULL);
+
+-- Split owners of a token just before `(ts, receipt)`, by timestamp and then
+-- receipt ID, unless the token has been transferred since they were set
+CREATE FUNCTION nft_splits_before(
+  contract TEXT,
+  token TEXT,
+  ts TIMESTAMP,
+  receipt TEXT
+) RETURNS JSONB AS $$
+  SELECT nullif(s.splits, '{}')
+  FROM nft_split_changes s
+  WHERE s.nft_contract_id = contract
+    AND s.token_id = token
+    AND (s.timestamp, s.receipt_id) < (ts, receipt)
+    AND NOT EXISTS (
+      SELECT 1
+      FROM nft_ownership_changes o
+      WHERE o.nft_contract_id = contract
+        AND o.token_id = token
+        AND o.receipt_id <> s.receipt_id
+        AND (o.timestamp, o.receipt_id) > (s.timestamp, s.receipt_id)
+        AND (o.timestamp, o.receipt_id) < (ts, receipt)
+    )
+  ORDER BY s.timestamp DESC, s.receipt_id DESC
+  LIMIT 1
+$$ LANGUAGE sql STABLE;
+
+-- Seller, royalties and split owners that applied to a sale, i.e. those from
+-- before the transfer to the buyer (which clears the split owners), or from
+-- before the sale if that transfer is unknown. They are recorded once the
+-- sale is indexed, and again whenever royalties or split owners from before
+-- it arrive later, such that the roles of earnings don't change with later
+-- changes to the token.
+CREATE TABLE nft_sale_terms (
+  receipt_id TEXT NOT NULL,
+  nft_contract_id TEXT NOT NULL,
+  token_id TEXT NOT NULL,
+  timestamp TIMESTAMP NOT NULL,
+  -- lister of the sold listing, if known
+  seller_id TEXT,
+  royalties JSONB,
+  royalties_percent INT,
+  splits JSONB,
+  PRIMARY KEY (receipt_id, token_id)
+);
+
+CREATE INDEX nft_sale_terms_token_idx
+  ON nft_sale_terms (nft_contract_id, token_id, timestamp);
+
+CREATE TRIGGER nft_sale_terms_journal
+  AFTER INSERT OR UPDATE OR DELETE ON nft_sale_terms
+  FOR EACH ROW EXECUTE FUNCTION journal_block_write();
+
+-- Records the terms of the sale with the earnings of a receipt. Returns the
+-- number of written rows.
+CREATE FUNCTION record_nft_sale_terms(receipt TEXT) RETURNS BIGINT AS $$
+DECLARE
+  sale RECORD;
+  cutoff_timestamp TIMESTAMP;
+  cutoff_receipt TEXT;
+  n BIGINT;
+BEGIN
+  SELECT
+    e.nft_contract_id,
+    e.token_id,
+    e.market_id,
+    e.approval_id,
+    e.timestamp
+  INTO sale
+  FROM nft_earnings e
+  WHERE e.receipt_id = receipt
+  LIMIT 1;
+  IF NOT FOUND THEN
+    RETURN 0;
+  END IF;
+
+  SELECT o.timestamp, o.receipt_id INTO cutoff_timestamp, cutoff_receipt
+  FROM nft_ownership_changes o
+  JOIN nft_activities a
+    ON a.nft_contract_id = o.nft_contract_id
+    AND a.token_id = o.token_id
+    AND a.action_receiver = o.owner
+  WHERE a.receipt_id = receipt
+    AND a.kind = nft_sale_kind()
+    AND o.nft_contract_id = sale.nft_contract_id
+    AND o.token_id = sale.token_id
+    AND (o.timestamp, o.receipt_id) <= (sale.timestamp, receipt)
+  ORDER BY o.timestamp DESC, o.receipt_id DESC
+  LIMIT 1;
+  IF NOT FOUND THEN
+    cutoff_timestamp := sale.timestamp;
+    cutoff_receipt := receipt;
+  END IF;
+
+  INSERT INTO nft_sale_terms AS st
+  SELECT
+    receipt,
+    sale.nft_contract_id,
+    sale.token_id,
+    sale.timestamp,
+    (
+      SELECT l.listed_by
+      FROM nft_listings l
+      WHERE l.nft_contract_id = sale.nft_contract_id
+        AND l.token_id = sale.token_id
+        AND l.market_id = sale.market_id
+        AND l.approval_id = sale.approval_id
+      LIMIT 1
+    ),
+    nullif(m.royalties, '{}'),
+    m.royalties_percent,
+    nft_splits_before(
+      sale.nft_contract_id,
+      sale.token_id,
+      cutoff_timestamp,
+      cutoff_receipt
+    )
+  FROM (SELECT 1) one
+  LEFT JOIN LATERAL (
+    SELECT s.royalties, s.royalties_percent
+    FROM nft_split_changes s
+    WHERE s.nft_contract_id = sale.nft_contract_id
+      AND s.token_id = sale.token_id
+      AND s.royalties IS NOT NULL
+    ORDER BY s.timestamp, s.receipt_id
+    LIMIT 1
+  ) m ON true
+  ON CONFLICT (receipt_id, token_id) DO UPDATE SET
+    seller_id = excluded.seller_id,
+    royalties = excluded.royalties,
+    royalties_percent = excluded.royalties_percent,
+    splits = excluded.splits
+  WHERE (st.seller_id, st.royalties, st.royalties_percent, st.splits)
+    IS DISTINCT FROM (
+      excluded.seller_id,
+      excluded.royalties,
+      excluded.royalties_percent,
+      excluded.splits
+    );
+
+  GET DIAGNOSTICS n = ROW_COUNT;
+  RETURN n;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Royalties and split owners that arrive after sales of their token (e.g. when
+-- reindexing out of order) update the terms of those sales
+CREATE FUNCTION rerecord_nft_sale_terms() RETURNS TRIGGER AS $$
+BEGIN
+  PERFORM record_nft_sale_terms(st.receipt_id)
+  FROM nft_sale_terms st
+  WHERE st.nft_contract_id = NEW.nft_contract_id
+    AND st.token_id = NEW.token_id
+    AND st.timestamp >= NEW.timestamp;
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER nft_split_changes_sale_terms
+  AFTER INSERT OR UPDATE ON nft_split_changes
+  FOR EACH ROW EXECUTE FUNCTION rerecord_nft_sale_terms();
+
+SELECT record_nft_sale_terms(receipt_id)
+FROM (SELECT DISTINCT receipt_id FROM nft_earnings) e;
+
+-- Earnings with the role in which the receiver earned them. Payouts don't
+-- tell their parts apart, so roles of payout receivers are derived from the
+-- terms of the sale: The lister is the seller (unless the token had split
+-- owners, who are paid instead), accounts from the royalties of the token earn
+-- royalties, and the remaining receivers are split owners. Sellers that also
+-- receive royalties are paid in a single payout entry, which counts as sold.
+CREATE VIEW nft_earnings_roles AS
+SELECT
+  e.*,
+  CASE
+    WHEN e.is_mintbase_cut THEN 'mintbase'
+    WHEN e.is_affiliate OR e.is_referral THEN 'affiliate'
+    WHEN e.receiver_id = st.seller_id AND st.splits IS NULL THEN 'seller'
+    WHEN coalesce(st.royalties ? e.receiver_id, false) THEN 'royalty'
+    WHEN coalesce(st.splits ? e.receiver_id, false) THEN 'split'
+    ELSE 'seller'
+  END AS role
+FROM nft_earnings e
+LEFT JOIN nft_sale_terms st
+  ON st.receipt_id = e.receipt_id
+  AND st.token_id = e.token_id;
+
+-- Cumulative earnings per account, currency and role
+CREATE VIEW nft_earnings_ledger AS
+SELECT
+  receiver_id AS account_id,
+  currency,
+  role,
+  sum(amount) AS amount,
+  sum(amount_normalized) AS amount_normalized,
+  sum(usd_value) AS usd_value,
+  count(*) AS earnings_count,
+  min(timestamp) AS first_earned_at,
+  max(timestamp) AS last_earned_at
+FROM nft_earnings_roles
+GROUP BY receiver_id, currency, role;
+
+CREATE INDEX IF NOT EXISTS nft_earnings_receiver_timestamp_idx
+  ON nft_earnings (receiver_id, timestamp);
@@ -0,0 +1,5 @@
+DROP TRIGGER nft_sale_terms_verify ON nft_sale_terms;
+DROP FUNCTION reverify_nft_payout();
+DROP FUNCTION verify_nft_payout(TEXT);
+DROP TRIGGER nft_payout_mismatches_journal ON nft_payout_mismatches;
+DROP TABLE nft_payout_mismatches;
@@ -0,0 +1,141 @@
+-- Payout receivers of a sale that have been paid more or less than the
+-- royalties and split owners of the token as of the sale entail. Receivers that
+-- have not been expected at all have empty `roles`, missing ones an `actual`
+-- of zero.
+CREATE TABLE nft_payout_mismatches (
+  receipt_id TEXT NOT NULL,
+  receiver_id TEXT NOT NULL,
+  nft_contract_id TEXT NOT NULL,
+  token_id TEXT NOT NULL,
+  timestamp TIMESTAMP NOT NULL,
+  currency TEXT NOT NULL,
+  -- comma-separated roles in which the receiver should have been paid
+  roles TEXT NOT NULL,
+  expected NUMERIC NOT NULL,
+  actual NUMERIC NOT NULL,
+  PRIMARY KEY (receipt_id, receiver_id)
+);
+
+CREATE INDEX nft_payout_mismatches_token_idx
+  ON nft_payout_mismatches (nft_contract_id, token_id);
+
+CREATE TRIGGER nft_payout_mismatches_journal
+  AFTER INSERT OR UPDATE OR DELETE ON nft_payout_mismatches
+  FOR EACH ROW EXECUTE FUNCTION journal_block_write();
+
+-- Compares the payout of a sale with the royalties and splits of the token
+-- as of the sale (from `nft_sale_terms`, which need to be recorded first), the
+-- same way mintbase token contracts compute it: Royalties take their
+-- percentage of the payout, shared among the royalty receivers, and the rest
+-- goes to the split owners, or to the seller if there are none. Shares are
+-- out of 10000. Deviations up to 100 units are due to rounding. Tokens
+-- without royalties and splits aren't verified, and neither is the seller's
+-- part if the listing is unknown. Returns the number of mismatches.
+CREATE FUNCTION verify_nft_payout(receipt TEXT) RETURNS BIGINT AS $$
+DECLARE
+  sale RECORD;
+  royalties JSONB;
+  royalties_percent INT;
+  splits JSONB;
+  seller TEXT;
+  royalty_total NUMERIC := 0;
+  n BIGINT;
+BEGIN
+  DELETE FROM nft_payout_mismatches WHERE receipt_id = receipt;
+
+  SELECT
+    e.nft_contract_id,
+    e.token_id,
+    e.market_id,
+    e.approval_id,
+    e.timestamp,
+    e.currency,
+    sum(e.amount) AS total
+  INTO sale
+  FROM nft_earnings e
+  WHERE e.receipt_id = receipt
+    AND NOT e.is_mintbase_cut
+    AND NOT e.is_affiliate
+    AND NOT e.is_referral
+  GROUP BY 1, 2, 3, 4, 5, 6;
+  IF NOT FOUND THEN
+    RETURN 0;
+  END IF;
+
+  SELECT st.royalties, st.royalties_percent, st.splits, st.seller_id
+  INTO royalties, royalties_percent, splits, seller
+  FROM nft_sale_terms st
+  WHERE st.receipt_id = receipt AND st.token_id = sale.token_id;
+  IF royalties IS NULL AND splits IS NULL THEN
+    RETURN 0;
+  END IF;
+
+  IF royalties IS NOT NULL THEN
+    royalty_total := trunc(sale.total * coalesce(royalties_percent, 0) / 10000);
+  END IF;
+
+  INSERT INTO nft_payout_mismatches
+  WITH expected_parts (receiver_id, role, amount) AS (
+    SELECT key, 'royalty', trunc(royalty_total * value::NUMERIC / 10000)
+    FROM jsonb_each_text(coalesce(royalties, '{}'))
+    UNION ALL
+    SELECT
+      key,
+      'split',
+      trunc((sale.total - royalty_total) * value::NUMERIC / 10000)
+    FROM jsonb_each_text(coalesce(splits, '{}'))
+    UNION ALL
+    SELECT seller, 'seller', sale.total - royalty_total
+    WHERE seller IS NOT NULL AND splits IS NULL
+  ),
+  expected AS (
+    SELECT receiver_id, string_agg(role, ',' ORDER BY role) AS roles,
+      sum(amount) AS amount
+    FROM expected_parts
+    GROUP BY receiver_id
+  ),
+  actual AS (
+    SELECT receiver_id, sum(amount) AS amount
+    FROM nft_earnings
+    WHERE receipt_id = receipt
+      AND NOT is_mintbase_cut
+      AND NOT is_affiliate
+      AND NOT is_referral
+    GROUP BY receiver_id
+  )
+  SELECT
+    receipt,
+    coalesce(x.receiver_id, a.receiver_id),
+    sale.nft_contract_id,
+    sale.token_id,
+    sale.timestamp,
+    sale.currency,
+    coalesce(x.roles, ''),
+    coalesce(x.amount, 0),
+    coalesce(a.amount, 0)
+  FROM expected x
+  FULL JOIN actual a ON a.receiver_id = x.receiver_id
+  WHERE abs(coalesce(x.amount, 0) - coalesce(a.amount, 0)) > 100
+    -- without a known seller, unexpected receivers might be the seller
+    AND (x.receiver_id IS NOT NULL OR seller IS NOT NULL);
+
+  GET DIAGNOSTICS n = ROW_COUNT;
+  RETURN n;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Terms that change after the sale has been verified, because royalties or
+-- split owners from before it arrived later, verify it again
+CREATE FUNCTION reverify_nft_payout() RETURNS TRIGGER AS $$
+BEGIN
+  PERFORM verify_nft_payout(NEW.receipt_id);
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER nft_sale_terms_verify
+  AFTER UPDATE ON nft_sale_terms
+  FOR EACH ROW EXECUTE FUNCTION reverify_nft_payout();
+
+SELECT verify_nft_payout(receipt_id)
+FROM (SELECT DISTINCT receipt_id FROM nft_sale_terms) st;
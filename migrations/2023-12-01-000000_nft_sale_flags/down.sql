@@ -0,0 +1,40 @@
+CREATE OR REPLACE VIEW nft_sales AS
+SELECT
+  nft_contract_id,
+  token_id,
+  receipt_id,
+  timestamp,
+  currency,
+  price
+FROM nft_activities
+WHERE kind = nft_sale_kind() AND price IS NOT NULL AND currency IS NOT NULL
+UNION ALL
+SELECT
+  nft_contract_id,
+  token_id,
+  sale_receipt_id,
+  sold_at,
+  currency,
+  sale_price
+FROM nft_external_listings
+WHERE sold_at IS NOT NULL AND sale_price IS NOT NULL;
+
+CREATE OR REPLACE VIEW nft_counted_sales AS
+SELECT
+  a.nft_contract_id,
+  t.metadata_id,
+  a.receipt_id,
+  a.token_id,
+  a.currency,
+  a.price,
+  a.timestamp
+FROM nft_activities a
+LEFT JOIN nft_tokens t
+  ON t.nft_contract_id = a.nft_contract_id AND t.token_id = a.token_id
+WHERE a.kind = nft_sale_kind() AND a.price IS NOT NULL;
+
+DROP FUNCTION flag_nft_sale(TEXT);
+DROP TRIGGER nft_sale_flags_journal ON nft_sale_flags;
+DROP INDEX nft_activities_tx_sender_idx;
+DROP TABLE nft_sale_flags;
+ALTER TABLE accounts DROP COLUMN creator_id;
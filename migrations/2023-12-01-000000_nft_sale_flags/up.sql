@@ -0,0 +1,170 @@
+-- Sales that look like wash trades, one row per reason:
+-- - `self_trade`: The buyer is the seller.
+-- - `shared_key`: Transactions of the buyer and of the seller have been signed
+--   with the same key (by `sender_pk` of their activities, or `access_keys`).
+-- - `round_trip`: The buyer sold the same token within the last 7 days.
+-- - `same_creator`: Both accounts were created by the same account (other than
+--   a top-level registrar like `near`), or one created the other, by
+--   `accounts.creator_id`. Creators are only known for accounts whose creation
+--   has been indexed (all accounts when indexing live, only the tracked
+--   contracts when backfilling), and implicit accounts have none.
+-- Flagged sales are excluded from the statistics, `nft_sales` and thus the
+-- candles.
+-- Predecessor of the receipt that created the account, which funds it as well
+ALTER TABLE accounts ADD COLUMN creator_id TEXT;
+
+CREATE TABLE nft_sale_flags (
+  receipt_id TEXT NOT NULL,
+  nft_contract_id TEXT NOT NULL,
+  token_id TEXT NOT NULL,
+  timestamp TIMESTAMP NOT NULL,
+  seller_id TEXT NOT NULL,
+  buyer_id TEXT NOT NULL,
+  reason TEXT NOT NULL,
+  PRIMARY KEY (receipt_id, token_id, reason)
+);
+
+CREATE INDEX nft_sale_flags_contract_idx
+  ON nft_sale_flags (nft_contract_id, timestamp);
+
+CREATE INDEX nft_activities_tx_sender_idx
+  ON nft_activities (tx_sender, sender_pk);
+
+CREATE TRIGGER nft_sale_flags_journal
+  AFTER INSERT OR UPDATE OR DELETE ON nft_sale_flags
+  FOR EACH ROW EXECUTE FUNCTION journal_block_write();
+
+-- Flags the sale activities of a receipt, replacing earlier flags. Returns the
+-- number of flags.
+CREATE FUNCTION flag_nft_sale(receipt TEXT) RETURNS BIGINT AS $$
+DECLARE
+  n BIGINT;
+BEGIN
+  DELETE FROM nft_sale_flags WHERE receipt_id = receipt;
+
+  INSERT INTO nft_sale_flags
+  WITH sales AS (
+    SELECT
+      a.receipt_id,
+      a.nft_contract_id,
+      a.token_id,
+      a.timestamp,
+      a.action_sender AS seller_id,
+      a.action_receiver AS buyer_id
+    FROM nft_activities a
+    WHERE a.receipt_id = receipt
+      AND a.kind = nft_sale_kind()
+      AND a.action_sender IS NOT NULL
+      AND a.action_receiver IS NOT NULL
+  )
+  SELECT s.*, 'self_trade'
+  FROM sales s
+  WHERE s.seller_id = s.buyer_id
+  UNION ALL
+  SELECT s.*, 'shared_key'
+  FROM sales s
+  WHERE s.seller_id <> s.buyer_id
+    AND (
+      EXISTS (
+        SELECT 1
+        FROM nft_activities x
+        JOIN nft_activities y ON y.sender_pk = x.sender_pk
+        WHERE x.tx_sender = s.seller_id AND y.tx_sender = s.buyer_id
+      )
+      OR EXISTS (
+        SELECT 1
+        FROM access_keys x
+        JOIN access_keys y ON y.public_key = x.public_key
+        WHERE x.account_id = s.seller_id AND y.account_id = s.buyer_id
+      )
+    )
+  UNION ALL
+  SELECT s.*, 'round_trip'
+  FROM sales s
+  WHERE s.seller_id <> s.buyer_id
+    AND EXISTS (
+      SELECT 1
+      FROM nft_activities p
+      WHERE p.nft_contract_id = s.nft_contract_id
+        AND p.token_id = s.token_id
+        AND p.kind = nft_sale_kind()
+        AND p.action_sender = s.buyer_id
+        AND p.receipt_id <> s.receipt_id
+        AND p.timestamp <= s.timestamp
+        AND p.timestamp > s.timestamp - INTERVAL '7 days'
+    )
+  UNION ALL
+  SELECT s.*, 'same_creator'
+  FROM sales s
+  WHERE s.seller_id <> s.buyer_id
+    AND (
+      EXISTS (
+        SELECT 1
+        FROM accounts x
+        JOIN accounts y ON y.creator_id = x.creator_id
+        WHERE x.account_id = s.seller_id
+          AND y.account_id = s.buyer_id
+          AND strpos(x.creator_id, '.') > 0
+      )
+      OR EXISTS (
+        SELECT 1
+        FROM accounts c
+        WHERE (c.account_id = s.buyer_id AND c.creator_id = s.seller_id)
+          OR (c.account_id = s.seller_id AND c.creator_id = s.buyer_id)
+      )
+    );
+
+  GET DIAGNOSTICS n = ROW_COUNT;
+  RETURN n;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Same as before, but without flagged sales
+CREATE OR REPLACE VIEW nft_counted_sales AS
+SELECT
+  a.nft_contract_id,
+  t.metadata_id,
+  a.receipt_id,
+  a.token_id,
+  a.currency,
+  a.price,
+  a.timestamp
+FROM nft_activities a
+LEFT JOIN nft_tokens t
+  ON t.nft_contract_id = a.nft_contract_id AND t.token_id = a.token_id
+WHERE a.kind = nft_sale_kind()
+  AND a.price IS NOT NULL
+  AND NOT EXISTS (
+    SELECT 1
+    FROM nft_sale_flags f
+    WHERE f.receipt_id = a.receipt_id AND f.token_id = a.token_id
+  );
+
+-- Same as before, but without flagged sales
+CREATE OR REPLACE VIEW nft_sales AS
+SELECT
+  a.nft_contract_id,
+  a.token_id,
+  a.receipt_id,
+  a.timestamp,
+  a.currency,
+  a.price
+FROM nft_activities a
+WHERE a.kind = nft_sale_kind()
+  AND a.price IS NOT NULL
+  AND a.currency IS NOT NULL
+  AND NOT EXISTS (
+    SELECT 1
+    FROM nft_sale_flags f
+    WHERE f.receipt_id = a.receipt_id AND f.token_id = a.token_id
+  )
+UNION ALL
+SELECT
+  nft_contract_id,
+  token_id,
+  sale_receipt_id,
+  sold_at,
+  currency,
+  sale_price
+FROM nft_external_listings
+WHERE sold_at IS NOT NULL AND sale_price IS NOT NULL;
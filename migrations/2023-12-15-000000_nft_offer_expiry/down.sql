@@ -0,0 +1,4 @@
+DELETE FROM nft_activities WHERE kind = 'expire_offer';
+DROP FUNCTION expire_nft_offers(TIMESTAMP, TEXT[]);
+DROP INDEX nft_offers_expiry_idx;
+ALTER TABLE nft_offers DROP COLUMN expired_at;
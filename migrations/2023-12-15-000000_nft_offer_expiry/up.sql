@@ -0,0 +1,66 @@
+-- Offers that have run out at `expires_at` while still active get
+-- `expired_at`, which is always their `expires_at` instead of the time at
+-- which they have been swept, such that replays yield the same rows.
+ALTER TABLE nft_offers ADD COLUMN expired_at TIMESTAMP;
+
+CREATE INDEX nft_offers_expiry_idx ON nft_offers (expires_at)
+  WHERE expires_at IS NOT NULL
+    AND expired_at IS NULL
+    AND accepted_at IS NULL
+    AND withdrawn_at IS NULL
+    AND outbid_at IS NULL
+    AND invalidated_at IS NULL;
+
+-- Expires all active offers whose `expires_at` is not after `as_of`, the
+-- timestamp of the current block, and records an `expire_offer` activity for
+-- each of them under the receipt of the offer. Only offers on `contracts` are
+-- expired unless that is NULL. Returns the number of expired offers.
+CREATE FUNCTION expire_nft_offers(
+  as_of TIMESTAMP,
+  contracts TEXT[] DEFAULT NULL
+) RETURNS BIGINT AS $$
+DECLARE
+  n BIGINT;
+BEGIN
+  WITH expired AS (
+    UPDATE nft_offers o
+    SET expired_at = o.expires_at
+    WHERE o.expires_at <= as_of
+      AND (contracts IS NULL OR o.nft_contract_id = ANY(contracts))
+      AND o.expired_at IS NULL
+      AND o.accepted_at IS NULL
+      AND o.withdrawn_at IS NULL
+      AND o.outbid_at IS NULL
+      AND o.invalidated_at IS NULL
+    RETURNING o.*
+  ),
+  activities AS (
+    INSERT INTO nft_activities (
+      receipt_id, tx_sender, sender_pk, timestamp, nft_contract_id, token_id,
+      kind, action_sender, action_receiver, memo, price, currency
+    )
+    SELECT
+      e.receipt_id,
+      e.offered_by,
+      NULL,
+      e.expires_at,
+      e.nft_contract_id,
+      e.token_id,
+      'expire_offer',
+      e.offered_by,
+      l.listed_by,
+      NULL,
+      e.offer_price,
+      e.currency
+    FROM expired e
+    LEFT JOIN nft_listings l
+      ON l.nft_contract_id = e.nft_contract_id
+      AND l.token_id = e.token_id
+      AND l.market_id = e.market_id
+      AND l.approval_id = e.approval_id
+    ON CONFLICT DO NOTHING
+  )
+  SELECT count(*) INTO n FROM expired;
+  RETURN n;
+END;
+$$ LANGUAGE plpgsql;
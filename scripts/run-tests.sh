@@ -16,6 +16,20 @@ while docker ps | grep indexer >/dev/null; do
 done
 echo 'Indexer finished'
 
-docker compose exec tests cargo test || exit 1
+# UPDATE_GOLDEN=1 rewrites the golden files instead of comparing against them
+if [ -n "$UPDATE_GOLDEN" ]; then
+  docker compose exec -e UPDATE_GOLDEN=1 tests cargo test || exit 1
+  docker compose exec tests cargo run -- snapshot \
+    --golden tests/golden/testnet.snap --update || exit 1
+else
+  docker compose exec tests cargo test || exit 1
+
+  if [ ! -f tests/golden/testnet.snap ]; then
+    echo 'Missing tests/golden/testnet.snap, create it with UPDATE_GOLDEN=1'
+    exit 1
+  fi
+  docker compose exec tests cargo run -- snapshot \
+    --golden tests/golden/testnet.snap || exit 1
+fi
 
 docker-compose down
@@ -0,0 +1,140 @@
+use anyhow::{
+    bail,
+    Context,
+    Result,
+};
+use chrono::NaiveDateTime;
+use diesel::{
+    Connection,
+    ExpressionMethods,
+    PgConnection,
+    QueryDsl,
+    RunQueryDsl,
+};
+
+use crate::schema::backfill_jobs::dsl;
+
+/// A contract-scoped backfill, which indexes a range of blocks for a set of
+/// contracts into the live database. Its progress is kept in its own row of
+/// `backfill_jobs`, such that it can run next to the live indexer and resume
+/// after being interrupted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackfillJob {
+    pub job_id: String,
+    /// Sorted and deduplicated, empty for all contracts
+    pub contracts: Vec<String>,
+    pub start_height: u64,
+    pub stop_height: u64,
+    /// Last block that has been processed, if any
+    pub synced_height: Option<u64>,
+    /// Timestamps of the first and the last processed block
+    pub first_timestamp: Option<NaiveDateTime>,
+    pub synced_timestamp: Option<NaiveDateTime>,
+}
+
+impl BackfillJob {
+    /// Creates the job, or loads it to be resumed if it already exists. An
+    /// existing job needs to have the same contracts and range.
+    pub fn register(
+        pg_string: &str,
+        job_id: &str,
+        contracts: &[String],
+        start_height: u64,
+        stop_height: u64,
+    ) -> Result<BackfillJob> {
+        if stop_height < start_height {
+            bail!(
+                "Backfill stop height ({}) is below start height ({})",
+                stop_height,
+                start_height
+            );
+        }
+        let mut contracts = contracts.to_vec();
+        contracts.sort();
+        contracts.dedup();
+
+        let conn = PgConnection::establish(pg_string)?;
+        diesel::insert_into(dsl::backfill_jobs)
+            .values((
+                dsl::job_id.eq(job_id),
+                dsl::contracts.eq(&contracts),
+                dsl::start_height.eq(start_height as i64),
+                dsl::stop_height.eq(stop_height as i64),
+            ))
+            .on_conflict(dsl::job_id)
+            .do_nothing()
+            .execute(&conn)
+            .with_context(|| {
+                format!("Failed to register backfill {}", job_id)
+            })?;
+
+        let job = Self::load_with(&conn, job_id)?;
+        if job.contracts != contracts
+            || job.start_height != start_height
+            || job.stop_height != stop_height
+        {
+            bail!(
+                "Backfill {} already exists for {:?} at {}..={}",
+                job_id,
+                job.contracts,
+                job.start_height,
+                job.stop_height
+            );
+        }
+        Ok(job)
+    }
+
+    pub fn load(pg_string: &str, job_id: &str) -> Result<BackfillJob> {
+        let conn = PgConnection::establish(pg_string)?;
+        Self::load_with(&conn, job_id)
+    }
+
+    fn load_with(conn: &PgConnection, job_id: &str) -> Result<BackfillJob> {
+        let (
+            contracts,
+            start_height,
+            stop_height,
+            synced_height,
+            first_timestamp,
+            synced_timestamp,
+        ) = dsl::backfill_jobs
+            .filter(dsl::job_id.eq(job_id))
+            .select((
+                dsl::contracts,
+                dsl::start_height,
+                dsl::stop_height,
+                dsl::synced_height,
+                dsl::first_timestamp,
+                dsl::synced_timestamp,
+            ))
+            .first::<(
+                Vec<String>,
+                i64,
+                i64,
+                Option<i64>,
+                Option<NaiveDateTime>,
+                Option<NaiveDateTime>,
+            )>(conn)
+            .with_context(|| format!("Failed to load backfill {}", job_id))?;
+        Ok(BackfillJob {
+            job_id: job_id.to_string(),
+            contracts,
+            start_height: start_height as u64,
+            stop_height: stop_height as u64,
+            synced_height: synced_height.map(|h| h as u64),
+            first_timestamp,
+            synced_timestamp,
+        })
+    }
+
+    /// First block that still needs to be processed
+    pub fn resume_height(&self) -> u64 {
+        self.synced_height
+            .map(|h| h + 1)
+            .unwrap_or(self.start_height)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.synced_height.map_or(false, |h| h >= self.stop_height)
+    }
+}
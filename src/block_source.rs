@@ -0,0 +1,280 @@
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use near_lake_framework::{
+    near_indexer_primitives::{
+        views::BlockView,
+        IndexerShard,
+        StreamerMessage,
+    },
+    LakeConfigBuilder,
+};
+use tokio::{
+    io::AsyncBufReadExt,
+    sync::mpsc,
+};
+
+use crate::{
+    LakeHandle,
+    LakeStreamer,
+};
+
+mod rpc;
+
+/// Same as the default preload pool size of the lake framework
+const CHANNEL_SIZE: usize = 100;
+
+/// Whether blocks may still be orphaned after being streamed. The lake only
+/// serves final blocks, optimistic heads require writes to be journaled, such
+/// that they can be rolled back on forks.
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Finality {
+    #[default]
+    Final,
+    Optimistic,
+}
+
+/// Where blocks are read from. All sources feed a `LakeStreamer`, such that
+/// the runtime cannot tell them apart.
+#[derive(Debug, Clone)]
+pub enum BlockSource {
+    /// NEAR Lake S3 bucket
+    Lake {
+        s3_bucket_name: String,
+        s3_region_name: String,
+    },
+    /// Local copy of a lake bucket, with one directory per block:
+    /// `<height, zero-padded to 12 digits>/{block.json,shard_<id>.json}`
+    Directory(PathBuf),
+    /// File with one serialized `StreamerMessage` per line
+    Jsonl(PathBuf),
+    /// NEAR JSON-RPC node, polled for new blocks at the given finality.
+    /// Receipt outcomes are only collected for transactions to `accounts`
+    /// and their sub-accounts.
+    Rpc {
+        url: String,
+        finality: Finality,
+        accounts: Vec<String>,
+    },
+}
+
+impl BlockSource {
+    /// Picks the local source by looking at the path: Directories are read in
+    /// lake format, anything else is considered to be a JSONL file.
+    pub fn from_path(path: PathBuf) -> Self {
+        if path.is_dir() {
+            BlockSource::Directory(path)
+        } else {
+            BlockSource::Jsonl(path)
+        }
+    }
+
+    /// Starts streaming blocks from `start_height` (inclusive). Local sources
+    /// and RPC nodes end after `stop_height` (inclusive), local sources also
+    /// once all blocks have been read.
+    pub fn stream(
+        self,
+        start_height: u64,
+        stop_height: Option<u64>,
+    ) -> (LakeHandle, LakeStreamer) {
+        let range = HeightRange {
+            start: start_height,
+            stop: stop_height.filter(|h| *h != 0),
+        };
+        match self {
+            BlockSource::Lake {
+                s3_bucket_name,
+                s3_region_name,
+            } => {
+                let lake_config = LakeConfigBuilder::default()
+                    .s3_bucket_name(s3_bucket_name)
+                    .s3_region_name(s3_region_name)
+                    .start_block_height(start_height)
+                    .build()
+                    .unwrap();
+                near_lake_framework::streamer(lake_config)
+            }
+            BlockSource::Directory(path) => {
+                let (sender, receiver) = mpsc::channel(CHANNEL_SIZE);
+                let handle =
+                    actix_rt::spawn(stream_directory(path, range, sender));
+                (handle, receiver)
+            }
+            BlockSource::Jsonl(path) => {
+                let (sender, receiver) = mpsc::channel(CHANNEL_SIZE);
+                let handle = actix_rt::spawn(stream_jsonl(path, range, sender));
+                (handle, receiver)
+            }
+            BlockSource::Rpc {
+                url,
+                finality,
+                accounts,
+            } => {
+                let (sender, receiver) = mpsc::channel(CHANNEL_SIZE);
+                let handle = actix_rt::spawn(rpc::stream_rpc(
+                    url, finality, accounts, range, sender,
+                ));
+                (handle, receiver)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct HeightRange {
+    start: u64,
+    stop: Option<u64>,
+}
+
+impl HeightRange {
+    fn contains(&self, height: u64) -> bool {
+        height >= self.start && self.stop.map(|h| height <= h).unwrap_or(true)
+    }
+}
+
+async fn stream_directory(
+    path: PathBuf,
+    range: HeightRange,
+    sender: mpsc::Sender<StreamerMessage>,
+) -> Result<()> {
+    let mut heights = Vec::new();
+    let mut entries = tokio::fs::read_dir(&path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(height) = parse_block_dir(&entry.file_name()) {
+            if range.contains(height) {
+                heights.push((height, entry.path()));
+            }
+        }
+    }
+    heights.sort();
+
+    crate::info!("Streaming {} blocks from {}", heights.len(), path.display());
+    for (_, block_dir) in heights {
+        let msg = read_block_dir(&block_dir).await?;
+        // the receiver is dropped on shutdown, which is not an error
+        if sender.send(msg).await.is_err() {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Reads `block.json` and all `shard_<id>.json` files of a block directory.
+async fn read_block_dir(path: &Path) -> Result<StreamerMessage> {
+    let block: BlockView = read_json(&path.join("block.json")).await?;
+
+    let mut shard_paths = Vec::new();
+    let mut entries = tokio::fs::read_dir(path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(shard_id) = parse_shard_file(&entry.file_name()) {
+            shard_paths.push((shard_id, entry.path()));
+        }
+    }
+    shard_paths.sort();
+
+    let mut shards = Vec::with_capacity(shard_paths.len());
+    for (_, shard_path) in shard_paths {
+        shards.push(read_json::<IndexerShard>(&shard_path).await?);
+    }
+
+    Ok(StreamerMessage { block, shards })
+}
+
+async fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T> {
+    let content = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_slice(&content)
+        .with_context(|| format!("Invalid JSON in {}", path.display()))
+}
+
+async fn stream_jsonl(
+    path: PathBuf,
+    range: HeightRange,
+    sender: mpsc::Sender<StreamerMessage>,
+) -> Result<()> {
+    let file = tokio::fs::File::open(&path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut lines = tokio::io::BufReader::new(file).lines();
+
+    crate::info!("Streaming blocks from {}", path.display());
+    let mut line_number = 0;
+    while let Some(line) = lines.next_line().await? {
+        line_number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let msg = serde_json::from_str::<StreamerMessage>(&line).with_context(
+            || format!("Invalid block at {}:{}", path.display(), line_number),
+        )?;
+        if !range.contains(msg.block.header.height) {
+            continue;
+        }
+        // the receiver is dropped on shutdown, which is not an error
+        if sender.send(msg).await.is_err() {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Block directories are named by their zero-padded height
+fn parse_block_dir(name: &std::ffi::OsStr) -> Option<u64> {
+    let name = name.to_str()?;
+    if name.len() != 12 {
+        return None;
+    }
+    name.parse().ok()
+}
+
+fn parse_shard_file(name: &std::ffi::OsStr) -> Option<u64> {
+    name.to_str()?
+        .strip_prefix("shard_")?
+        .strip_suffix(".json")?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_lake_file_names() {
+        assert_eq!(parse_block_dir(OsStr::new("000061010419")), Some(61010419));
+        assert_eq!(parse_block_dir(OsStr::new("61010419")), None);
+        assert_eq!(parse_block_dir(OsStr::new("block.json")), None);
+        assert_eq!(parse_shard_file(OsStr::new("shard_3.json")), Some(3));
+        assert_eq!(parse_shard_file(OsStr::new("block.json")), None);
+    }
+
+    #[test]
+    fn test_height_range() {
+        let bounded = HeightRange {
+            start: 10,
+            stop: Some(20),
+        };
+        assert!(!bounded.contains(9));
+        assert!(bounded.contains(10));
+        assert!(bounded.contains(20));
+        assert!(!bounded.contains(21));
+
+        let unbounded = HeightRange {
+            start: 10,
+            stop: None,
+        };
+        assert!(unbounded.contains(u64::MAX));
+    }
+}
@@ -0,0 +1,396 @@
+use std::{
+    collections::{
+        HashMap,
+        VecDeque,
+    },
+    time::Duration,
+};
+
+use anyhow::{
+    bail,
+    Result,
+};
+use near_lake_framework::near_indexer_primitives::{
+    types::AccountId,
+    views::{
+        BlockView,
+        ExecutionOutcomeWithIdView,
+        ReceiptEnumView,
+        ReceiptView,
+        SignedTransactionView,
+    },
+    CryptoHash,
+    IndexerChunkView,
+    IndexerExecutionOutcomeWithReceipt,
+    IndexerShard,
+    StreamerMessage,
+};
+use tokio::sync::mpsc;
+
+use super::{
+    Finality,
+    HeightRange,
+};
+use crate::near_rpc::{
+    NearRpcClient,
+    TxStatusView,
+};
+
+/// Time between polls of the chain head once we caught up
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Blocks that emitted hashes, pending transactions and outcomes are kept
+/// for. Forks deeper than this can't be followed.
+const TRACKING_DEPTH: u64 = 100;
+/// Transactions whose outcomes are still being collected. Beyond this, the
+/// stream stops, as their outcomes can't be collected in time.
+const MAX_PENDING_TXS: usize = 1000;
+/// Status queries that are sent to the node at the same time
+const MAX_CONCURRENT_POLLS: usize = 16;
+
+/// Polls blocks from a NEAR RPC node, following the head at the given
+/// finality.
+///
+/// Receipt outcomes are not part of blocks or chunks in the RPC API. They are
+/// collected via `EXPERIMENTAL_tx_status` for the transactions to `accounts`
+/// (or their sub-accounts) that have been included in streamed chunks, until
+/// those are fully executed. Receipts of transactions that were included
+/// before the start height are thus missed, as are receipts of transactions
+/// to other accounts and state changes. If outcomes can't be collected before
+/// their block is streamed, the stream stops with an error instead of
+/// streaming incomplete blocks.
+pub(super) async fn stream_rpc(
+    url: String,
+    finality: Finality,
+    accounts: Vec<String>,
+    range: HeightRange,
+    sender: mpsc::Sender<StreamerMessage>,
+) -> Result<()> {
+    let mut streamer = RpcStreamer {
+        client: NearRpcClient::new(&url)?,
+        finality,
+        accounts,
+        next_height: range.start,
+        emitted: VecDeque::new(),
+        pending_txs: Vec::new(),
+        outcomes: HashMap::new(),
+        collected: HashMap::new(),
+    };
+
+    crate::info!("Streaming {:?} blocks from {}", finality, url);
+    let mut head = 0;
+    while range.contains(streamer.next_height) {
+        if streamer.next_height > head {
+            head = streamer.head_height().await?;
+        }
+        if streamer.next_height > head {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            continue;
+        }
+
+        let height = streamer.next_height;
+        streamer.next_height += 1;
+        let block = match streamer
+            .client
+            .block(serde_json::json!({ "block_id": height }))
+            .await?
+        {
+            Some(block) => block,
+            // skipped height
+            None => continue,
+        };
+
+        for block in streamer.blocks_to_emit(block).await? {
+            let msg = streamer.assemble(block).await?;
+            // the receiver is dropped on shutdown, which is not an error
+            if sender.send(msg).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Transaction that hasn't been fully executed yet
+struct PendingTx {
+    hash: CryptoHash,
+    signer_id: AccountId,
+    /// Height of the block in which the transaction was first seen
+    height: u64,
+}
+
+struct RpcStreamer {
+    client: NearRpcClient,
+    finality: Finality,
+    /// Receivers of the transactions whose outcomes are collected, including
+    /// their sub-accounts
+    accounts: Vec<String>,
+    next_height: u64,
+    /// Hashes of recently emitted blocks, most recent last
+    emitted: VecDeque<CryptoHash>,
+    /// In order of inclusion, which determines the order of their outcomes
+    pending_txs: Vec<PendingTx>,
+    /// Outcomes by hash of the block they were executed in, with the height
+    /// at which they were first seen
+    outcomes:
+        HashMap<CryptoHash, (u64, Vec<IndexerExecutionOutcomeWithReceipt>)>,
+    /// Ids and block hashes of all collected outcomes, with the height at
+    /// which they were last seen
+    collected: HashMap<(CryptoHash, CryptoHash), u64>,
+}
+
+impl RpcStreamer {
+    async fn head_height(&self) -> Result<u64> {
+        let finality = match self.finality {
+            Finality::Final => "final",
+            Finality::Optimistic => "optimistic",
+        };
+        match self
+            .client
+            .block(serde_json::json!({ "finality": finality }))
+            .await?
+        {
+            Some(block) => Ok(block.header.height),
+            None => bail!("RPC node did not return the chain head"),
+        }
+    }
+
+    /// Usually just `block`. If it doesn't build on the last emitted block,
+    /// its ancestors are fetched back to the last emitted block they build on,
+    /// such that the runtime can roll back to that block.
+    async fn blocks_to_emit(
+        &mut self,
+        block: BlockView,
+    ) -> Result<Vec<BlockView>> {
+        let mut chain = vec![block];
+        loop {
+            let prev_hash = chain.last().unwrap().header.prev_hash;
+            let is_known = self.emitted.contains(&prev_hash);
+            if self.emitted.is_empty() || is_known {
+                break;
+            }
+            if chain.len() as u64 > TRACKING_DEPTH {
+                bail!("Fork at {} is too deep to follow", self.next_height - 1);
+            }
+            match self
+                .client
+                .block(serde_json::json!({ "block_id": prev_hash }))
+                .await?
+            {
+                Some(prev) => chain.push(prev),
+                None => bail!("Unknown ancestor block {}", prev_hash),
+            }
+        }
+        chain.reverse();
+
+        // forget orphaned blocks
+        let fork_hash = chain[0].header.prev_hash;
+        while let Some(hash) = self.emitted.back() {
+            if *hash == fork_hash {
+                break;
+            }
+            self.emitted.pop_back();
+        }
+        Ok(chain)
+    }
+
+    async fn assemble(&mut self, block: BlockView) -> Result<StreamerMessage> {
+        let height = block.header.height;
+        let hash = block.header.hash;
+
+        let mut shards = Vec::with_capacity(block.chunks.len());
+        for chunk_header in block.chunks.iter() {
+            // chunks that haven't been produced for this block are repeated
+            if chunk_header.height_included != height {
+                shards.push(IndexerShard {
+                    shard_id: chunk_header.shard_id,
+                    chunk: None,
+                    receipt_execution_outcomes: vec![],
+                    state_changes: vec![],
+                });
+                continue;
+            }
+
+            let chunk = self.client.chunk(&chunk_header.chunk_hash).await?;
+            for tx in chunk.transactions.iter() {
+                if !self.is_tracked(&tx.receiver_id) {
+                    continue;
+                }
+                if !self.pending_txs.iter().any(|p| p.hash == tx.hash) {
+                    self.pending_txs.push(PendingTx {
+                        hash: tx.hash,
+                        signer_id: tx.signer_id.clone(),
+                        height,
+                    });
+                }
+            }
+            shards.push(IndexerShard {
+                shard_id: chunk_header.shard_id,
+                chunk: Some(IndexerChunkView {
+                    author: chunk.author,
+                    header: chunk.header,
+                    // not used by the runtime, and would need one outcome
+                    // query per transaction
+                    transactions: vec![],
+                    receipts: chunk.receipts,
+                }),
+                receipt_execution_outcomes: vec![],
+                state_changes: vec![],
+            });
+        }
+
+        if self.pending_txs.len() > MAX_PENDING_TXS {
+            bail!(
+                "{} transactions pending at {}, their outcomes can't be \
+                 collected in time",
+                self.pending_txs.len(),
+                height
+            );
+        }
+        self.poll_pending_txs(height).await?;
+
+        // the shard of a receipt isn't known, the runtime doesn't care though
+        let outcomes = self
+            .outcomes
+            .remove(&hash)
+            .map(|(_, outcomes)| outcomes)
+            .unwrap_or_default();
+        if let Some(shard) = shards.iter_mut().find(|s| s.chunk.is_some()) {
+            shard.receipt_execution_outcomes = outcomes;
+        }
+
+        self.emitted.push_back(hash);
+        while self.emitted.len() as u64 > TRACKING_DEPTH {
+            self.emitted.pop_front();
+        }
+        self.prune(height);
+
+        Ok(StreamerMessage { block, shards })
+    }
+
+    /// Whether outcomes of transactions to `receiver` are collected
+    fn is_tracked(&self, receiver: &AccountId) -> bool {
+        let receiver = receiver.as_str();
+        self.accounts.iter().any(|account| {
+            receiver == account
+                || receiver
+                    .strip_suffix(account.as_str())
+                    .map_or(false, |sub| sub.ends_with('.'))
+        })
+    }
+
+    /// Collects the outcomes of all pending transactions, and stops tracking
+    /// those that have been fully executed. Outcomes of blocks that have
+    /// already been emitted can't be streamed anymore, which stops the
+    /// stream.
+    async fn poll_pending_txs(&mut self, height: u64) -> Result<()> {
+        use futures::StreamExt;
+
+        let statuses = futures::stream::iter(
+            self.pending_txs
+                .iter()
+                .map(|tx| self.client.tx_status(&tx.hash, &tx.signer_id)),
+        )
+        .buffered(MAX_CONCURRENT_POLLS)
+        .collect::<Vec<_>>()
+        .await;
+
+        let mut final_txs = Vec::new();
+        for (tx, status) in self.pending_txs.iter().zip(statuses) {
+            let status = match status {
+                Ok(status) => status,
+                // might not have been processed by the node yet
+                Err(e) => {
+                    crate::debug!("Failed to query tx {}: {:?}", tx.hash, e);
+                    continue;
+                }
+            };
+            if status.is_final() {
+                final_txs.push(tx.hash);
+            }
+            for outcome in receipt_outcomes(status) {
+                // pending transactions are polled repeatedly
+                let id = outcome.execution_outcome.id;
+                let block_hash = outcome.execution_outcome.block_hash;
+                if self.collected.insert((id, block_hash), height).is_some() {
+                    continue;
+                }
+                if self.emitted.contains(&block_hash) {
+                    bail!(
+                        "Outcome of receipt {} arrived after its block {} has \
+                         been streamed",
+                        id,
+                        block_hash
+                    );
+                }
+                self.outcomes
+                    .entry(block_hash)
+                    .or_insert_with(|| (height, Vec::new()))
+                    .1
+                    .push(outcome);
+            }
+        }
+        self.pending_txs.retain(|tx| !final_txs.contains(&tx.hash));
+        Ok(())
+    }
+
+    /// Drops transactions and outcomes that are not going to be needed
+    /// anymore, e.g. because they belong to orphaned blocks.
+    fn prune(&mut self, height: u64) {
+        let min_height = height.saturating_sub(TRACKING_DEPTH);
+        self.pending_txs.retain(|tx| tx.height >= min_height);
+        self.outcomes.retain(|_, (h, _)| *h >= min_height);
+        self.collected.retain(|_, h| *h >= min_height);
+    }
+}
+
+/// Pairs receipt outcomes of a transaction with their receipts. The receipt
+/// that the transaction has been converted to is not part of the response
+/// and is reconstructed from the transaction.
+fn receipt_outcomes(
+    status: TxStatusView,
+) -> Vec<IndexerExecutionOutcomeWithReceipt> {
+    let mut receipts = status
+        .receipts
+        .into_iter()
+        .map(|receipt| (receipt.receipt_id, receipt))
+        .collect::<HashMap<_, _>>();
+    if let Some(receipt_id) =
+        status.transaction_outcome.outcome.receipt_ids.first()
+    {
+        receipts.insert(
+            *receipt_id,
+            transaction_receipt(&status.transaction, *receipt_id),
+        );
+    }
+
+    status
+        .receipts_outcome
+        .into_iter()
+        .filter_map(|execution_outcome: ExecutionOutcomeWithIdView| {
+            let receipt = receipts.remove(&execution_outcome.id)?;
+            Some(IndexerExecutionOutcomeWithReceipt {
+                execution_outcome,
+                receipt,
+            })
+        })
+        .collect()
+}
+
+fn transaction_receipt(
+    tx: &SignedTransactionView,
+    receipt_id: CryptoHash,
+) -> ReceiptView {
+    ReceiptView {
+        predecessor_id: tx.signer_id.clone(),
+        receiver_id: tx.receiver_id.clone(),
+        receipt_id,
+        receipt: ReceiptEnumView::Action {
+            signer_id: tx.signer_id.clone(),
+            signer_public_key: tx.public_key.clone(),
+            gas_price: 0,
+            output_data_receivers: vec![],
+            input_data_ids: vec![],
+            actions: tx.actions.clone(),
+        },
+    }
+}
@@ -0,0 +1,486 @@
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use diesel::{
+    pg::upsert::{
+        excluded,
+        on_constraint,
+    },
+    Connection,
+    ExpressionMethods,
+    PgConnection,
+    QueryDsl,
+    RunQueryDsl,
+};
+use minterop_data::{
+    db_rows::{
+        NftActivity,
+        NftEarning,
+        NftToken,
+    },
+    schema::{
+        nft_activities,
+        nft_earnings,
+        nft_tokens,
+    },
+};
+
+use crate::{
+    database::ExecuteDb,
+    handlers::{
+        NftOwnershipChange,
+        NftSplitChange,
+    },
+    rpc_connection::MinteropRpcConnector,
+    runtime::{
+        Cursor,
+        CursorPosition,
+        TxProcessingRuntime,
+    },
+    schema::{
+        nft_ownership_changes,
+        nft_split_changes,
+    },
+    stats::StaleStats,
+    ReceiptData,
+};
+
+/// Rows per statement, staying well below the limit of bind parameters
+const ROWS_PER_STATEMENT: usize = 1000;
+
+/// The upsert a buffered token row is written with, mirroring the handler
+/// that produced it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TokenWrite {
+    Mint,
+    Transfer,
+    Burn,
+}
+
+impl TokenWrite {
+    /// Timestamp and receipt of the write, by which it is ordered
+    fn order<'a>(
+        &self,
+        token: &'a NftToken,
+    ) -> (Option<chrono::NaiveDateTime>, Option<&'a str>) {
+        let (timestamp, receipt_id) = match self {
+            TokenWrite::Mint => {
+                (token.minted_timestamp, &token.minted_receipt_id)
+            }
+            TokenWrite::Transfer => (
+                token.last_transfer_timestamp,
+                &token.last_transfer_receipt_id,
+            ),
+            TokenWrite::Burn => {
+                (token.burned_timestamp, &token.burned_receipt_id)
+            }
+        };
+        (timestamp, receipt_id.as_deref())
+    }
+}
+
+type TokenKey = (String, String);
+
+/// Whether the handler of an event log reads or updates `nft_tokens`, which
+/// in bulk mode requires the tokens of earlier blocks to be flushed: Listings
+/// look up the metadata ID of the token, and split owners are set on it.
+pub(crate) fn reads_tokens(log: &str) -> bool {
+    matches!(
+        near_events::partial_deserialize_event(log),
+        Some((standard, _, event, _))
+            if (standard == "mb_market" && event == "nft_list")
+                || (standard == "mb_store" && event == "nft_set_split_owners")
+    )
+}
+
+/// Token metadata request to the minterop RPC, which needs the token to exist
+struct TokenRpc {
+    contract_id: String,
+    token_ids: Vec<String>,
+    minter: Option<String>,
+}
+
+/// Rows that have been buffered since the last flush. Token rows are merged
+/// per token, such that each upsert touches every token at most once.
+#[derive(Default)]
+struct BulkRows {
+    activities: Vec<NftActivity>,
+    earnings: Vec<NftEarning>,
+    ownership_changes: Vec<NftOwnershipChange>,
+    split_changes: Vec<NftSplitChange>,
+    minted: HashMap<TokenKey, NftToken>,
+    transferred: HashMap<TokenKey, NftToken>,
+    burned: HashMap<TokenKey, NftToken>,
+    token_rpcs: Vec<TokenRpc>,
+}
+
+/// Buffer shared by the handlers of a runtime in bulk mode
+#[derive(Clone, Default)]
+pub(crate) struct BulkBuffer(Arc<Mutex<BulkRows>>);
+
+impl BulkBuffer {
+    pub(crate) fn push_activities(&self, activities: Vec<NftActivity>) {
+        self.rows().activities.extend(activities);
+    }
+
+    pub(crate) fn push_earnings(&self, earnings: Vec<NftEarning>) {
+        self.rows().earnings.extend(earnings);
+    }
+
+    pub(crate) fn push_ownership_changes(
+        &self,
+        changes: Vec<NftOwnershipChange>,
+    ) {
+        self.rows().ownership_changes.extend(changes);
+    }
+
+    pub(crate) fn push_split_changes(&self, changes: Vec<NftSplitChange>) {
+        self.rows().split_changes.extend(changes);
+    }
+
+    /// Later writes of the same kind replace earlier ones for a token. Writes
+    /// of the same block are handled concurrently, so they are ordered by
+    /// receipt ID like in the reconciliation of a reindex, instead of by
+    /// whichever handler happens to finish last.
+    pub(crate) fn push_tokens(&self, write: TokenWrite, tokens: Vec<NftToken>) {
+        let mut rows = self.rows();
+        let buffered = match write {
+            TokenWrite::Mint => &mut rows.minted,
+            TokenWrite::Transfer => &mut rows.transferred,
+            TokenWrite::Burn => &mut rows.burned,
+        };
+        for token in tokens {
+            let key = (token.nft_contract_id.clone(), token.token_id.clone());
+            match buffered.get(&key) {
+                Some(prev) if write.order(prev) > write.order(&token) => {}
+                _ => {
+                    buffered.insert(key, token);
+                }
+            }
+        }
+    }
+
+    /// Defers a token RPC until the buffered tokens have been flushed
+    pub(crate) fn defer_token_rpc(
+        &self,
+        contract_id: String,
+        token_ids: Vec<String>,
+        minter: Option<String>,
+    ) {
+        self.rows().token_rpcs.push(TokenRpc {
+            contract_id,
+            token_ids,
+            minter,
+        });
+    }
+
+    fn take(&self) -> BulkRows {
+        std::mem::take(&mut *self.rows())
+    }
+
+    fn rows(&self) -> std::sync::MutexGuard<'_, BulkRows> {
+        // handlers never panic while holding the lock
+        self.0.lock().expect("Bulk buffer is poisoned")
+    }
+}
+
+/// Write mode for backfills, in which handlers buffer activities, earnings
+/// and tokens instead of writing them one statement at a time. The buffer is
+/// flushed with multi-row statements every `flush_blocks` blocks, together
+/// with the cursor and the statistics of touched contracts, after which
+/// deferred token RPCs are dispatched.
+pub(crate) struct BulkMode {
+    pub(crate) buffer: BulkBuffer,
+    pg_string: String,
+    flush_blocks: u64,
+    /// Blocks processed since the last flush
+    pending: Cell<u64>,
+}
+
+impl BulkMode {
+    pub(crate) fn new(pg_string: &str, flush_blocks: u64) -> Self {
+        Self {
+            buffer: Default::default(),
+            pg_string: pg_string.to_string(),
+            flush_blocks,
+            pending: Cell::new(0),
+        }
+    }
+
+    /// Counts a processed block, and returns whether a flush is due
+    pub(crate) fn block_done(&self) -> bool {
+        self.pending.set(self.pending.get() + 1);
+        self.pending.get() >= self.flush_blocks
+    }
+
+    pub(crate) fn has_pending(&self) -> bool {
+        self.pending.get() > 0
+    }
+
+    /// Writes all buffered rows and moves `cursor` to `position` in a single
+    /// transaction, such that a failed flush resumes from the last one.
+    pub(crate) async fn flush(
+        &self,
+        rpc: &MinteropRpcConnector,
+        stats: &StaleStats,
+        cursor: &Cursor,
+        position: CursorPosition,
+    ) -> Result<()> {
+        let mut rows = self.buffer.take();
+        let token_rpcs = std::mem::take(&mut rows.token_rpcs);
+        let stale_stats = stats.take();
+        let blocks = self.pending.replace(0);
+        let pg_string = self.pg_string.clone();
+        let cursor = cursor.clone();
+        let height = position.height;
+
+        crate::info!(
+            "Flushing {} activities of {} blocks up to {}",
+            rows.activities.len(),
+            blocks,
+            height
+        );
+        actix_rt::task::spawn_blocking(move || {
+            let conn = PgConnection::establish(&pg_string)?;
+            conn.transaction(|| {
+                write_rows(&conn, rows)?;
+                crate::stats::refresh_sync(&conn, stale_stats)?;
+                write_cursor(&conn, &cursor, &position)
+            })
+        })
+        .await
+        .context("Bulk flush panicked")?
+        .with_context(|| format!("Failed to flush blocks up to {}", height))?;
+
+        for req in token_rpcs {
+            let rpc_ = rpc.clone();
+            rpc.dispatch(async move {
+                rpc_.token(req.contract_id, req.token_ids, req.minter, None)
+                    .await
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Inserts activities, buffering them if the runtime is in bulk mode
+pub(crate) async fn insert_activities(
+    rt: &TxProcessingRuntime,
+    tx: &ReceiptData,
+    activities: Vec<NftActivity>,
+    msg: &str,
+) {
+    match &rt.bulk {
+        Some(buffer) => buffer.push_activities(activities),
+        None => {
+            diesel::insert_into(nft_activities::table)
+                .values(activities)
+                .execute_db(rt, tx, msg)
+                .await
+        }
+    }
+}
+
+/// Inserts earnings, buffering them if the runtime is in bulk mode
+pub(crate) async fn insert_earnings(
+    rt: &TxProcessingRuntime,
+    tx: &ReceiptData,
+    earnings: Vec<NftEarning>,
+    msg: &str,
+) {
+    match &rt.bulk {
+        Some(buffer) => buffer.push_earnings(earnings),
+        None => {
+            diesel::insert_into(nft_earnings::table)
+                .values(earnings)
+                .execute_db(rt, tx, msg)
+                .await
+        }
+    }
+}
+
+/// Inserts ownership changes, buffering them if the runtime is in bulk mode
+pub(crate) async fn insert_ownership_changes(
+    rt: &TxProcessingRuntime,
+    tx: &ReceiptData,
+    changes: Vec<NftOwnershipChange>,
+    msg: &str,
+) {
+    match &rt.bulk {
+        Some(buffer) => buffer.push_ownership_changes(changes),
+        None => {
+            diesel::insert_into(nft_ownership_changes::table)
+                .values(changes)
+                .on_conflict_do_nothing()
+                .execute_db(rt, tx, msg)
+                .await
+        }
+    }
+}
+
+/// Inserts royalties and split owners that have been set on tokens, buffering
+/// them if the runtime is in bulk mode
+pub(crate) async fn insert_split_changes(
+    rt: &TxProcessingRuntime,
+    tx: &ReceiptData,
+    changes: Vec<NftSplitChange>,
+    msg: &str,
+) {
+    match &rt.bulk {
+        Some(buffer) => buffer.push_split_changes(changes),
+        None => {
+            diesel::insert_into(nft_split_changes::table)
+                .values(changes)
+                .on_conflict_do_nothing()
+                .execute_db(rt, tx, msg)
+                .await
+        }
+    }
+}
+
+/// Activities, earnings, ownership and split changes that already exist are
+/// skipped, such that ranges can be backfilled again. Tokens are written in the order
+/// of the handlers: Mints first, then transfers, then burns.
+fn write_rows(conn: &PgConnection, rows: BulkRows) -> Result<()> {
+    use minterop_data::schema::nft_tokens::dsl;
+
+    for chunk in rows.activities.chunks(ROWS_PER_STATEMENT) {
+        diesel::insert_into(nft_activities::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .context("Failed to insert activities")?;
+    }
+    for chunk in rows.earnings.chunks(ROWS_PER_STATEMENT) {
+        diesel::insert_into(nft_earnings::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .context("Failed to insert earnings")?;
+    }
+    for chunk in rows.ownership_changes.chunks(ROWS_PER_STATEMENT) {
+        diesel::insert_into(nft_ownership_changes::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .context("Failed to insert ownership changes")?;
+    }
+    for chunk in rows.split_changes.chunks(ROWS_PER_STATEMENT) {
+        diesel::insert_into(nft_split_changes::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .context("Failed to insert split changes")?;
+    }
+
+    let minted = rows.minted.into_values().collect::<Vec<_>>();
+    for chunk in minted.chunks(ROWS_PER_STATEMENT) {
+        diesel::insert_into(nft_tokens::table)
+            .values(chunk)
+            .on_conflict(on_constraint("nft_tokens_pkey"))
+            .do_update()
+            .set((
+                dsl::mint_memo.eq(excluded(dsl::mint_memo)),
+                dsl::minted_timestamp.eq(excluded(dsl::minted_timestamp)),
+                dsl::minted_receipt_id.eq(excluded(dsl::minted_receipt_id)),
+                dsl::minter.eq(excluded(dsl::minter)),
+                dsl::royalties.eq(excluded(dsl::royalties)),
+                dsl::royalties_percent.eq(excluded(dsl::royalties_percent)),
+            ))
+            .execute(conn)
+            .context("Failed to insert minted tokens")?;
+    }
+
+    let transferred = rows.transferred.into_values().collect::<Vec<_>>();
+    for chunk in transferred.chunks(ROWS_PER_STATEMENT) {
+        diesel::insert_into(nft_tokens::table)
+            .values(chunk)
+            .on_conflict(on_constraint("nft_tokens_pkey"))
+            .do_update()
+            .set((
+                dsl::owner.eq(excluded(dsl::owner)),
+                dsl::last_transfer_timestamp
+                    .eq(excluded(dsl::last_transfer_timestamp)),
+                dsl::last_transfer_receipt_id
+                    .eq(excluded(dsl::last_transfer_receipt_id)),
+                dsl::splits.eq(Option::<serde_json::Value>::None),
+            ))
+            .execute(conn)
+            .context("Failed to insert transferred tokens")?;
+    }
+
+    let burned = rows.burned.into_values().collect::<Vec<_>>();
+    for chunk in burned.chunks(ROWS_PER_STATEMENT) {
+        diesel::insert_into(nft_tokens::table)
+            .values(chunk)
+            .on_conflict(on_constraint("nft_tokens_pkey"))
+            .do_update()
+            .set((
+                dsl::burned_timestamp.eq(excluded(dsl::burned_timestamp)),
+                dsl::burned_receipt_id.eq(excluded(dsl::burned_receipt_id)),
+            ))
+            .execute(conn)
+            .context("Failed to insert burned tokens")?;
+    }
+
+    Ok(())
+}
+
+fn write_cursor(
+    conn: &PgConnection,
+    cursor: &Cursor,
+    position: &CursorPosition,
+) -> Result<()> {
+    let height = position.height;
+    match cursor {
+        Cursor::Live => {
+            use crate::schema::blocks::dsl;
+
+            diesel::update(dsl::blocks)
+                .set((
+                    dsl::synced_height.eq(height as i64),
+                    dsl::synced_hash.eq(Some(position.hash.clone())),
+                ))
+                .execute(conn)
+        }
+        Cursor::Backfill(job_id) => {
+            use crate::schema::backfill_jobs::dsl;
+
+            // resumed jobs keep the timestamp of their first block
+            let job = dsl::backfill_jobs.filter(dsl::job_id.eq(job_id));
+            let replayed = position.replayed.as_ref();
+            if let Some(first) = replayed.map(|range| *range.start()) {
+                diesel::update(
+                    job.clone().filter(dsl::first_timestamp.is_null()),
+                )
+                .set(dsl::first_timestamp.eq(Some(first)))
+                .execute(conn)
+                .context("Failed to set first timestamp")?;
+            }
+
+            let finished_at =
+                position.finished.then(|| chrono::Utc::now().naive_utc());
+            diesel::update(job)
+                .set((
+                    dsl::synced_height.eq(Some(height as i64)),
+                    dsl::synced_timestamp
+                        .eq(replayed.map(|range| *range.end())),
+                    dsl::finished_at.eq(finished_at),
+                ))
+                .execute(conn)
+        }
+        Cursor::Replay => Ok(0),
+    }
+    .with_context(|| format!("Failed to set cursor to {}", height))?;
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use std::path::Path;
+
+use anyhow::{
+    Context,
+    Result,
+};
+use near_lake_framework::near_indexer_primitives::{
+    views::StateChangeValueView,
+    IndexerShard,
+    StreamerMessage,
+};
+
+use crate::{
+    shutdown::ShutdownSignal,
+    LakeStreamer,
+};
+
+/// Number of blocks and receipts written by `capture`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CaptureSummary {
+    pub blocks: usize,
+    pub receipts: usize,
+}
+
+/// Writes blocks up to `stop_height` (inclusive) to `dir`, in the lake format
+/// that `BlockSource::Directory` reads. Only receipt outcomes executed by one
+/// of `contracts` and state changes of their accounts are kept, blocks without
+/// any of these are skipped entirely.
+pub async fn capture(
+    mut stream: LakeStreamer,
+    stop_height: u64,
+    contracts: &[String],
+    dir: &Path,
+    mut shutdown: ShutdownSignal,
+) -> Result<CaptureSummary> {
+    tokio::fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let mut summary = CaptureSummary::default();
+    while let Some(msg) =
+        crate::runtime::next_block(&mut stream, &mut shutdown).await
+    {
+        let height = msg.block.header.height;
+        if height > stop_height {
+            break;
+        }
+        if height % 1000 == 0 {
+            crate::info!("Capturing block {}", height);
+        }
+
+        if let Some(msg) = filter_message(msg, contracts) {
+            summary.blocks += 1;
+            summary.receipts += msg
+                .shards
+                .iter()
+                .map(|shard| shard.receipt_execution_outcomes.len())
+                .sum::<usize>();
+            write_block_dir(dir, &msg).await?;
+        }
+    }
+
+    crate::info!(
+        "Captured {} receipts in {} blocks to {}",
+        summary.receipts,
+        summary.blocks,
+        dir.display()
+    );
+    Ok(summary)
+}
+
+/// Strips the message down to what concerns `contracts`, or `None` if nothing
+/// does. Chunk headers are kept, as the runtime skips shards without chunk.
+fn filter_message(
+    mut msg: StreamerMessage,
+    contracts: &[String],
+) -> Option<StreamerMessage> {
+    let is_relevant =
+        |account_id: &str| contracts.iter().any(|c| c == account_id);
+
+    msg.shards = msg
+        .shards
+        .into_iter()
+        .filter_map(|shard| filter_shard(shard, &is_relevant))
+        .collect();
+
+    if msg.shards.is_empty() {
+        None
+    } else {
+        Some(msg)
+    }
+}
+
+fn filter_shard(
+    mut shard: IndexerShard,
+    is_relevant: &impl Fn(&str) -> bool,
+) -> Option<IndexerShard> {
+    shard
+        .receipt_execution_outcomes
+        .retain(|outcome| is_relevant(outcome.receipt.receiver_id.as_str()));
+    shard.state_changes.retain(|state_change| {
+        state_change_account(&state_change.value).map_or(false, is_relevant)
+    });
+    if shard.receipt_execution_outcomes.is_empty()
+        && shard.state_changes.is_empty()
+    {
+        return None;
+    }
+
+    // transactions and receipts of the chunk are not used by the runtime
+    if let Some(chunk) = shard.chunk.as_mut() {
+        chunk.transactions.clear();
+        chunk.receipts.clear();
+    }
+    Some(shard)
+}
+
+fn state_change_account(value: &StateChangeValueView) -> Option<&str> {
+    match value {
+        StateChangeValueView::AccountUpdate { account_id, .. }
+        | StateChangeValueView::AccountDeletion { account_id }
+        | StateChangeValueView::AccessKeyUpdate { account_id, .. }
+        | StateChangeValueView::AccessKeyDeletion { account_id, .. } => {
+            Some(account_id.as_str())
+        }
+        // not looked at by the runtime, and potentially large
+        _ => None,
+    }
+}
+
+async fn write_block_dir(dir: &Path, msg: &StreamerMessage) -> Result<()> {
+    let block_dir = dir.join(format!("{:012}", msg.block.header.height));
+    tokio::fs::create_dir_all(&block_dir)
+        .await
+        .with_context(|| format!("Failed to create {}", block_dir.display()))?;
+
+    write_json(&block_dir.join("block.json"), &msg.block).await?;
+    for shard in msg.shards.iter() {
+        let path = block_dir.join(format!("shard_{}.json", shard.shard_id));
+        write_json(&path, shard).await?;
+    }
+    Ok(())
+}
+
+async fn write_json<T: serde::Serialize>(path: &Path, value: &T) -> Result<()> {
+    let content = serde_json::to_vec_pretty(value)?;
+    tokio::fs::write(path, content)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
@@ -0,0 +1,289 @@
+use std::path::PathBuf;
+
+use clap::{
+    Args,
+    Parser,
+    Subcommand,
+};
+
+use crate::{
+    HoldersFormat,
+    PriceSource,
+};
+
+/// Mintbase indexer, consuming NEAR blocks and writing NFT data to postgres.
+///
+/// All configuration can be supplied via environment variables (or a `.env`
+/// file). Flags take precedence over the environment.
+#[derive(Parser, Debug)]
+#[command(name = "minterop_indexer", version)]
+pub struct Cli {
+    #[command(flatten)]
+    pub overrides: ConfigOverrides,
+    /// Defaults to `run`
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Index blocks as configured (default)
+    Run,
+    /// Migrate the database to the most recent schema and exit
+    Migrate,
+    /// Index a range of blocks once, optionally only for some contracts
+    Replay {
+        /// First block height to index
+        #[arg(long)]
+        from: u64,
+        /// Last block height to index
+        #[arg(long)]
+        to: u64,
+        /// Comma-separated list of contracts to index
+        #[arg(long, value_delimiter = ',')]
+        contracts: Option<Vec<String>>,
+        /// Print a report of what would be written instead of writing it,
+        /// the database is only read from
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Index a range of blocks for some contracts into the live database,
+    /// next to a running indexer. Progress is kept per job, such that an
+    /// interrupted backfill resumes where it stopped.
+    Backfill {
+        /// Name of the job, reusing it resumes the job
+        #[arg(long)]
+        job: String,
+        /// First block height to index
+        #[arg(long)]
+        from: u64,
+        /// Last block height to index
+        #[arg(long)]
+        to: u64,
+        /// Comma-separated list of contracts to index
+        #[arg(long, value_delimiter = ',', required = true)]
+        contracts: Vec<String>,
+    },
+    /// Index a range of blocks for all contracts with several workers in
+    /// parallel, then fix ownership and listings that depend on the order of
+    /// blocks. Rerunning with the same arguments resumes each worker.
+    Reindex {
+        /// First block height to index
+        #[arg(long)]
+        from: u64,
+        /// Last block height to index
+        #[arg(long)]
+        to: u64,
+        /// Number of parts to split the range into
+        #[arg(long, default_value_t = 4)]
+        workers: u64,
+    },
+    /// Print the owners of all non-burned tokens of some contracts at a block
+    /// height, with the number of tokens each of them holds
+    Holders {
+        /// Block height, holders are taken after this block
+        #[arg(long)]
+        height: u64,
+        /// Comma-separated list of contracts
+        #[arg(long, value_delimiter = ',', required = true)]
+        contracts: Vec<String>,
+        #[arg(long, value_enum, default_value_t = HoldersFormat::Csv)]
+        format: HoldersFormat,
+    },
+    /// Print the earnings of an account within a period as CSV, with the role
+    /// in which they were earned
+    Statement {
+        #[arg(long)]
+        account: String,
+        /// First day of the period, e.g. `2022-01-01`
+        #[arg(long)]
+        from: chrono::NaiveDate,
+        /// Day after the period (exclusive)
+        #[arg(long)]
+        to: chrono::NaiveDate,
+        /// Print totals per currency and role instead of single earnings
+        #[arg(long)]
+        totals: bool,
+    },
+    /// Import USD prices of currencies, which are used to value earnings and
+    /// sales
+    Prices {
+        /// CSV file (`currency,timestamp,usd_price`), or an HTTP(S) URL
+        /// serving a JSON array of objects with these fields
+        #[arg(long)]
+        source: PriceSource,
+    },
+    /// Print the synced block height and the lag behind the chain head
+    Status,
+    /// Validate the configuration and exit
+    CheckConfig,
+    /// Write the blocks of a range that concern some contracts to a directory,
+    /// which can be read back via `BLOCKS_PATH`
+    Capture {
+        /// First block height to capture
+        #[arg(long)]
+        from: u64,
+        /// Last block height to capture
+        #[arg(long)]
+        to: u64,
+        /// Comma-separated list of contracts to capture
+        #[arg(long, value_delimiter = ',', required = true)]
+        contracts: Vec<String>,
+        /// Directory to write blocks to
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Compare the indexed tables against a golden snapshot file, after
+    /// optionally indexing a range of blocks (into an empty database)
+    Snapshot {
+        /// Snapshot file to compare against
+        #[arg(long)]
+        golden: PathBuf,
+        /// First block height to index
+        #[arg(long, requires = "to")]
+        from: Option<u64>,
+        /// Last block height to index
+        #[arg(long, requires = "from")]
+        to: Option<u64>,
+        /// Overwrite the golden file instead of comparing against it
+        #[arg(long)]
+        update: bool,
+    },
+}
+
+/// Overrides for the environment configuration. Each flag corresponds to the
+/// environment variable of the same name in upper snake case.
+#[derive(Args, Debug, Default, Clone)]
+pub struct ConfigOverrides {
+    /// TOML or YAML file with the lowest precedence
+    #[arg(long, global = true)]
+    pub config_file: Option<String>,
+    /// `mainnet` or `testnet`, fills in defaults for the values below
+    #[arg(long, global = true)]
+    pub network: Option<String>,
+    #[arg(long, global = true)]
+    pub start_block_height: Option<u64>,
+    #[arg(long, global = true)]
+    pub stop_block_height: Option<u64>,
+    #[arg(long, global = true)]
+    pub postgres: Option<String>,
+    /// Local directory (lake format) or JSONL file to read blocks from
+    #[arg(long, global = true)]
+    pub blocks_path: Option<String>,
+    /// NEAR RPC node to poll blocks from
+    #[arg(long, global = true)]
+    pub blocks_rpc_url: Option<String>,
+    /// `final` (default) or `optimistic`, the latter allows rolling back forks
+    #[arg(long, global = true)]
+    pub finality: Option<String>,
+    #[arg(long, global = true)]
+    pub s3_region_name: Option<String>,
+    #[arg(long, global = true)]
+    pub s3_bucket_name: Option<String>,
+    #[arg(long, global = true)]
+    pub rust_log: Option<String>,
+    #[arg(long, global = true)]
+    pub rpc_url: Option<String>,
+    #[arg(long, global = true)]
+    pub near_rpc_url: Option<String>,
+    #[arg(long, global = true)]
+    pub mintbase_root: Option<String>,
+    /// Comma-separated list of known market contracts
+    #[arg(long, global = true)]
+    pub market_ids: Option<String>,
+    #[arg(long, global = true)]
+    pub db_pool_size: Option<u32>,
+    /// Makes backfills buffer writes and flush them every this many blocks
+    #[arg(long, global = true)]
+    pub bulk_flush_blocks: Option<u64>,
+    /// Comma-separated list of contracts to index
+    #[arg(long, global = true)]
+    pub contract_filter: Option<String>,
+    /// Comma-separated list of contracts to ignore
+    #[arg(long, global = true)]
+    pub contract_denylist: Option<String>,
+    #[arg(long, global = true)]
+    pub paras_marketplace_id: Option<String>,
+    #[arg(long, global = true)]
+    pub otlp_endpoint: Option<String>,
+    #[arg(long, global = true)]
+    pub otlp_service_name: Option<String>,
+}
+
+impl ConfigOverrides {
+    /// Turns the set flags into (environment variable, value) pairs.
+    pub(crate) fn to_vars(&self) -> Vec<(String, String)> {
+        let vars: Vec<(&str, Option<String>)> = vec![
+            ("CONFIG_FILE", self.config_file.clone()),
+            ("NETWORK", self.network.clone()),
+            (
+                "START_BLOCK_HEIGHT",
+                self.start_block_height.map(|h| h.to_string()),
+            ),
+            (
+                "STOP_BLOCK_HEIGHT",
+                self.stop_block_height.map(|h| h.to_string()),
+            ),
+            ("POSTGRES", self.postgres.clone()),
+            ("BLOCKS_PATH", self.blocks_path.clone()),
+            ("BLOCKS_RPC_URL", self.blocks_rpc_url.clone()),
+            ("FINALITY", self.finality.clone()),
+            ("S3_REGION_NAME", self.s3_region_name.clone()),
+            ("S3_BUCKET_NAME", self.s3_bucket_name.clone()),
+            ("RUST_LOG", self.rust_log.clone()),
+            ("RPC_URL", self.rpc_url.clone()),
+            ("NEAR_RPC_URL", self.near_rpc_url.clone()),
+            ("MINTBASE_ROOT", self.mintbase_root.clone()),
+            ("MARKET_IDS", self.market_ids.clone()),
+            ("DB_POOL_SIZE", self.db_pool_size.map(|s| s.to_string())),
+            (
+                "BULK_FLUSH_BLOCKS",
+                self.bulk_flush_blocks.map(|n| n.to_string()),
+            ),
+            ("CONTRACT_FILTER", self.contract_filter.clone()),
+            ("CONTRACT_DENYLIST", self.contract_denylist.clone()),
+            ("PARAS_MARKETPLACE_ID", self.paras_marketplace_id.clone()),
+            ("OTLP_ENDPOINT", self.otlp_endpoint.clone()),
+            ("OTLP_SERVICE_NAME", self.otlp_service_name.clone()),
+        ];
+
+        vars.into_iter()
+            .filter_map(|(key, value)| value.map(|v| (key.to_string(), v)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_override_subcommand_position() {
+        let cli = Cli::parse_from([
+            "minterop_indexer",
+            "replay",
+            "--from",
+            "10",
+            "--to",
+            "20",
+            "--contracts",
+            "a.near,b.near",
+            "--postgres",
+            "postgres://localhost",
+        ]);
+
+        assert_eq!(
+            cli.overrides.to_vars(),
+            vec![("POSTGRES".to_string(), "postgres://localhost".to_string())]
+        );
+        match cli.command {
+            Some(Command::Replay {
+                from: 10,
+                to: 20,
+                contracts: Some(contracts),
+                dry_run: false,
+            }) => assert_eq!(contracts, vec!["a.near", "b.near"]),
+            c => panic!("Unexpected command: {:?}", c),
+        }
+    }
+}
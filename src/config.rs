@@ -1,44 +1,368 @@
-use anyhow::Result;
-use near_lake_framework::LakeConfigBuilder;
+mod file;
 
+use std::{
+    collections::HashMap,
+    path::{
+        Path,
+        PathBuf,
+    },
+    str::FromStr,
+};
+
+use anyhow::{
+    bail,
+    Context,
+    Result,
+};
+
+use self::file::FileConfig;
 use crate::{
+    block_source::{
+        BlockSource,
+        Finality,
+    },
+    cli::ConfigOverrides,
+    currencies::Currencies,
+    database::DbConnPool,
+    near_rpc::NearRpcClient,
+    network::Network,
     rpc_connection::MinteropRpcConnector,
     runtime::MintlakeRuntime,
 };
 
 #[derive(serde::Deserialize)]
 pub struct Config {
+    network: Option<Network>,
     start_block_height: u64,
     stop_block_height: Option<u64>,
     postgres: String,
+    #[serde(default)]
     s3_region_name: String,
+    #[serde(default)]
     s3_bucket_name: String,
     rust_log: Option<String>,
     rpc_url: String,
+    near_rpc_url: Option<String>,
+    #[serde(default)]
     mintbase_root: String,
+    #[serde(default)]
+    market_ids: Vec<String>,
     db_pool_size: Option<u32>,
     contract_filter: Option<String>,
+    #[serde(default)]
+    contract_denylist: Vec<String>,
+    #[serde(default)]
     paras_marketplace_id: String,
+    otlp_endpoint: Option<String>,
+    otlp_service_name: Option<String>,
+    config_file: Option<PathBuf>,
+    /// Local directory (lake format) or JSONL file to read blocks from,
+    /// instead of the lake bucket
+    blocks_path: Option<PathBuf>,
+    /// NEAR RPC node to poll blocks from, instead of the lake bucket
+    blocks_rpc_url: Option<String>,
+    /// `optimistic` journals all writes, such that forks can be rolled back
+    #[serde(default)]
+    finality: Finality,
+    /// Backfills buffer writes and flush them every this many blocks
+    bulk_flush_blocks: Option<u64>,
+    /// Only configurable via config file
+    #[serde(skip)]
+    market_versions: HashMap<String, Vec<String>>,
+    /// Only configurable via config file
+    #[serde(skip)]
+    currencies: Vec<crate::currencies::Currency>,
 }
 
 impl Config {
     /// Read environment variables and generate config.
     pub fn from_env() -> Result<Self> {
-        Ok(envy::from_env::<Self>()?)
+        Self::from_env_with(&ConfigOverrides::default())
+    }
+
+    /// Read environment variables, replace those for which a flag has been
+    /// given, and generate config. If `CONFIG_FILE` is set, its values are
+    /// used for anything that has been set neither in the environment nor
+    /// via flags.
+    pub fn from_env_with(overrides: &ConfigOverrides) -> Result<Self> {
+        let mut vars = std::env::vars().collect::<HashMap<_, _>>();
+        vars.extend(overrides.to_vars());
+
+        let file = match vars.get("CONFIG_FILE") {
+            Some(path) if !path.is_empty() => {
+                Some(FileConfig::load(Path::new(path))?)
+            }
+            _ => None,
+        };
+        if let Some(file) = file.as_ref() {
+            let mut file_vars = file.to_vars();
+            file_vars.extend(vars);
+            vars = file_vars;
+        }
+
+        let mut cfg = envy::from_iter::<_, Self>(vars)?;
+        if let Some(file) = file {
+            cfg.market_versions = file.market_versions();
+            cfg.currencies = file.currencies();
+        }
+        cfg.apply_network()
+    }
+
+    /// Fills in unset values from the `NETWORK` preset, and rejects values
+    /// that belong to the other network.
+    fn apply_network(mut self) -> Result<Self> {
+        if let Some(network) = self.network {
+            let preset = network.preset();
+            fill_default(&mut self.s3_bucket_name, preset.s3_bucket_name);
+            fill_default(&mut self.s3_region_name, preset.s3_region_name);
+            fill_default(&mut self.mintbase_root, preset.mintbase_root);
+            fill_default(
+                &mut self.paras_marketplace_id,
+                preset.paras_marketplace_id,
+            );
+            if self.market_ids.is_empty() {
+                self.market_ids =
+                    preset.market_ids.iter().map(|id| id.to_string()).collect();
+            }
+            if self.near_rpc_url.is_none() {
+                self.near_rpc_url = Some(preset.near_rpc_url.to_string());
+            }
+
+            network.check_resource("S3_BUCKET_NAME", &self.s3_bucket_name)?;
+            if let Some(near_rpc_url) = &self.near_rpc_url {
+                network.check_resource("NEAR_RPC_URL", near_rpc_url)?;
+            }
+            network.check_account_id("MINTBASE_ROOT", &self.mintbase_root)?;
+            network.check_account_id(
+                "PARAS_MARKETPLACE_ID",
+                &self.paras_marketplace_id,
+            )?;
+            for market_id in self.market_ids.iter() {
+                network.check_account_id("MARKET_IDS", market_id)?;
+            }
+            for contract_id in
+                self.contract_filter.iter().flat_map(|f| f.split(','))
+            {
+                network.check_account_id("CONTRACT_FILTER", contract_id)?;
+            }
+            for contract_id in self.contract_denylist.iter() {
+                network.check_account_id("CONTRACT_DENYLIST", contract_id)?;
+            }
+        }
+
+        for (name, value) in [
+            ("S3_BUCKET_NAME", &self.s3_bucket_name),
+            ("S3_REGION_NAME", &self.s3_region_name),
+            ("MINTBASE_ROOT", &self.mintbase_root),
+            ("PARAS_MARKETPLACE_ID", &self.paras_marketplace_id),
+        ] {
+            // the bucket is only needed when reading blocks from the lake
+            let is_lake_setting = name.starts_with("S3_");
+            if value.is_empty() && (!is_lake_setting || self.reads_from_lake())
+            {
+                bail!("{} needs to be set if NETWORK is not", name);
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Checks the config for values that would only fail at a later point,
+    /// e.g. when the first RPC call is made.
+    pub fn validate(&self) -> Result<()> {
+        hyper::Uri::from_str(&self.rpc_url)
+            .with_context(|| format!("Invalid RPC_URL: {}", self.rpc_url))?;
+        if let Some(near_rpc_url) = &self.near_rpc_url {
+            hyper::Uri::from_str(near_rpc_url).with_context(|| {
+                format!("Invalid NEAR_RPC_URL: {}", near_rpc_url)
+            })?;
+        }
+        if let Some(stop) = self.stop_block_height {
+            if stop != 0 && stop < self.start_block_height {
+                bail!(
+                    "STOP_BLOCK_HEIGHT ({}) is below START_BLOCK_HEIGHT ({})",
+                    stop,
+                    self.start_block_height
+                );
+            }
+        }
+        if self.db_pool_size == Some(0) {
+            bail!("DB_POOL_SIZE must be positive");
+        }
+        if self.bulk_flush_blocks == Some(0) {
+            bail!("BULK_FLUSH_BLOCKS must be positive");
+        }
+        if let Some(path) = &self.blocks_path {
+            if !path.exists() {
+                bail!("BLOCKS_PATH does not exist: {}", path.display());
+            }
+        }
+        if let Some(url) = &self.blocks_rpc_url {
+            if self.blocks_path.is_some() {
+                bail!("BLOCKS_PATH and BLOCKS_RPC_URL are mutually exclusive");
+            }
+            hyper::Uri::from_str(url)
+                .with_context(|| format!("Invalid BLOCKS_RPC_URL: {}", url))?;
+        }
+        if self.finality == Finality::Optimistic
+            && matches!(self.block_source(), BlockSource::Lake { .. })
+        {
+            bail!("FINALITY=optimistic requires a source other than the lake");
+        }
+
+        check_account_id("MINTBASE_ROOT", &self.mintbase_root)?;
+        check_account_id("PARAS_MARKETPLACE_ID", &self.paras_marketplace_id)?;
+        for market_id in self.market_ids.iter() {
+            check_account_id("MARKET_IDS", market_id)?;
+        }
+        for market_id in self.market_versions.keys() {
+            check_account_id("markets", market_id)?;
+        }
+        for contract_id in
+            self.contract_filter.iter().flat_map(|f| f.split(','))
+        {
+            check_account_id("CONTRACT_FILTER", contract_id)?;
+        }
+        for contract_id in self.contract_denylist.iter() {
+            check_account_id("CONTRACT_DENYLIST", contract_id)?;
+        }
+        Ok(())
+    }
+
+    /// Prints the non-secret parts of the config
+    pub fn print_summary(&self) {
+        println!("config_file:          {:?}", self.config_file);
+        println!("network:              {:?}", self.network);
+        println!("start_block_height:   {}", self.start_block_height);
+        println!("stop_block_height:    {:?}", self.stop_block_height);
+        println!("blocks_path:          {:?}", self.blocks_path);
+        println!("blocks_rpc_url:       {:?}", self.blocks_rpc_url);
+        println!("finality:             {:?}", self.finality);
+        println!("s3_bucket_name:       {}", self.s3_bucket_name);
+        println!("s3_region_name:       {}", self.s3_region_name);
+        println!("rpc_url:              {}", self.rpc_url);
+        println!("near_rpc_url:         {:?}", self.near_rpc_url);
+        println!("mintbase_root:        {}", self.mintbase_root);
+        println!("market_ids:           {:?}", self.market_ids);
+        println!("paras_marketplace_id: {}", self.paras_marketplace_id);
+        println!("market_versions:      {:?}", self.market_versions);
+        let currencies = self.currencies.iter().map(|c| &c.currency);
+        println!("currencies:           {:?}", currencies.collect::<Vec<_>>());
+        println!("contract_filter:      {:?}", self.contract_filter);
+        println!("contract_denylist:    {:?}", self.contract_denylist);
+        println!("db_pool_size:         {:?}", self.db_pool_size);
+        println!("bulk_flush_blocks:    {:?}", self.bulk_flush_blocks);
+        println!("otlp_endpoint:        {:?}", self.otlp_endpoint);
+    }
+
+    /// Returns the last block height that the indexer has synced and (if
+    /// `NEAR_RPC_URL` is configured) the current final height of the chain.
+    pub async fn sync_status(&self) -> Result<(u64, Option<u64>)> {
+        let synced_height =
+            crate::database::query_synced_height(&self.postgres)?;
+        let chain_height = match &self.near_rpc_url {
+            None => None,
+            Some(url) => {
+                Some(NearRpcClient::new(url)?.final_block_height().await?)
+            }
+        };
+        Ok((synced_height, chain_height))
+    }
+
+    /// Fixes state that depends on the order in which blocks have been
+    /// processed, for the tokens and contracts that the finished `jobs` of a
+    /// parallel reindex wrote activities of
+    pub fn reconcile(
+        &self,
+        jobs: &[crate::BackfillJob],
+    ) -> Result<crate::Reconciliation> {
+        crate::reindex::reconcile(&self.postgres, jobs)
+    }
+
+    /// Owners of the tokens of `contracts` at `height`
+    pub fn holders(
+        &self,
+        contracts: &[String],
+        height: u64,
+    ) -> Result<crate::HolderSnapshot> {
+        crate::HolderSnapshot::query(&self.postgres, contracts, height)
+    }
+
+    /// Earnings of an account from `from` (inclusive) to `to` (exclusive)
+    pub fn statement(
+        &self,
+        account_id: &str,
+        from: chrono::NaiveDate,
+        to: chrono::NaiveDate,
+    ) -> Result<crate::Statement> {
+        crate::Statement::query(&self.postgres, account_id, from, to)
+    }
+
+    /// Imports USD prices of currencies, and revalues the earnings and sales
+    /// they apply to
+    pub async fn import_prices(
+        &self,
+        source: &crate::PriceSource,
+    ) -> Result<crate::PriceImport> {
+        let points = source.fetch().await?;
+        crate::prices::import(&self.postgres, &source.to_string(), &points)
+    }
+
+    /// Canonical dump of the indexed tables, optionally only of the rows
+    /// written by blocks within `range`
+    pub fn snapshot(
+        &self,
+        range: Option<&std::ops::RangeInclusive<chrono::NaiveDateTime>>,
+    ) -> Result<crate::Snapshot> {
+        crate::Snapshot::dump(&self.postgres, range)
     }
 
     /// Initiates postgres connection
     pub fn get_runtime(&self) -> Result<MintlakeRuntime> {
+        self.new_runtime(true)
+    }
+
+    /// Connection pool of `DB_POOL_SIZE` connections. Without `journal`, none
+    /// of its writes end up in the journal of the live indexer.
+    pub(crate) fn db_pool(&self, journal: bool) -> DbConnPool {
+        crate::database::init_db_connection(
+            &self.postgres,
+            self.db_pool_size,
+            journal,
+        )
+    }
+
+    fn new_runtime(&self, journal: bool) -> Result<MintlakeRuntime> {
+        self.new_runtime_with(self.db_pool(journal))
+    }
+
+    fn new_runtime_with(
+        &self,
+        pg_connection: DbConnPool,
+    ) -> Result<MintlakeRuntime> {
         let minterop_rpc = MinteropRpcConnector::new(&self.rpc_url)?;
+        let near_rpc = self
+            .near_rpc_url
+            .as_deref()
+            .map(NearRpcClient::new)
+            .transpose()?;
         Ok(MintlakeRuntime {
             stop_block_height: self.stop_block_height,
-            pg_connection: crate::database::init_db_connection(
-                &self.postgres,
-                self.db_pool_size,
-            ),
+            pg_connection,
             minterop_rpc,
             mintbase_root: self.mintbase_root.clone(),
             paras_marketplace_id: self.paras_marketplace_id.clone(),
+            market_ids: self.market_ids.clone(),
+            market_versions: self.market_versions.clone(),
+            contract_denylist: self.contract_denylist.clone(),
+            finality: self.finality,
+            last_block: Default::default(),
+            replayed: Default::default(),
+            cursor: Default::default(),
+            bulk: None,
+            dry_run: None,
+            stats: Default::default(),
+            currencies: Currencies::new(near_rpc),
             contract_filter: self
                 .contract_filter
                 .clone()
@@ -46,20 +370,141 @@ impl Config {
         })
     }
 
-    /// Initiate streaming of blocks from S3
-    pub fn connect_s3(&self) -> (crate::LakeHandle, crate::LakeStreamer) {
-        // FIXME: use mainnet/testnet var instead of manual region/bucket name
-        let lake_config = LakeConfigBuilder::default()
-            .s3_bucket_name(self.s3_bucket_name.clone())
-            .s3_region_name(self.s3_region_name.clone())
-            .start_block_height(self.start_block_height)
-            .build()
-            .unwrap();
-        near_lake_framework::streamer(lake_config)
+    /// Creates a backfill job, or loads it if it already exists
+    pub fn register_backfill(
+        &self,
+        job_id: &str,
+        contracts: &[String],
+        start_height: u64,
+        stop_height: u64,
+    ) -> Result<crate::BackfillJob> {
+        crate::BackfillJob::register(
+            &self.postgres,
+            job_id,
+            contracts,
+            start_height,
+            stop_height,
+        )
+    }
+
+    pub fn backfill_job(&self, job_id: &str) -> Result<crate::BackfillJob> {
+        crate::BackfillJob::load(&self.postgres, job_id)
+    }
+
+    /// Runtime for a backfill job, which only indexes the contracts of the job
+    /// (if any) up to its stop height and persists its progress in the row of
+    /// the job. With `BULK_FLUSH_BLOCKS`, writes are buffered and flushed in
+    /// bulk.
+    pub fn get_backfill_runtime(
+        &self,
+        job: &crate::BackfillJob,
+    ) -> Result<MintlakeRuntime> {
+        self.backfill_runtime_with(job, self.db_pool(false))
     }
 
-    /// Initializes logging from the filters defined via `RUST_LOG`
+    /// Same as `get_backfill_runtime`, on a pool that may be shared with the
+    /// runtimes of other jobs
+    pub(crate) fn backfill_runtime_with(
+        &self,
+        job: &crate::BackfillJob,
+        pg_connection: DbConnPool,
+    ) -> Result<MintlakeRuntime> {
+        // optimistic blocks would need to be journaled, which is done for the
+        // live indexer only
+        if self.finality != Finality::Final {
+            bail!("Backfills can only process final blocks");
+        }
+        let mut rt = self.new_runtime_with(pg_connection)?;
+        rt.stop_block_height = Some(job.stop_height);
+        rt.contract_filter =
+            (!job.contracts.is_empty()).then(|| job.contracts.clone());
+        rt.cursor = crate::runtime::Cursor::Backfill(job.job_id.clone());
+        rt.bulk = self.bulk_flush_blocks.map(|flush_blocks| {
+            crate::bulk::BulkMode::new(&self.postgres, flush_blocks)
+        });
+        Ok(rt)
+    }
+
+    /// Runtime for replaying the configured range, which persists no progress
+    /// and writes nothing to the journal of the live indexer
+    pub fn get_replay_runtime(&self) -> Result<MintlakeRuntime> {
+        // optimistic blocks would need to be journaled, which is done for the
+        // live indexer only
+        if self.finality != Finality::Final {
+            bail!("Replays can only process final blocks");
+        }
+        let mut rt = self.new_runtime(false)?;
+        rt.cursor = crate::runtime::Cursor::Replay;
+        Ok(rt)
+    }
+
+    /// Runtime that records what it would write to the returned `DryRun`,
+    /// instead of writing to the database or sending RPC messages. The
+    /// database is only read from, e.g. to look up listings.
+    pub fn get_dry_run_runtime(
+        &self,
+    ) -> Result<(MintlakeRuntime, crate::DryRun)> {
+        // forks could only be handled by rolling back writes
+        if self.finality != Finality::Final {
+            bail!("Dry runs can only process final blocks");
+        }
+        let dry_run = crate::DryRun::default();
+        let mut rt = self.get_runtime()?;
+        rt.minterop_rpc = rt.minterop_rpc.with_dry_run(dry_run.clone());
+        rt.dry_run = Some(dry_run.clone());
+        Ok((rt, dry_run))
+    }
+
+    /// The configured block source, which is S3 unless `BLOCKS_PATH` or
+    /// `BLOCKS_RPC_URL` is set
+    pub fn block_source(&self) -> BlockSource {
+        match (&self.blocks_path, &self.blocks_rpc_url) {
+            (Some(path), _) => BlockSource::from_path(path.clone()),
+            (None, Some(url)) => BlockSource::Rpc {
+                url: url.clone(),
+                finality: self.finality,
+                accounts: self.rpc_accounts(),
+            },
+            (None, None) => BlockSource::Lake {
+                s3_bucket_name: self.s3_bucket_name.clone(),
+                s3_region_name: self.s3_region_name.clone(),
+            },
+        }
+    }
+
+    /// Accounts whose transactions the RPC block source collects outcomes
+    /// for: The filtered contracts, or all mintbase stores without a filter,
+    /// and the markets.
+    fn rpc_accounts(&self) -> Vec<String> {
+        let mut accounts = match &self.contract_filter {
+            Some(filter) => filter.split(',').map(|c| c.to_string()).collect(),
+            None => vec![self.mintbase_root.clone()],
+        };
+        accounts.extend(self.market_ids.iter().cloned());
+        accounts.push(self.paras_marketplace_id.clone());
+        accounts.retain(|account| !account.is_empty());
+        accounts
+    }
+
+    fn reads_from_lake(&self) -> bool {
+        self.blocks_path.is_none() && self.blocks_rpc_url.is_none()
+    }
+
+    /// Initiate streaming of blocks from the configured block source
+    pub fn connect_blocks(&self) -> (crate::LakeHandle, crate::LakeStreamer) {
+        self.block_source()
+            .stream(self.start_block_height, self.stop_block_height)
+    }
+
+    /// Initializes logging from the filters defined via `RUST_LOG`. If
+    /// `OTLP_ENDPOINT` is set, spans will additionally be exported via OTLP.
     pub fn init_logging(&self) -> Result<()> {
+        use tracing_subscriber::{
+            layer::SubscriberExt,
+            util::SubscriberInitExt,
+            Layer,
+        };
+
         let mut env_filter = tracing_subscriber::EnvFilter::new("");
 
         if let Some(rust_log) = &self.rust_log {
@@ -70,20 +515,119 @@ impl Config {
             }
         }
 
-        tracing_subscriber::fmt::Subscriber::builder()
-            .with_env_filter(env_filter)
+        let fmt_layer = tracing_subscriber::fmt::layer()
             .with_writer(std::io::stdout)
-            .init();
+            .with_filter(env_filter);
+
+        // Exported spans should not depend on `RUST_LOG`, so they get their
+        // own filter
+        let otlp_layer = match self.otlp_endpoint.as_deref() {
+            None | Some("") => None,
+            Some(endpoint) => {
+                let tracer = crate::telemetry::init_tracer(
+                    endpoint,
+                    self.otlp_service_name.as_deref(),
+                )?;
+                let filter = tracing_subscriber::filter::Targets::new()
+                    .with_target("minterop", tracing::Level::INFO);
+                Some(
+                    tracing_opentelemetry::layer()
+                        .with_tracer(tracer)
+                        .with_filter(filter),
+                )
+            }
+        };
+
+        tracing_subscriber::registry()
+            .with(fmt_layer)
+            .with(otlp_layer)
+            .try_init()?;
 
         Ok(())
     }
 
-    /// Migrates the database to the most recent schema
+    /// Migrates the database to the most recent schema, and registers the
+    /// currencies of the config file
     pub fn migrate_db(&self) -> Result<()> {
-        minterop_data::run_migrations(&self.postgres)
+        minterop_data::run_migrations(&self.postgres)?;
+        crate::database::run_local_migrations(&self.postgres)?;
+        crate::currencies::register_configured(&self.postgres, &self.currencies)
+    }
+}
+
+fn check_account_id(field: &str, id: &str) -> Result<()> {
+    near_sdk::AccountId::from_str(id).map_err(|e| {
+        anyhow::anyhow!("{} contains invalid account ID {:?}: {}", field, id, e)
+    })?;
+    Ok(())
+}
+
+fn fill_default(value: &mut String, default: &str) {
+    if value.is_empty() {
+        *value = default.to_string();
     }
 }
 
 fn parse_directive(s: &str) -> Result<tracing_subscriber::filter::Directive> {
     Ok(s.parse()?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_from(vars: &[(&str, &str)]) -> Result<Config> {
+        let base = [
+            ("START_BLOCK_HEIGHT", "0"),
+            ("POSTGRES", "postgres://localhost"),
+            ("RPC_URL", "http://localhost:3000/publish"),
+        ];
+        let vars = base
+            .iter()
+            .chain(vars.iter())
+            .map(|(k, v)| (k.to_string(), v.to_string()));
+        envy::from_iter::<_, Config>(vars)?.apply_network()
+    }
+
+    #[test]
+    fn test_network_fills_defaults() {
+        let cfg = config_from(&[("NETWORK", "testnet")]).unwrap();
+        assert_eq!(cfg.s3_bucket_name, "near-lake-data-testnet");
+        assert_eq!(cfg.mintbase_root, "mintspace2.testnet");
+        assert_eq!(
+            cfg.near_rpc_url.as_deref(),
+            Some("https://rpc.testnet.near.org")
+        );
+        assert!(!cfg.market_ids.is_empty());
+    }
+
+    #[test]
+    fn test_network_keeps_overrides() {
+        let cfg = config_from(&[
+            ("NETWORK", "mainnet"),
+            ("MINTBASE_ROOT", "custom.near"),
+        ])
+        .unwrap();
+        assert_eq!(cfg.mintbase_root, "custom.near");
+        assert_eq!(cfg.paras_marketplace_id, "marketplace.paras.near");
+    }
+
+    #[test]
+    fn test_network_rejects_mismatches() {
+        assert!(config_from(&[
+            ("NETWORK", "mainnet"),
+            ("MINTBASE_ROOT", "mintspace2.testnet"),
+        ])
+        .is_err());
+        assert!(config_from(&[
+            ("NETWORK", "testnet"),
+            ("S3_BUCKET_NAME", "near-lake-data-mainnet"),
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_no_network_requires_values() {
+        assert!(config_from(&[]).is_err());
+    }
+}
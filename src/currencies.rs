@@ -0,0 +1,225 @@
+use std::{
+    collections::HashSet,
+    str::FromStr,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use diesel::{
+    Connection,
+    ExpressionMethods,
+    PgConnection,
+    QueryDsl,
+    RunQueryDsl,
+};
+use tracing::Instrument;
+
+use crate::{
+    database::DbConnPool,
+    near_rpc::NearRpcClient,
+    runtime::TxProcessingRuntime,
+    schema::currencies,
+};
+
+/// Prefix of currencies that are fungible tokens, followed by the FT contract
+const FT_PREFIX: &str = "ft::";
+
+/// Row of `currencies`, which the database uses to normalize amounts
+#[derive(Debug, Clone, PartialEq, Eq, Insertable)]
+#[table_name = "currencies"]
+pub(crate) struct Currency {
+    pub(crate) currency: String,
+    pub(crate) symbol: String,
+    pub(crate) decimals: i32,
+    pub(crate) source: String,
+}
+
+/// Currencies that are known to be registered, or whose metadata has already
+/// been requested. Unknown FT currencies are registered from the
+/// `ft_metadata` of their contract.
+#[derive(Clone)]
+pub(crate) struct Currencies {
+    known: Arc<Mutex<HashSet<String>>>,
+    near_rpc: Option<NearRpcClient>,
+}
+
+impl Currencies {
+    pub(crate) fn new(near_rpc: Option<NearRpcClient>) -> Self {
+        Self {
+            known: Default::default(),
+            near_rpc,
+        }
+    }
+
+    /// Marks a currency as known, returning whether it was unknown before
+    fn insert(&self, currency: &str) -> bool {
+        self.lock().insert(currency.to_string())
+    }
+
+    fn remove(&self, currency: &str) {
+        self.lock().remove(currency);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashSet<String>> {
+        // nothing panics while holding the lock
+        self.known.lock().expect("Known currencies are poisoned")
+    }
+}
+
+/// Makes sure that an FT currency is registered, such that amounts in it can
+/// be normalized. Rows written before the registration are normalized by the
+/// database once it happens, so this is dispatched in the background.
+pub(crate) fn ensure_currency(rt: &TxProcessingRuntime, currency: &str) {
+    let ft_contract_id = match currency.strip_prefix(FT_PREFIX) {
+        Some(ft_contract_id) => ft_contract_id.to_string(),
+        // NEAR is built in
+        None => return,
+    };
+    if rt.dry_run.is_some() || !rt.currencies.insert(currency) {
+        return;
+    }
+    let near_rpc = match rt.currencies.near_rpc.clone() {
+        Some(near_rpc) => near_rpc,
+        None => {
+            crate::warn!(
+                "Cannot register {} without NEAR_RPC_URL, its amounts won't \
+                 be normalized",
+                currency
+            );
+            return;
+        }
+    };
+
+    let currencies = rt.currencies.clone();
+    let db = rt.pg_connection.clone();
+    let currency = currency.to_string();
+    rt.minterop_rpc.dispatch(
+        async move {
+            if let Err(e) =
+                register_ft(&db, &near_rpc, &currency, &ft_contract_id).await
+            {
+                crate::warn!("Failed to register {}: {:?}", currency, e);
+                // retried on the next occurrence
+                currencies.remove(&currency);
+            }
+        }
+        .in_current_span(),
+    );
+}
+
+#[tracing::instrument(target = "minterop", skip(db, near_rpc))]
+async fn register_ft(
+    db: &DbConnPool,
+    near_rpc: &NearRpcClient,
+    currency: &str,
+    ft_contract_id: &str,
+) -> Result<()> {
+    use actix_diesel::dsl::AsyncRunQueryDsl;
+
+    use crate::schema::currencies::dsl;
+
+    // registered by an earlier run or via the config file
+    let registered = dsl::currencies
+        .filter(dsl::currency.eq(currency.to_string()))
+        .count()
+        .get_result_async::<i64>(db)
+        .await
+        .context("Failed to query currency")?;
+    if registered > 0 {
+        return Ok(());
+    }
+
+    let metadata = near_rpc.ft_metadata(ft_contract_id).await?;
+    diesel::insert_into(currencies::table)
+        .values(Currency {
+            currency: currency.to_string(),
+            symbol: metadata.symbol,
+            decimals: metadata.decimals as i32,
+            source: "ft_metadata".to_string(),
+        })
+        .on_conflict_do_nothing()
+        .execute_async(db)
+        .await
+        .context("Failed to insert currency")?;
+    crate::info!("Registered {} ({} decimals)", currency, metadata.decimals);
+    Ok(())
+}
+
+/// Upserts the currencies from the config file, which take precedence over
+/// FT metadata. Unchanged currencies are left alone, as this runs on every
+/// start and changed decimals renormalize all amounts in the currency.
+pub(crate) fn register_configured(
+    pg_string: &str,
+    configured: &[Currency],
+) -> Result<()> {
+    use diesel::sql_types::{
+        Integer,
+        Text,
+    };
+
+    if configured.is_empty() {
+        return Ok(());
+    }
+    let conn = PgConnection::establish(pg_string)?;
+    conn.transaction(|| {
+        for currency in configured {
+            diesel::sql_query(
+                "INSERT INTO currencies (currency, symbol, decimals, source) \
+                 VALUES ($1, $2, $3, $4) \
+                 ON CONFLICT (currency) DO UPDATE \
+                 SET symbol = excluded.symbol, \
+                 decimals = excluded.decimals, \
+                 source = excluded.source, \
+                 updated_at = now() \
+                 WHERE currencies.decimals IS DISTINCT FROM excluded.decimals \
+                 OR (currencies.symbol, currencies.source) \
+                 IS DISTINCT FROM (excluded.symbol, excluded.source)",
+            )
+            .bind::<Text, _>(&currency.currency)
+            .bind::<Text, _>(&currency.symbol)
+            .bind::<Integer, _>(currency.decimals)
+            .bind::<Text, _>(&currency.source)
+            .execute(&conn)
+            .with_context(|| {
+                format!("Failed to register currency {}", currency.currency)
+            })?;
+        }
+        Ok(())
+    })
+}
+
+/// Currencies are either `near` or an FT contract prefixed with `ft::`
+pub(crate) fn is_valid_currency(currency: &str) -> bool {
+    currency == "near"
+        || currency
+            .strip_prefix(FT_PREFIX)
+            .map_or(false, |id| near_sdk::AccountId::from_str(id).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_currency() {
+        assert!(is_valid_currency("near"));
+        assert!(is_valid_currency("ft::usdc.near"));
+        assert!(!is_valid_currency("usdc.near"));
+        assert!(!is_valid_currency("ft::"));
+    }
+
+    #[test]
+    fn test_known_currencies() {
+        let currencies = Currencies::new(None);
+        assert!(currencies.insert("ft::usdc.near"));
+        assert!(!currencies.insert("ft::usdc.near"));
+        currencies.remove("ft::usdc.near");
+        assert!(currencies.insert("ft::usdc.near"));
+    }
+}
@@ -1,3 +1,7 @@
+use tracing::Instrument;
+
+use crate::runtime::TxProcessingRuntime;
+
 const DEFAULT_DB_POOL_SIZE: u32 = 50;
 
 // ------------------------------ actix_diesel ------------------------------ //
@@ -5,27 +9,48 @@ pub(crate) type DbConnPool = actix_diesel::Database<diesel::PgConnection>;
 
 // timeouts and lifetimes?
 // https://docs.rs/actix-diesel/0.3.0/actix_diesel/struct.Builder.html
+/// With `journal`, writes of the pool are journaled while a block is being
+/// journaled, see `begin_journaled_block`.
 pub(crate) fn init_db_connection(
     pg_string: &str,
     db_pool_size: Option<u32>,
+    journal: bool,
 ) -> DbConnPool {
+    let pg_string = if journal {
+        with_journal_option(pg_string)
+    } else {
+        pg_string.to_string()
+    };
     actix_diesel::Database::builder()
         .pool_max_size(db_pool_size.unwrap_or(DEFAULT_DB_POOL_SIZE))
-        .open(pg_string)
+        .open(&pg_string)
+}
+
+/// Turns on `minterop.journal` for every session of the connection string,
+/// either a URL or `key=value` pairs.
+fn with_journal_option(pg_string: &str) -> String {
+    if !pg_string.contains("://") {
+        return format!("{} options='-c minterop.journal=on'", pg_string);
+    }
+    let separator = if pg_string.contains('?') { '&' } else { '?' };
+    format!(
+        "{}{}options=-c%20minterop.journal%3Don",
+        pg_string, separator
+    )
 }
 
 #[async_trait::async_trait]
 pub(crate) trait ExecuteDb {
     async fn execute_db(
         self,
-        db: &DbConnPool,
+        rt: &TxProcessingRuntime,
         tx: &crate::runtime::ReceiptData,
         msg: &str,
     );
 
     async fn execute_db_action(
         self,
-        db: &DbConnPool,
+        rt: &TxProcessingRuntime,
         receipt_id: &str,
         msg: &str,
     );
@@ -36,26 +61,51 @@ impl<Q> ExecuteDb for Q
 where
     Q: actix_diesel::dsl::AsyncRunQueryDsl<diesel::PgConnection>
         + diesel::query_dsl::load_dsl::ExecuteDsl<diesel::PgConnection>
+        + diesel::query_builder::QueryFragment<diesel::pg::Pg>
         + Send,
 {
     async fn execute_db(
         self,
-        db: &DbConnPool,
+        rt: &TxProcessingRuntime,
         tx: &crate::runtime::ReceiptData,
         msg: &str,
     ) {
-        if let Err(e) = self.execute_async(db).await {
+        if let Some(dry_run) = &rt.dry_run {
+            dry_run.record_statement(msg, debug_sql(&self));
+            return;
+        }
+        let span = tracing::info_span!(
+            target: "minterop",
+            "execute_db",
+            msg,
+            receipt_id = %tx.id
+        );
+        if let Err(e) =
+            self.execute_async(&rt.pg_connection).instrument(span).await
+        {
             crate::error!("Failed to {}: {} ({:?})", msg, e, tx);
         }
     }
 
     async fn execute_db_action(
         self,
-        db: &DbConnPool,
+        rt: &TxProcessingRuntime,
         receipt_id: &str,
         msg: &str,
     ) {
-        if let Err(e) = self.execute_async(db).await {
+        if let Some(dry_run) = &rt.dry_run {
+            dry_run.record_statement(msg, debug_sql(&self));
+            return;
+        }
+        let span = tracing::info_span!(
+            target: "minterop",
+            "execute_db",
+            msg,
+            receipt_id
+        );
+        if let Err(e) =
+            self.execute_async(&rt.pg_connection).instrument(span).await
+        {
             crate::error!(
                 "Failed to {}:, {} (receipt_id: {})",
                 msg,
@@ -66,6 +116,15 @@ where
     }
 }
 
+/// SQL of a statement with its bind values, as recorded by dry runs
+fn debug_sql<Q>(query: &Q) -> String
+where
+    Q: diesel::query_builder::QueryFragment<diesel::pg::Pg>,
+{
+    diesel::debug_query::<diesel::pg::Pg, _>(query).to_string()
+}
+
+#[tracing::instrument(target = "minterop", skip(db))]
 pub(crate) async fn query_metadata_id(
     nft_contract_id: String,
     token_id: String,
@@ -98,6 +157,7 @@ pub(crate) async fn query_metadata_id(
     }
 }
 
+#[tracing::instrument(target = "minterop", skip(db))]
 pub(crate) async fn query_lister_currency(
     nft_contract_id: String,
     token_id: String,
@@ -136,6 +196,7 @@ pub(crate) async fn query_lister_currency(
     }
 }
 
+#[tracing::instrument(target = "minterop", skip(db))]
 pub(crate) async fn query_offerer(
     nft_contract_id: String,
     token_id: String,
@@ -176,6 +237,7 @@ pub(crate) async fn query_offerer(
     }
 }
 
+#[tracing::instrument(target = "minterop", skip(db))]
 pub(crate) async fn query_lister_currency_offerer(
     nft_contract_id: String,
     token_id: String,
@@ -205,3 +267,208 @@ pub(crate) async fn query_lister_currency_offerer(
 
     (lister_currency, offerer)
 }
+
+/// Synchronous query for the last synced block height, e.g. for health
+/// checks that don't require a whole runtime.
+pub(crate) fn query_synced_height(pg_string: &str) -> anyhow::Result<u64> {
+    use diesel::{
+        Connection,
+        QueryDsl,
+        RunQueryDsl,
+    };
+    use minterop_data::schema::blocks::dsl;
+
+    let conn = diesel::PgConnection::establish(pg_string)?;
+    let height = dsl::blocks.select(dsl::synced_height).first::<i64>(&conn)?;
+    Ok(height as u64)
+}
+
+// ------------------------------ fork handling ----------------------------- //
+/// Blocks whose journal entries are kept when following optimistic heads.
+/// Forks deeper than this cannot be rolled back.
+const JOURNAL_DEPTH: u64 = 100;
+
+embed_migrations!("migrations");
+
+/// Runs the migrations of this crate, which need to be applied after those of
+/// `minterop_data`. The migrations know the kind of sale activities, which
+/// is checked against the one of `minterop_data`.
+pub(crate) fn run_local_migrations(pg_string: &str) -> anyhow::Result<()> {
+    use diesel::{
+        Connection,
+        RunQueryDsl,
+    };
+
+    let conn = diesel::PgConnection::establish(pg_string)?;
+    embedded_migrations::run(&conn)?;
+
+    let sale_kind = diesel::select(crate::schema::nft_sale_kind)
+        .get_result::<String>(&conn)?;
+    anyhow::ensure!(
+        sale_kind == minterop_data::db_rows::NFT_ACTIVITY_KIND_SOLD,
+        "Migrations count {:?} activities as sales instead of {:?}",
+        sale_kind,
+        minterop_data::db_rows::NFT_ACTIVITY_KIND_SOLD
+    );
+    Ok(())
+}
+
+/// Height and hash of the last synced block
+#[tracing::instrument(target = "minterop", skip(db))]
+pub(crate) async fn query_synced_block(
+    db: &DbConnPool,
+) -> anyhow::Result<Option<(u64, String)>> {
+    use actix_diesel::dsl::AsyncRunQueryDsl;
+    use diesel::QueryDsl;
+
+    use crate::schema::blocks::dsl;
+
+    let synced = dsl::blocks
+        .select((dsl::synced_height, dsl::synced_hash))
+        .limit(1)
+        .get_results_async::<(i64, Option<String>)>(db)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to query synced block: {}", e))?;
+    Ok(synced
+        .into_iter()
+        .find_map(|(height, hash)| hash.map(|hash| (height as u64, hash))))
+}
+
+/// Hash of a journaled block, if it is still known
+#[tracing::instrument(target = "minterop", skip(db))]
+pub(crate) async fn query_block_hash(
+    db: &DbConnPool,
+    height: u64,
+) -> Option<String> {
+    use actix_diesel::dsl::AsyncRunQueryDsl;
+    use diesel::{
+        ExpressionMethods,
+        QueryDsl,
+    };
+
+    use crate::schema::block_hashes::dsl;
+
+    match dsl::block_hashes
+        .filter(dsl::height.eq(height as i64))
+        .select(dsl::hash)
+        .get_results_async::<String>(db)
+        .await
+    {
+        Err(e) => {
+            crate::error!("Failed to query block hash: {}", e);
+            None
+        }
+        Ok(hashes) => hashes.into_iter().next(),
+    }
+}
+
+/// Tags all following writes with `height` in the journal, such that they can
+/// be rolled back if the block gets orphaned. Also drops journal entries that
+/// have become too old to matter.
+#[tracing::instrument(target = "minterop", skip(db))]
+pub(crate) async fn begin_journaled_block(
+    db: &DbConnPool,
+    height: u64,
+    hash: String,
+) {
+    use actix_diesel::dsl::AsyncRunQueryDsl;
+    use diesel::ExpressionMethods;
+
+    use crate::{
+        logging::HandleErr,
+        schema::{
+            block_hashes,
+            block_journal,
+            blocks,
+        },
+    };
+
+    let prune_height = height.saturating_sub(JOURNAL_DEPTH) as i64;
+    let (pruned_journal, pruned_hashes, inserted_hash) =
+        futures::future::join3(
+            diesel::delete(block_journal::table)
+                .filter(block_journal::dsl::height.lt(prune_height))
+                .execute_async(db),
+            diesel::delete(block_hashes::table)
+                .filter(block_hashes::dsl::height.lt(prune_height))
+                .execute_async(db),
+            diesel::insert_into(block_hashes::table)
+                .values((
+                    block_hashes::dsl::height.eq(height as i64),
+                    block_hashes::dsl::hash.eq(hash),
+                ))
+                .on_conflict(block_hashes::dsl::height)
+                .do_nothing()
+                .execute_async(db),
+        )
+        .await;
+    pruned_journal
+        .and(pruned_hashes)
+        .and(inserted_hash)
+        .handle_err(|e| crate::error!("Failed to update block journal: {}", e));
+
+    diesel::update(blocks::table)
+        .set(blocks::dsl::journal_height.eq(Some(height as i64)))
+        .execute_async(db)
+        .await
+        .handle_err(|e| {
+            crate::error!("Failed to start journal for {}: {}", height, e)
+        });
+}
+
+/// Stops journaling writes, e.g. when only processing final blocks.
+pub(crate) async fn disable_journal(db: &DbConnPool) {
+    use actix_diesel::dsl::AsyncRunQueryDsl;
+    use diesel::ExpressionMethods;
+
+    use crate::{
+        logging::HandleErr,
+        schema::blocks::dsl,
+    };
+
+    diesel::update(dsl::blocks)
+        .set(dsl::journal_height.eq(None::<i64>))
+        .execute_async(db)
+        .await
+        .handle_err(|e| crate::error!("Failed to disable journal: {}", e));
+}
+
+/// Reverts all writes of blocks above `height` and resets the cursor to it.
+#[tracing::instrument(target = "minterop", skip(db))]
+pub(crate) async fn rollback_to_height(
+    db: &DbConnPool,
+    height: u64,
+) -> anyhow::Result<u64> {
+    use actix_diesel::dsl::AsyncRunQueryDsl;
+
+    let reverted =
+        diesel::select(crate::schema::rollback_to_height(height as i64))
+            .get_result_async::<i64>(db)
+            .await
+            .map_err(|e| {
+                anyhow::anyhow!("Failed to roll back to {}: {}", height, e)
+            })?;
+    Ok(reverted as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_journal_option() {
+        assert_eq!(
+            with_journal_option("postgres://u:p@localhost/db"),
+            "postgres://u:p@localhost/db?options=-c%20minterop.journal%3Don"
+        );
+        assert_eq!(
+            with_journal_option("postgres://localhost/db?sslmode=disable"),
+            "postgres://localhost/db?sslmode=disable\
+             &options=-c%20minterop.journal%3Don"
+        );
+        assert_eq!(
+            with_journal_option("host=localhost dbname=db"),
+            "host=localhost dbname=db options='-c minterop.journal=on'"
+        );
+    }
+}
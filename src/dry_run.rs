@@ -0,0 +1,113 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+/// Samples kept per statement or RPC method
+const MAX_SAMPLES: usize = 3;
+
+/// What indexing would have written, as recorded by a dry run
+#[derive(Debug, Default, Clone, serde::Serialize)]
+pub struct DryRunReport {
+    pub blocks: u64,
+    /// Handled events by `standard/version/event`
+    pub events: BTreeMap<String, u64>,
+    /// Database writes by the description the handlers give them, with
+    /// samples of the SQL and its bind values
+    pub statements: BTreeMap<String, Samples>,
+    /// Messages to the minterop RPC by method, with samples of the payloads
+    pub rpc_messages: BTreeMap<String, Samples>,
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize)]
+pub struct Samples {
+    pub count: u64,
+    pub samples: Vec<serde_json::Value>,
+}
+
+impl Samples {
+    fn record(&mut self, sample: serde_json::Value) {
+        self.count += 1;
+        if self.samples.len() < MAX_SAMPLES {
+            self.samples.push(sample);
+        }
+    }
+}
+
+/// Recorder shared by the runtime, its handlers and the RPC connector during
+/// a dry run, which replaces all writes and RPC messages
+#[derive(Debug, Clone, Default)]
+pub struct DryRun(Arc<Mutex<DryRunReport>>);
+
+impl DryRun {
+    /// The report as recorded so far
+    pub fn report(&self) -> DryRunReport {
+        self.lock().clone()
+    }
+
+    pub(crate) fn record_block(&self) {
+        self.lock().blocks += 1;
+    }
+
+    pub(crate) fn record_event(
+        &self,
+        standard: &str,
+        version: &str,
+        event: &str,
+    ) {
+        let key = format!("{}/{}/{}", standard, version, event);
+        *self.lock().events.entry(key).or_default() += 1;
+    }
+
+    pub(crate) fn record_statement(&self, msg: &str, sql: String) {
+        self.lock()
+            .statements
+            .entry(msg.to_string())
+            .or_default()
+            .record(serde_json::Value::String(sql));
+    }
+
+    pub(crate) fn record_rpc<T: serde::Serialize>(
+        &self,
+        method: &str,
+        payload: &T,
+    ) {
+        let payload = serde_json::to_value(payload)
+            .unwrap_or_else(|e| serde_json::Value::String(e.to_string()));
+        self.lock()
+            .rpc_messages
+            .entry(method.to_string())
+            .or_default()
+            .record(payload);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, DryRunReport> {
+        // recording never panics while holding the lock
+        self.0.lock().expect("Dry run report is poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_caps_samples() {
+        let dry_run = DryRun::default();
+        for i in 0..5 {
+            dry_run.record_statement("insert activity", format!("sql {}", i));
+        }
+        dry_run.record_event("nep171", "1.0.0", "nft_mint");
+        dry_run.record_event("nep171", "1.0.0", "nft_mint");
+
+        let report = dry_run.report();
+        let statements = &report.statements["insert activity"];
+        assert_eq!(statements.count, 5);
+        assert_eq!(statements.samples.len(), MAX_SAMPLES);
+        assert_eq!(statements.samples[0], "sql 0");
+        assert_eq!(report.events["nep171/1.0.0/nft_mint"], 2);
+    }
+}
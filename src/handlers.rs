@@ -11,8 +11,20 @@ pub(crate) mod prelude {
     };
 
     pub(crate) use crate::{
+        bulk::{
+            insert_activities,
+            insert_earnings,
+            insert_ownership_changes,
+            insert_split_changes,
+            TokenWrite,
+        },
+        currencies::ensure_currency,
         database::ExecuteDb,
         error,
+        handlers::{
+            ownership_changes,
+            split_changes,
+        },
         runtime::TxProcessingRuntime,
         ReceiptData,
     };
@@ -31,6 +43,11 @@ pub mod market_v01;
 pub mod market_v02;
 pub mod paras;
 
+use crate::schema::{
+    nft_ownership_changes,
+    nft_split_changes,
+};
+
 async fn invalidate_nft_listings(
     rt: crate::runtime::TxProcessingRuntime,
     tx: crate::ReceiptData,
@@ -43,6 +60,7 @@ async fn invalidate_nft_listings(
 
     use crate::handlers::prelude::*;
 
+    rt.stats.touch_listings(&nft_contract_id, tx.timestamp);
     let source = nft_listings::table
         .filter(dsl::nft_contract_id.eq(nft_contract_id))
         .filter(dsl::token_id.eq(any(token_ids)))
@@ -54,12 +72,12 @@ async fn invalidate_nft_listings(
     if let Some(market_id) = market_id {
         diesel::update(source.filter(dsl::market_id.eq(market_id)))
             .set(dsl::invalidated_at.eq(tx.timestamp))
-            .execute_db(&rt.pg_connection, &tx, "invalidate listing")
+            .execute_db(&rt, &tx, "invalidate listing")
             .await
     } else {
         diesel::update(source)
             .set(dsl::invalidated_at.eq(tx.timestamp))
-            .execute_db(&rt.pg_connection, &tx, "invalidate listing")
+            .execute_db(&rt, &tx, "invalidate listing")
             .await
     }
 }
@@ -87,12 +105,85 @@ async fn invalidate_nft_offers(
     if let Some(market_id) = market_id {
         diesel::update(source.filter(dsl::market_id.eq(market_id)))
             .set(dsl::invalidated_at.eq(tx.timestamp))
-            .execute_db(&rt.pg_connection, &tx, "invalidate offer")
+            .execute_db(&rt, &tx, "invalidate offer")
             .await
     } else {
         diesel::update(source)
             .set(dsl::invalidated_at.eq(tx.timestamp))
-            .execute_db(&rt.pg_connection, &tx, "invalidate offer")
+            .execute_db(&rt, &tx, "invalidate offer")
             .await
     }
 }
+
+/// A mint, transfer or burn of a token, with the owner it left the token with
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "nft_ownership_changes"]
+pub(crate) struct NftOwnershipChange {
+    pub(crate) nft_contract_id: String,
+    pub(crate) token_id: String,
+    pub(crate) receipt_id: String,
+    /// Position of the event log among the logs of the receipt
+    pub(crate) log_index: i32,
+    /// Position of the event within the data of its log
+    pub(crate) event_index: i32,
+    pub(crate) block_height: i64,
+    pub(crate) timestamp: chrono::NaiveDateTime,
+    /// `None` once the token has been burned
+    pub(crate) owner: Option<String>,
+}
+
+/// Changes for the `event_index`-th event of the log that `tx` is handling
+pub(crate) fn ownership_changes(
+    tx: &crate::ReceiptData,
+    event_index: usize,
+    token_ids: &[String],
+    owner: Option<String>,
+) -> Vec<NftOwnershipChange> {
+    token_ids
+        .iter()
+        .map(|token_id| NftOwnershipChange {
+            nft_contract_id: tx.receiver.to_string(),
+            token_id: token_id.clone(),
+            receipt_id: tx.id.clone(),
+            log_index: tx.log_index as i32,
+            event_index: event_index as i32,
+            block_height: tx.block_height as i64,
+            timestamp: tx.timestamp,
+            owner: owner.clone(),
+        })
+        .collect()
+}
+
+/// Royalties (only at mint) and split owners that have been set on a token
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "nft_split_changes"]
+pub(crate) struct NftSplitChange {
+    pub(crate) nft_contract_id: String,
+    pub(crate) token_id: String,
+    pub(crate) receipt_id: String,
+    pub(crate) timestamp: chrono::NaiveDateTime,
+    pub(crate) royalties: Option<serde_json::Value>,
+    pub(crate) royalties_percent: Option<i32>,
+    pub(crate) splits: Option<serde_json::Value>,
+}
+
+pub(crate) fn split_changes(
+    tx: &crate::ReceiptData,
+    token_ids: &[String],
+    royalties: Option<serde_json::Value>,
+    royalties_percent: Option<i32>,
+    splits: Option<serde_json::Value>,
+) -> Vec<NftSplitChange> {
+    token_ids
+        .iter()
+        .map(|token_id| NftSplitChange {
+            nft_contract_id: tx.receiver.to_string(),
+            token_id: token_id.clone(),
+            receipt_id: tx.id.clone(),
+            timestamp: tx.timestamp,
+            royalties: royalties.clone(),
+            royalties_percent,
+            splits: splits.clone(),
+        })
+        .collect()
+}
@@ -90,7 +90,7 @@ async fn insert_nft_listing(
 
     diesel::insert_into(nft_listings::table)
         .values(listing)
-        .execute_db(&rt.pg_connection, &tx, "insert listing")
+        .execute_db(&rt, &tx, "insert listing")
         .await
 }
 
@@ -114,8 +114,6 @@ async fn insert_nft_activities(
         currency: Some(CURRENCY_NEAR.to_string()),
     };
 
-    diesel::insert_into(nft_activities::table)
-        .values(activity)
-        .execute_db(&rt.pg_connection, &tx, "insert activity on listing")
+    insert_activities(&rt, &tx, vec![activity], "insert activity on listing")
         .await
 }
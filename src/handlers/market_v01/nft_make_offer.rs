@@ -75,7 +75,7 @@ async fn insert_nft_offer(
 
     diesel::insert_into(nft_offers::table)
         .values(offer)
-        .execute_db(&rt.pg_connection, &tx, "insert listing")
+        .execute_db(&rt, &tx, "insert listing")
         .await;
 }
 
@@ -110,7 +110,7 @@ async fn outbid_nft_offers(
             .filter(dsl::invalidated_at.is_null()),
     )
     .set(dsl::outbid_at.eq(tx.timestamp))
-    .execute_db(&rt.pg_connection, &tx, "invalidate_offer")
+    .execute_db(&rt, &tx, "invalidate_offer")
     .await
 }
 
@@ -167,8 +167,6 @@ async fn insert_nft_activities(
         currency: Some(CURRENCY_NEAR.to_string()),
     };
 
-    diesel::insert_into(nft_activities::table)
-        .values(activity)
-        .execute_db(&rt.pg_connection, &tx, "insert activity on make offer")
+    insert_activities(&rt, &tx, vec![activity], "insert activity on make offer")
         .await
 }
@@ -53,6 +53,9 @@ async fn update_nft_listings(
             Some(triple) => triple,
         };
 
+    rt.stats.touch_listings(nft_contract, tx.timestamp);
+    rt.stats.touch_sale(nft_contract, tx.timestamp);
+    rt.stats.touch_sale_receipt(&tx.id);
     diesel::update(
         dsl::nft_listings
             .filter(dsl::token_id.eq(token_id.to_string()))
@@ -63,7 +66,7 @@ async fn update_nft_listings(
         dsl::accepted_at.eq(tx.timestamp),
         dsl::accepted_offer_id.eq(data.offer_num as i64),
     ))
-    .execute_db(&rt.pg_connection, &tx, "update listing on sale")
+    .execute_db(&rt, &tx, "update listing on sale")
     .await
 }
 
@@ -95,7 +98,7 @@ async fn update_nft_offers(
             .filter(dsl::offer_id.eq(data.offer_num as i64)),
     )
     .set((dsl::accepted_at.eq(tx.timestamp),))
-    .execute_db(&rt.pg_connection, &tx, "update offer on sale")
+    .execute_db(&rt, &tx, "update offer on sale")
     .await
 }
 
@@ -155,10 +158,7 @@ async fn insert_nft_earnings(
         });
     }
 
-    diesel::insert_into(nft_earnings::table)
-        .values(values)
-        .execute_db(&rt.pg_connection, &tx, "insert earnings on sale")
-        .await;
+    insert_earnings(&rt, &tx, values, "insert earnings on sale").await;
 }
 
 async fn insert_nft_activities(
@@ -209,9 +209,7 @@ async fn insert_nft_activities(
             currency: Some(CURRENCY_NEAR.to_string()),
         };
 
-        diesel::insert_into(nft_activities::table)
-            .values(activity)
-            .execute_db(&rt.pg_connection, &tx, "insert activity on sale")
+        insert_activities(&rt, &tx, vec![activity], "insert activity on sale")
             .await
     }
 }
@@ -246,7 +244,7 @@ async fn remove_listing_invalidation(
             .filter(dsl::market_id.eq(tx.receiver.to_string())),
     )
     .set(dsl::invalidated_at.eq(Option::<chrono::NaiveDateTime>::None))
-    .execute_db(&rt.pg_connection, &tx, "revalidate listing")
+    .execute_db(&rt, &tx, "revalidate listing")
     .await
 }
 
@@ -281,7 +279,7 @@ async fn remove_offer_invalidation(
             .filter(dsl::offer_id.eq(data.offer_num as i64)),
     )
     .set(dsl::invalidated_at.eq(Option::<chrono::NaiveDateTime>::None))
-    .execute_db(&rt.pg_connection, &tx, "revalidate offer")
+    .execute_db(&rt, &tx, "revalidate offer")
     .await
 }
 
@@ -53,6 +53,7 @@ async fn update_nft_listings(
             Some(triple) => triple,
         };
 
+    rt.stats.touch_listings(nft_contract, tx.timestamp);
     diesel::update(
         dsl::nft_listings
             .filter(dsl::token_id.eq(token_id.to_string()))
@@ -60,7 +61,7 @@ async fn update_nft_listings(
             .filter(dsl::approval_id.eq(pg_numeric(approval_id))),
     )
     .set(dsl::unlisted_at.eq(tx.timestamp))
-    .execute_db(&rt.pg_connection, &tx, "update listing on unlist")
+    .execute_db(&rt, &tx, "update listing on unlist")
     .await
 }
 
@@ -95,7 +96,7 @@ async fn invalidate_nft_offers(
             .filter(dsl::invalidated_at.is_null()),
     )
     .set(dsl::invalidated_at.eq(tx.timestamp))
-    .execute_db(&rt.pg_connection, &tx, "invalidate_offer")
+    .execute_db(&rt, &tx, "invalidate_offer")
     .await
 }
 
@@ -127,8 +128,6 @@ async fn insert_nft_activities(
         currency: None,
     };
 
-    diesel::insert_into(nft_activities::table)
-        .values(activity)
-        .execute_db(&rt.pg_connection, &tx, "insert activity on unlist")
+    insert_activities(&rt, &tx, vec![activity], "insert activity on unlist")
         .await
 }
@@ -30,6 +30,7 @@ pub(crate) async fn handle_nft_update_list(
             Some(triple) => triple,
         };
 
+    rt.stats.touch_listings(nft_contract, tx.timestamp);
     let target_row = diesel::update(
         dsl::nft_listings
             .filter(dsl::token_id.eq(token_id.to_string()))
@@ -40,17 +41,17 @@ pub(crate) async fn handle_nft_update_list(
     if let Some(new_price) = data.price.map(|price| pg_numeric(price.0)) {
         target_row
             .set(dsl::price.eq(new_price))
-            .execute_db(&rt.pg_connection, tx, "update listing")
+            .execute_db(rt, tx, "update listing")
             .await;
     } else if let Some(true) = data.auto_transfer {
         target_row
             .set(dsl::kind.eq(NFT_LISTING_KIND_SIMPLE))
-            .execute_db(&rt.pg_connection, tx, "update listing")
+            .execute_db(rt, tx, "update listing")
             .await;
     } else if let Some(false) = data.auto_transfer {
         target_row
             .set(dsl::kind.eq(NFT_LISTING_KIND_AUCTION))
-            .execute_db(&rt.pg_connection, tx, "update listing")
+            .execute_db(rt, tx, "update listing")
             .await;
     } else {
         crate::error!("Invalid listing update date: {:?} ({:?})", data, tx);
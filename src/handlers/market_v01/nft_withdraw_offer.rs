@@ -50,7 +50,7 @@ async fn update_nft_offer(
             .filter(dsl::offer_id.eq(data.offer_num as i64)),
     )
     .set(dsl::withdrawn_at.eq(tx.timestamp))
-    .execute_db(&rt.pg_connection, &tx, "update offer on withdrawal")
+    .execute_db(&rt, &tx, "update offer on withdrawal")
     .await
 }
 
@@ -97,8 +97,11 @@ async fn insert_nft_activities(
         currency: None,
     };
 
-    diesel::insert_into(nft_activities::table)
-        .values(activity)
-        .execute_db(&rt.pg_connection, &tx, "insert activity on withdraw offer")
-        .await
+    insert_activities(
+        &rt,
+        &tx,
+        vec![activity],
+        "insert activity on withdraw offer",
+    )
+    .await
 }
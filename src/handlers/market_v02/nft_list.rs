@@ -16,6 +16,7 @@ pub(crate) async fn handle_nft_list(
         }
         Ok(data) => data,
     };
+    ensure_currency(rt, &data.currency);
 
     future::join(
         crate::handlers::invalidate_nft_listings(
@@ -76,7 +77,7 @@ async fn insert_nft_listing(
 
     diesel::insert_into(nft_listings::table)
         .values(listing)
-        .execute_db(&rt.pg_connection, &tx, "insert listing")
+        .execute_db(&rt, &tx, "insert listing")
         .await
 }
 
@@ -100,8 +101,6 @@ async fn insert_nft_activities(
         currency: Some(data.currency),
     };
 
-    diesel::insert_into(nft_activities::table)
-        .values(activity)
-        .execute_db(&rt.pg_connection, &tx, "insert activity on listing")
+    insert_activities(&rt, &tx, vec![activity], "insert activity on listing")
         .await
 }
@@ -18,6 +18,7 @@ pub(crate) async fn handle_nft_make_offer_v021(
             }
             Ok(data) => data,
         };
+    ensure_currency(rt, &data.currency);
 
     future::join(
         insert_nft_offer(rt.clone(), tx.clone(), data.clone()),
@@ -62,7 +63,7 @@ async fn insert_nft_offer(
 
     diesel::insert_into(nft_offers::table)
         .values(offer)
-        .execute_db(&rt.pg_connection, &tx, "insert listing")
+        .execute_db(&rt, &tx, "insert listing")
         .await
 }
 
@@ -95,9 +96,12 @@ async fn insert_nft_activities(
             currency: Some(currency),
         };
 
-        diesel::insert_into(nft_activities::table)
-            .values(activity)
-            .execute_db(&rt.pg_connection, &tx, "insert activity on make offer")
-            .await
+        insert_activities(
+            &rt,
+            &tx,
+            vec![activity],
+            "insert activity on make offer",
+        )
+        .await
     };
 }
@@ -14,6 +14,7 @@ pub(crate) async fn handle_nft_sold_v022(
         }
         Ok(data) => data,
     };
+    ensure_currency(rt, &data.currency);
 
     // cannot use future::join_all, because it doesn't allow me to generate the
     // array/vector without boxing futures
@@ -40,6 +41,11 @@ async fn update_nft_listings(
 ) {
     use nft_listings::dsl;
 
+    rt.stats
+        .touch_listings(&data.nft_contract_id.to_string(), tx.timestamp);
+    rt.stats
+        .touch_sale(&data.nft_contract_id.to_string(), tx.timestamp);
+    rt.stats.touch_sale_receipt(&tx.id);
     diesel::update(
         dsl::nft_listings
             .filter(dsl::nft_contract_id.eq(data.nft_contract_id.to_string()))
@@ -51,7 +57,7 @@ async fn update_nft_listings(
         dsl::accepted_at.eq(tx.timestamp),
         dsl::accepted_offer_id.eq(data.accepted_offer_id as i64),
     ))
-    .execute_db(&rt.pg_connection, &tx, "update listing on sale")
+    .execute_db(&rt, &tx, "update listing on sale")
     .await
 }
 
@@ -71,7 +77,7 @@ async fn update_nft_offers(
             .filter(dsl::offer_id.eq(data.accepted_offer_id as i64)),
     )
     .set((dsl::accepted_at.eq(tx.timestamp),))
-    .execute_db(&rt.pg_connection, &tx, "update listing on sale")
+    .execute_db(&rt, &tx, "update listing on sale")
     .await
 }
 
@@ -136,10 +142,7 @@ async fn insert_nft_earnings(
         });
     }
 
-    diesel::insert_into(nft_earnings::table)
-        .values(values)
-        .execute_db(&rt.pg_connection, &tx, "insert earnings on sale")
-        .await;
+    insert_earnings(&rt, &tx, values, "insert earnings on sale").await;
 }
 
 async fn insert_nft_activities(
@@ -173,9 +176,7 @@ async fn insert_nft_activities(
             currency: Some(currency),
         };
 
-        diesel::insert_into(nft_activities::table)
-            .values(activity)
-            .execute_db(&rt.pg_connection, &tx, "insert activity on sale")
+        insert_activities(&rt, &tx, vec![activity], "insert activity on sale")
             .await
     }
 }
@@ -197,7 +198,7 @@ async fn remove_listing_invalidation(
             .filter(dsl::market_id.eq(tx.receiver.to_string())),
     )
     .set(dsl::invalidated_at.eq(Option::<chrono::NaiveDateTime>::None))
-    .execute_db(&rt.pg_connection, &tx, "revalidate listing")
+    .execute_db(&rt, &tx, "revalidate listing")
     .await
 }
 
@@ -219,7 +220,7 @@ async fn remove_offer_invalidation(
             .filter(dsl::offer_id.eq(data.accepted_offer_id as i64)),
     )
     .set(dsl::invalidated_at.eq(Option::<chrono::NaiveDateTime>::None))
-    .execute_db(&rt.pg_connection, &tx, "revalidate offer")
+    .execute_db(&rt, &tx, "revalidate offer")
     .await
 }
 
@@ -29,6 +29,8 @@ async fn update_nft_listings(
 ) {
     use nft_listings::dsl;
 
+    rt.stats
+        .touch_listings(&data.nft_contract_id.to_string(), tx.timestamp);
     diesel::update(
         dsl::nft_listings
             .filter(dsl::token_id.eq(data.nft_token_id.to_string()))
@@ -37,7 +39,7 @@ async fn update_nft_listings(
             .filter(dsl::approval_id.eq(pg_numeric(data.nft_approval_id))),
     )
     .set(dsl::unlisted_at.eq(tx.timestamp))
-    .execute_db(&rt.pg_connection, &tx, "update listing on unlist")
+    .execute_db(&rt, &tx, "update listing on unlist")
     .await
 }
 
@@ -61,8 +63,6 @@ async fn insert_nft_activities(
         currency: None,
     };
 
-    diesel::insert_into(nft_activities::table)
-        .values(activity)
-        .execute_db(&rt.pg_connection, &tx, "insert activity on unlist")
+    insert_activities(&rt, &tx, vec![activity], "insert activity on unlist")
         .await
 }
@@ -39,7 +39,7 @@ pub(crate) async fn handle_mb_store_deploy(
                 is_mintbase: true,
                 category: None,
             })
-            .execute_db(&rt.pg_connection, tx, "Updating new contract"),
+            .execute_db(rt, tx, "Updating new contract"),
         // add owner as minter
         diesel::insert_into(mb_store_minters::table)
             .values(MbStoreMinter {
@@ -48,7 +48,7 @@ pub(crate) async fn handle_mb_store_deploy(
                 receipt_id: tx.id.clone(),
                 timestamp: tx.timestamp,
             })
-            .execute_db(&rt.pg_connection, tx, "insert new contract"),
+            .execute_db(rt, tx, "insert new contract"),
     )
     .await;
 }
@@ -82,7 +82,7 @@ pub(crate) async fn handle_mb_store_change_setting(
                 receipt_id: tx.id.clone(),
                 timestamp: tx.timestamp,
             })
-            .execute_db(&rt.pg_connection, tx, "insert minter")
+            .execute_db(rt, tx, "insert minter")
             .await;
     }
 
@@ -95,7 +95,7 @@ pub(crate) async fn handle_mb_store_change_setting(
                 )
                 .filter(mb_store_minters::dsl::minter_id.eq(revoked_minter)),
         )
-        .execute_db(&rt.pg_connection, tx, "delete minter")
+        .execute_db(rt, tx, "delete minter")
         .await;
     }
 
@@ -105,7 +105,7 @@ pub(crate) async fn handle_mb_store_change_setting(
                 .filter(nft_contracts::dsl::id.eq(tx.receiver.to_string())),
         )
         .set(nft_contracts::dsl::owner_id.eq(new_owner))
-        .execute_db(&rt.pg_connection, tx, "updating owner")
+        .execute_db(rt, tx, "updating owner")
         .await
     }
 
@@ -115,7 +115,7 @@ pub(crate) async fn handle_mb_store_change_setting(
                 .filter(nft_contracts::dsl::id.eq(tx.receiver.to_string())),
         )
         .set(nft_contracts::dsl::icon.eq(new_icon))
-        .execute_db(&rt.pg_connection, tx, "updating owner")
+        .execute_db(rt, tx, "updating owner")
         .await
     }
 
@@ -125,7 +125,7 @@ pub(crate) async fn handle_mb_store_change_setting(
                 .filter(nft_contracts::dsl::id.eq(tx.receiver.to_string())),
         )
         .set(nft_contracts::dsl::base_uri.eq(new_uri))
-        .execute_db(&rt.pg_connection, tx, "updating owner")
+        .execute_db(rt, tx, "updating owner")
         .await
     }
 }
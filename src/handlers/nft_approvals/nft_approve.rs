@@ -51,7 +51,7 @@ async fn insert_nft_approvals(
         .on_conflict(diesel::pg::upsert::on_constraint("nft_approvals_pkey"))
         .do_update()
         .set((dsl::approval_id.eq(pg_numeric(log.approval_id)),))
-        .execute_db(&rt.pg_connection, &tx, "insert token on transfer")
+        .execute_db(&rt, &tx, "insert token on transfer")
         .await
 }
 
@@ -60,21 +60,21 @@ async fn insert_nft_activities(
     tx: ReceiptData,
     log: NftApproveLog,
 ) {
-    diesel::insert_into(nft_activities::table)
-        .values(NftActivity {
-            receipt_id: tx.id.clone(),
-            tx_sender: tx.sender.to_string(),
-            sender_pk: tx.sender_pk.clone(),
-            timestamp: tx.timestamp,
-            nft_contract_id: tx.receiver.to_string(),
-            token_id: log.token_id,
-            kind: NFT_ACTIVITY_KIND_APPROVE.to_string(),
-            action_sender: tx.sender.to_string(),
-            action_receiver: Some(log.account_id.to_string()),
-            memo: None,
-            price: None,
-            currency: None,
-        })
-        .execute_db(&rt.pg_connection, &tx, "insert activity on transfer")
+    let activity = NftActivity {
+        receipt_id: tx.id.clone(),
+        tx_sender: tx.sender.to_string(),
+        sender_pk: tx.sender_pk.clone(),
+        timestamp: tx.timestamp,
+        nft_contract_id: tx.receiver.to_string(),
+        token_id: log.token_id,
+        kind: NFT_ACTIVITY_KIND_APPROVE.to_string(),
+        action_sender: tx.sender.to_string(),
+        action_receiver: Some(log.account_id.to_string()),
+        memo: None,
+        price: None,
+        currency: None,
+    };
+
+    insert_activities(&rt, &tx, vec![activity], "insert activity on transfer")
         .await
 }
@@ -48,7 +48,7 @@ async fn delete_nft_approvals(
             .filter(dsl::nft_contract_id.eq(tx.receiver.to_string()))
             .filter(dsl::token_id.eq(data.token_id)),
     )
-    .execute_db(&rt.pg_connection, &tx, "delete approval on revoke")
+    .execute_db(&rt, &tx, "delete approval on revoke")
     .await;
 }
 
@@ -57,21 +57,21 @@ async fn insert_nft_activities(
     tx: ReceiptData,
     log: NftRevokeAllData,
 ) {
-    diesel::insert_into(nft_activities::table)
-        .values(NftActivity {
-            receipt_id: tx.id.clone(),
-            tx_sender: tx.sender.to_string(),
-            sender_pk: tx.sender_pk.clone(),
-            timestamp: tx.timestamp,
-            nft_contract_id: tx.receiver.to_string(),
-            token_id: log.token_id,
-            kind: NFT_ACTIVITY_KIND_REVOKE_ALL.to_string(),
-            action_sender: tx.sender.to_string(),
-            action_receiver: None,
-            memo: None,
-            price: None,
-            currency: None,
-        })
-        .execute_db(&rt.pg_connection, &tx, "insert activity on transfer")
+    let activity = NftActivity {
+        receipt_id: tx.id.clone(),
+        tx_sender: tx.sender.to_string(),
+        sender_pk: tx.sender_pk.clone(),
+        timestamp: tx.timestamp,
+        nft_contract_id: tx.receiver.to_string(),
+        token_id: log.token_id,
+        kind: NFT_ACTIVITY_KIND_REVOKE_ALL.to_string(),
+        action_sender: tx.sender.to_string(),
+        action_receiver: None,
+        memo: None,
+        price: None,
+        currency: None,
+    };
+
+    insert_activities(&rt, &tx, vec![activity], "insert activity on transfer")
         .await
 }
@@ -1,7 +1,10 @@
 use mb_sdk::events::nft_core::NftBurnLog;
 
 use crate::{
-    error, handlers::prelude::*, runtime::TxProcessingRuntime, ReceiptData,
+    error,
+    handlers::prelude::*,
+    runtime::TxProcessingRuntime,
+    ReceiptData,
 };
 
 pub(crate) async fn handle_nft_burn(
@@ -18,9 +21,9 @@ pub(crate) async fn handle_nft_burn(
         Err(_) => error!(r#"Invalid log for "nft_burn": {} ({:?})"#, data, tx),
         Ok(data_logs) => {
             future::join_all(
-                data_logs
-                    .into_iter()
-                    .map(|log| handle_nft_burn_log(rt, tx, log)),
+                data_logs.into_iter().enumerate().map(|(index, log)| {
+                    handle_nft_burn_log(rt, tx, index, log)
+                }),
             )
             .await;
         }
@@ -30,11 +33,25 @@ pub(crate) async fn handle_nft_burn(
 async fn handle_nft_burn_log(
     rt: &TxProcessingRuntime,
     tx: &ReceiptData,
+    index: usize,
     log: NftBurnLog,
 ) {
-    future::join4(
+    rt.stats.touch_owners(
+        tx.receiver.as_str(),
+        tx.timestamp,
+        &log.token_ids,
+        Some(log.owner_id.as_str()),
+        None,
+    );
+    future::join5(
         insert_nft_tokens(rt.clone(), tx.clone(), log.clone()),
         insert_nft_activities(rt.clone(), tx.clone(), log.clone()),
+        insert_ownership_changes(
+            rt,
+            tx,
+            ownership_changes(tx, index, &log.token_ids, None),
+            "insert ownership change on burn",
+        ),
         crate::handlers::invalidate_nft_listings(
             rt.clone(),
             tx.clone(),
@@ -73,6 +90,11 @@ async fn insert_nft_tokens(
         })
         .collect::<Vec<_>>();
 
+    if let Some(bulk) = &rt.bulk {
+        bulk.push_tokens(TokenWrite::Burn, tokens);
+        return;
+    }
+
     diesel::insert_into(nft_tokens::table)
         .values(tokens)
         .on_conflict(diesel::pg::upsert::on_constraint("nft_tokens_pkey"))
@@ -81,7 +103,7 @@ async fn insert_nft_tokens(
             dsl::burned_timestamp.eq(tx.timestamp),
             dsl::burned_receipt_id.eq(tx.id.clone()),
         ))
-        .execute_db(&rt.pg_connection, &tx, "insert token on transfer")
+        .execute_db(&rt, &tx, "insert token on transfer")
         .await
 }
 
@@ -109,8 +131,5 @@ async fn insert_nft_activities(
         })
         .collect::<Vec<_>>();
 
-    diesel::insert_into(nft_activities::table)
-        .values(activities)
-        .execute_db(&rt.pg_connection, &tx, "insert activity on mint")
-        .await
+    insert_activities(&rt, &tx, activities, "insert activity on mint").await
 }
@@ -1,4 +1,5 @@
 use mb_sdk::events::nft_core::NftMintLog;
+use tracing::Instrument;
 
 use crate::{
     error,
@@ -20,11 +21,11 @@ pub(crate) async fn handle_nft_mint(
     match serde_json::from_value::<Vec<NftMintLog>>(data.clone()) {
         Err(_) => error!(r#"Invalid log for "nft_mint": {} ({:?})"#, data, tx),
         Ok(data_logs) => {
-            future::join_all(
-                data_logs.into_iter().map(|log| {
-                    handle_nft_mint_log(rt.clone(), tx.clone(), log)
-                }),
-            )
+            future::join_all(data_logs.into_iter().enumerate().map(
+                |(index, log)| {
+                    handle_nft_mint_log(rt.clone(), tx.clone(), index, log)
+                },
+            ))
             .await;
         }
     }
@@ -33,27 +34,57 @@ pub(crate) async fn handle_nft_mint(
 async fn handle_nft_mint_log(
     rt: TxProcessingRuntime,
     tx: ReceiptData,
+    index: usize,
     log: NftMintLog,
 ) {
+    rt.stats.touch_owners(
+        tx.receiver.as_str(),
+        tx.timestamp,
+        &log.token_ids,
+        None,
+        Some(log.owner_id.as_str()),
+    );
     // TODO: join in RPC call? -> would require `on_conflict`
-    future::join(
+    future::join3(
         insert_nft_tokens(rt.clone(), tx.clone(), log.clone()),
         insert_nft_activities(rt.clone(), tx.clone(), log.clone()),
+        insert_ownership_changes(
+            &rt,
+            &tx,
+            ownership_changes(
+                &tx,
+                index,
+                &log.token_ids,
+                Some(log.owner_id.clone()),
+            ),
+            "insert ownership change on mint",
+        ),
     )
     .await;
 
-    // Async block prevents runtime borrow from being invalidated
-    #[allow(clippy::redundant_async_block)]
-    actix_rt::spawn(async move {
-        rt.minterop_rpc
-            .token(
+    // Dispatched in the background, but awaited on shutdown. In bulk mode,
+    // the token is only written once the buffer is flushed.
+    if let Some(bulk) = &rt.bulk {
+        bulk.defer_token_rpc(
+            tx.receiver.to_string(),
+            log.token_ids,
+            Some(tx.sender.to_string()),
+        );
+        return;
+    }
+    let rpc = rt.minterop_rpc.clone();
+    rt.minterop_rpc.dispatch(
+        async move {
+            rpc.token(
                 tx.receiver.to_string(),
                 log.token_ids,
                 Some(tx.sender.to_string()),
                 None,
             )
             .await
-    });
+        }
+        .in_current_span(),
+    );
 }
 
 async fn insert_nft_tokens(
@@ -61,6 +92,8 @@ async fn insert_nft_tokens(
     tx: ReceiptData,
     log: NftMintLog,
 ) {
+    use minterop_data::schema::nft_tokens::dsl;
+
     // FIXME: only try on mintbase contracts!
     let (royalties_percent, royalties, splits) =
         if log.memo.is_some() && tx.receiver.ends_with(&rt.mintbase_root) {
@@ -68,6 +101,21 @@ async fn insert_nft_tokens(
         } else {
             (None, None, None)
         };
+    if royalties.is_some() || splits.is_some() {
+        insert_split_changes(
+            &rt,
+            &tx,
+            split_changes(
+                &tx,
+                &log.token_ids,
+                royalties.clone(),
+                royalties_percent,
+                splits.clone(),
+            ),
+            "insert split change on mint",
+        )
+        .await;
+    }
 
     let tokens = log
         .token_ids
@@ -87,9 +135,26 @@ async fn insert_nft_tokens(
         })
         .collect::<Vec<_>>();
 
+    if let Some(bulk) = &rt.bulk {
+        bulk.push_tokens(TokenWrite::Mint, tokens);
+        return;
+    }
+
+    // Tokens can already exist when a range is reindexed, possibly out of
+    // order. Ownership is then left to the reconciliation of the reindex.
     diesel::insert_into(nft_tokens::table)
         .values(tokens)
-        .execute_db(&rt.pg_connection, &tx, "insert token on mint")
+        .on_conflict(diesel::pg::upsert::on_constraint("nft_tokens_pkey"))
+        .do_update()
+        .set((
+            dsl::mint_memo.eq(log.memo.clone()),
+            dsl::minted_timestamp.eq(tx.timestamp),
+            dsl::minted_receipt_id.eq(tx.id.clone()),
+            dsl::minter.eq(tx.sender.to_string()),
+            dsl::royalties.eq(royalties),
+            dsl::royalties_percent.eq(royalties_percent),
+        ))
+        .execute_db(&rt, &tx, "insert token on mint")
         .await
 }
 
@@ -117,10 +182,7 @@ async fn insert_nft_activities(
         })
         .collect::<Vec<_>>();
 
-    diesel::insert_into(nft_activities::table)
-        .values(activities)
-        .execute_db(&rt.pg_connection, &tx, "insert activity on mint")
-        .await
+    insert_activities(&rt, &tx, activities, "insert activity on mint").await
 }
 
 // ----------- logic for parsing mint memos on MB token contracts ----------- //
@@ -1,7 +1,11 @@
 use mb_sdk::events::nft_core::NftTransferLog;
+use tracing::Instrument;
 
 use crate::{
-    error, handlers::prelude::*, runtime::TxProcessingRuntime, ReceiptData,
+    error,
+    handlers::prelude::*,
+    runtime::TxProcessingRuntime,
+    ReceiptData,
 };
 
 pub(crate) async fn handle_nft_transfer(
@@ -19,9 +23,11 @@ pub(crate) async fn handle_nft_transfer(
             error!(r#"Invalid log for "nft_transfer": {} ({:?})"#, data, tx)
         }
         Ok(data_logs) => {
-            future::join_all(data_logs.into_iter().map(|log| {
-                handle_nft_transfer_log(rt.clone(), tx.clone(), log)
-            }))
+            future::join_all(data_logs.into_iter().enumerate().map(
+                |(index, log)| {
+                    handle_nft_transfer_log(rt.clone(), tx.clone(), index, log)
+                },
+            ))
             .await;
         }
     }
@@ -30,12 +36,31 @@ pub(crate) async fn handle_nft_transfer(
 async fn handle_nft_transfer_log(
     rt: TxProcessingRuntime,
     tx: ReceiptData,
+    index: usize,
     log: NftTransferLog,
 ) {
+    rt.stats.touch_owners(
+        tx.receiver.as_str(),
+        tx.timestamp,
+        &log.token_ids,
+        Some(log.old_owner_id.as_str()),
+        Some(log.new_owner_id.as_str()),
+    );
     // TODO: join in RPC call? -> would require `on_conflict`
-    future::join4(
+    future::join5(
         insert_nft_tokens(rt.clone(), tx.clone(), log.clone()),
         insert_nft_activities(rt.clone(), tx.clone(), log.clone()),
+        insert_ownership_changes(
+            &rt,
+            &tx,
+            ownership_changes(
+                &tx,
+                index,
+                &log.token_ids,
+                Some(log.new_owner_id.clone()),
+            ),
+            "insert ownership change on transfer",
+        ),
         crate::handlers::invalidate_nft_listings(
             rt.clone(),
             tx.clone(),
@@ -53,18 +78,29 @@ async fn handle_nft_transfer_log(
     )
     .await;
 
-    // Async block prevents runtime borrow from being invalidated
-    #[allow(clippy::redundant_async_block)]
-    actix_rt::spawn(async move {
-        rt.minterop_rpc
-            .token(
+    // Dispatched in the background, but awaited on shutdown. In bulk mode,
+    // the token is only written once the buffer is flushed.
+    if let Some(bulk) = &rt.bulk {
+        bulk.defer_token_rpc(
+            tx.receiver.to_string(),
+            log.token_ids,
+            Some(tx.sender.to_string()),
+        );
+        return;
+    }
+    let rpc = rt.minterop_rpc.clone();
+    rt.minterop_rpc.dispatch(
+        async move {
+            rpc.token(
                 tx.receiver.to_string(),
                 log.token_ids,
                 Some(tx.sender.to_string()),
                 None,
             )
             .await
-    });
+        }
+        .in_current_span(),
+    );
 }
 
 async fn insert_nft_tokens(
@@ -87,6 +123,11 @@ async fn insert_nft_tokens(
         })
         .collect::<Vec<_>>();
 
+    if let Some(bulk) = &rt.bulk {
+        bulk.push_tokens(TokenWrite::Transfer, tokens);
+        return;
+    }
+
     diesel::insert_into(nft_tokens::table)
         .values(tokens)
         .on_conflict(diesel::pg::upsert::on_constraint("nft_tokens_pkey"))
@@ -97,7 +138,7 @@ async fn insert_nft_tokens(
             dsl::last_transfer_receipt_id.eq(tx.id.clone()),
             dsl::splits.eq(Option::<serde_json::Value>::None),
         ))
-        .execute_db(&rt.pg_connection, &tx, "insert token on transfer")
+        .execute_db(&rt, &tx, "insert token on transfer")
         .await
 }
 
@@ -125,8 +166,5 @@ async fn insert_nft_activities(
         })
         .collect::<Vec<_>>();
 
-    diesel::insert_into(nft_activities::table)
-        .values(activities)
-        .execute_db(&rt.pg_connection, &tx, "insert activity on transfer")
-        .await
+    insert_activities(&rt, &tx, activities, "insert activity on transfer").await
 }
@@ -24,15 +24,21 @@ pub(crate) async fn handle_nft_set_split_owners(
     // unwrap ok, because schema has been validated
     let splits_json = data.get("split_owners").unwrap();
 
+    let changes =
+        split_changes(tx, &token_ids, None, None, Some(splits_json.clone()));
+
     // TODO: can this be accomplished in a single query?
-    future::join_all(token_ids.into_iter().map(|token_id| {
-        diesel::update(
-            nft_tokens::table
-                .filter(dsl::nft_contract_id.eq(tx.receiver.to_string()))
-                .filter(dsl::token_id.eq(token_id)),
-        )
-        .set(dsl::splits.eq(splits_json.clone()))
-        .execute_db(&rt.pg_connection, tx, "set splits")
-    }))
+    future::join(
+        insert_split_changes(rt, tx, changes, "insert split change"),
+        future::join_all(token_ids.into_iter().map(|token_id| {
+            diesel::update(
+                nft_tokens::table
+                    .filter(dsl::nft_contract_id.eq(tx.receiver.to_string()))
+                    .filter(dsl::token_id.eq(token_id)),
+            )
+            .set(dsl::splits.eq(splits_json.clone()))
+            .execute_db(rt, tx, "set splits")
+        })),
+    )
     .await;
 }
@@ -128,7 +128,7 @@ async fn handle_resolve_purchase_fail(
         dsl::failed_at.eq(tx.timestamp),
         dsl::failure_receipt_id.eq(tx.id.clone()),
     ))
-    .execute_db(&rt.pg_connection, tx, "mark external listing as failed")
+    .execute_db(rt, tx, "mark external listing as failed")
     .await;
 }
 
@@ -154,6 +154,7 @@ async fn handle_resolve_purchase(
             }
         };
 
+    rt.stats.touch_sale(&params.nft_contract_id, tx.timestamp);
     diesel::update(
         dsl::nft_external_listings
             .filter(dsl::nft_contract_id.eq(params.nft_contract_id))
@@ -168,7 +169,7 @@ async fn handle_resolve_purchase(
         dsl::sold_at.eq(tx.timestamp),
         dsl::sale_receipt_id.eq(tx.id.clone()),
     ))
-    .execute_db(&rt.pg_connection, tx, "mark external listing as sold")
+    .execute_db(rt, tx, "mark external listing as sold")
     .await;
 }
 
@@ -197,6 +198,7 @@ async fn handle_add_market_data(
     } else {
         format!("ft::{}", params.ft_token_id)
     };
+    ensure_currency(rt, &currency);
 
     diesel::insert_into(nft_external_listings::table)
         .values(NftExternalListing {
@@ -218,7 +220,7 @@ async fn handle_add_market_data(
             failed_at: None,
             failure_receipt_id: None,
         })
-        .execute_db(&rt.pg_connection, tx, "insert external listing")
+        .execute_db(rt, tx, "insert external listing")
         .await;
 }
 
@@ -256,7 +258,7 @@ async fn handle_delete_market_data(
         dsl::deleted_at.eq(tx.timestamp),
         dsl::deletion_receipt_id.eq(tx.id.clone()),
     ))
-    .execute_db(&rt.pg_connection, tx, "mark external listing as deleted")
+    .execute_db(rt, tx, "mark external listing as deleted")
     .await;
 }
 
@@ -21,6 +21,7 @@ pub(crate) enum TrackedAction {
 impl TrackedAction {
     pub fn try_new(
         account_id: &AccountId,
+        predecessor_id: &AccountId,
         timestamp: NaiveDateTime,
         receipt_id: &CryptoHash,
         view: &ActionView,
@@ -53,6 +54,7 @@ impl TrackedAction {
             ActionView::CreateAccount => {
                 Some(TrackedAction::CreateAccount(CreateAccount {
                     account_id: account_id.to_string(),
+                    creator_id: predecessor_id.to_string(),
                     timestamp,
                     receipt_id: receipt_id.to_string(),
                 }))
@@ -97,11 +99,7 @@ impl AddKey {
                 removed_at: None,
                 removed_receipt_id: None,
             })
-            .execute_db_action(
-                &rt.pg_connection,
-                &self.receipt_id,
-                "insert new access key",
-            )
+            .execute_db_action(rt, &self.receipt_id, "insert new access key")
             .await;
     }
 }
@@ -127,37 +125,32 @@ impl DeleteKey {
             dsl::removed_at.eq(self.timestamp),
             dsl::removed_receipt_id.eq(self.receipt_id.clone()),
         ))
-        .execute_db_action(
-            &rt.pg_connection,
-            &self.receipt_id,
-            "mark access key as removed",
-        )
+        .execute_db_action(rt, &self.receipt_id, "mark access key as removed")
         .await;
     }
 }
 
 pub(crate) struct CreateAccount {
     account_id: String,
+    /// Predecessor of the receipt, which funds the account as well
+    creator_id: String,
     timestamp: NaiveDateTime,
     receipt_id: String,
 }
 
 impl CreateAccount {
     async fn process(self, rt: &TxProcessingRuntime) {
-        diesel::insert_into(accounts::table)
-            .values(Account {
-                account_id: self.account_id,
-                created_at: self.timestamp,
-                created_receipt_id: self.receipt_id.clone(),
-                removed_at: None,
-                removed_receipt_id: None,
-                beneficiary_id: None,
-            })
-            .execute_db_action(
-                &rt.pg_connection,
-                &self.receipt_id,
-                "insert new account",
-            )
+        // `crate::schema::accounts` has the creator, which `Account` lacks
+        use crate::schema::accounts::dsl;
+
+        diesel::insert_into(dsl::accounts)
+            .values((
+                dsl::account_id.eq(self.account_id),
+                dsl::created_at.eq(self.timestamp),
+                dsl::created_receipt_id.eq(self.receipt_id.clone()),
+                dsl::creator_id.eq(self.creator_id),
+            ))
+            .execute_db_action(rt, &self.receipt_id, "insert new account")
             .await;
     }
 }
@@ -183,11 +176,7 @@ impl DeleteAccount {
             dsl::removed_receipt_id.eq(self.receipt_id.clone()),
             dsl::beneficiary_id.eq(self.beneficiary_id),
         ))
-        .execute_db_action(
-            &rt.pg_connection,
-            &self.receipt_id,
-            "mark account as removed",
-        )
+        .execute_db_action(rt, &self.receipt_id, "mark account as removed")
         .await;
     }
 }
@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+
+use anyhow::{
+    bail,
+    Context,
+    Result,
+};
+use chrono::NaiveDateTime;
+use diesel::{
+    dsl::{
+        any,
+        max,
+        min,
+    },
+    BoolExpressionMethods,
+    Connection,
+    ExpressionMethods,
+    PgConnection,
+    QueryDsl,
+    RunQueryDsl,
+};
+
+/// Output format of `holders`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum HoldersFormat {
+    Csv,
+    Json,
+}
+
+/// Owners of the non-burned tokens of some contracts at a block height
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct HolderSnapshot {
+    pub height: u64,
+    pub contracts: Vec<String>,
+    /// Sorted by descending number of tokens, then by owner
+    pub holders: Vec<Holder>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Holder {
+    pub owner: String,
+    pub tokens: u64,
+}
+
+impl HolderSnapshot {
+    /// Counts the tokens per owner from `nft_token_ownerships`, as of the end
+    /// of block `height`, which must have been synced already. Changes seeded
+    /// from older activities have no height and only count as preceding it if
+    /// an indexed change after all seeded ones is not above `height`.
+    pub(crate) fn query(
+        pg_string: &str,
+        contracts: &[String],
+        height: u64,
+    ) -> Result<HolderSnapshot> {
+        use crate::schema::nft_token_ownerships::dsl;
+
+        let conn = PgConnection::establish(pg_string)?;
+        let height_ = height as i64;
+        check_height(&conn, contracts, height_)?;
+
+        let owners = dsl::nft_token_ownerships
+            .select(dsl::owner)
+            .filter(dsl::nft_contract_id.eq(any(contracts.to_vec())))
+            .filter(dsl::from_height.is_null().or(dsl::from_height.le(height_)))
+            .filter(dsl::to_receipt_id.is_null().or(dsl::to_height.gt(height_)))
+            .load::<String>(&conn)
+            .with_context(|| {
+                format!("Failed to query holders at {}", height)
+            })?;
+
+        Ok(HolderSnapshot {
+            height,
+            contracts: contracts.to_vec(),
+            holders: count_tokens(owners),
+        })
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("owner,tokens\n");
+        for holder in &self.holders {
+            csv.push_str(&format!(
+                "{},{}\n",
+                crate::util::csv_field(&holder.owner),
+                holder.tokens
+            ));
+        }
+        csv
+    }
+
+    pub fn format(&self, format: HoldersFormat) -> Result<String> {
+        match format {
+            HoldersFormat::Csv => Ok(self.to_csv()),
+            HoldersFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+        }
+    }
+}
+
+/// Fails for heights that haven't been synced yet, and for heights that may
+/// precede seeded changes of `contracts`. Seeded changes only have a
+/// timestamp, so they are known to precede all blocks from the first one with
+/// an indexed change after the latest seeded one.
+fn check_height(
+    conn: &PgConnection,
+    contracts: &[String],
+    height: i64,
+) -> Result<()> {
+    use crate::schema::{
+        blocks::dsl as blocks,
+        nft_ownership_changes::dsl,
+    };
+
+    let synced_height = blocks::blocks
+        .select(blocks::synced_height)
+        .first::<i64>(conn)
+        .context("Failed to query synced height")?;
+    if height > synced_height {
+        bail!(
+            "Height {} has not been synced yet, the indexer is at {}",
+            height,
+            synced_height
+        );
+    }
+
+    let seeded_until = dsl::nft_ownership_changes
+        .select(max(dsl::timestamp))
+        .filter(dsl::nft_contract_id.eq(any(contracts.to_vec())))
+        .filter(dsl::block_height.is_null())
+        .first::<Option<NaiveDateTime>>(conn)
+        .context("Failed to query seeded ownership changes")?;
+    let seeded_until = match seeded_until {
+        Some(timestamp) => timestamp,
+        None => return Ok(()),
+    };
+    let first_height = dsl::nft_ownership_changes
+        .select(min(dsl::block_height))
+        .filter(dsl::timestamp.gt(seeded_until))
+        .first::<Option<i64>>(conn)
+        .context("Failed to query indexed ownership changes")?;
+    match first_height {
+        Some(first_height) if height >= first_height => Ok(()),
+        Some(first_height) => bail!(
+            "Holders at {} are unknown, as ownership changes up to {} have \
+             been seeded without height. Use a height from {} on, or reindex \
+             the contracts.",
+            height,
+            seeded_until,
+            first_height
+        ),
+        None => bail!(
+            "Holders at {} are unknown, as ownership changes up to {} have \
+             been seeded without height and none has been indexed since. \
+             Reindex the contracts.",
+            height,
+            seeded_until
+        ),
+    }
+}
+
+/// One owner per held token in, tokens per owner out
+fn count_tokens(owners: Vec<String>) -> Vec<Holder> {
+    let mut counts = BTreeMap::<String, u64>::new();
+    for owner in owners {
+        *counts.entry(owner).or_default() += 1;
+    }
+
+    let mut holders = counts
+        .into_iter()
+        .map(|(owner, tokens)| Holder { owner, tokens })
+        .collect::<Vec<_>>();
+    // stable, such that ties stay sorted by owner
+    holders.sort_by(|a, b| b.tokens.cmp(&a.tokens));
+    holders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_tokens() {
+        let owners = ["bob.near", "alice.near", "carol.near", "bob.near"]
+            .iter()
+            .map(|o| o.to_string())
+            .collect();
+        let snapshot = HolderSnapshot {
+            height: 10,
+            contracts: vec!["x.mintbase1.near".to_string()],
+            holders: count_tokens(owners),
+        };
+
+        assert_eq!(
+            snapshot.to_csv(),
+            "owner,tokens\nbob.near,2\nalice.near,1\ncarol.near,1\n"
+        );
+    }
+}
@@ -1,14 +1,89 @@
+#[macro_use]
+extern crate diesel;
+#[macro_use]
+extern crate diesel_migrations;
+
+mod backfill;
+mod block_source;
+mod bulk;
+mod capture;
+mod cli;
 mod config;
+mod currencies;
 mod database;
+mod dry_run;
 mod handlers;
+mod holders;
 mod logging;
+mod near_rpc;
+mod network;
+mod prices;
+mod reindex;
 mod rpc_connection;
 mod runtime;
+mod schema;
+mod shutdown;
+mod snapshot;
+mod statements;
+mod stats;
+mod telemetry;
 mod util;
 
+pub use backfill::BackfillJob;
+pub use block_source::{
+    BlockSource,
+    Finality,
+};
+pub use capture::{
+    capture,
+    CaptureSummary,
+};
+pub use cli::{
+    Cli,
+    Command,
+    ConfigOverrides,
+};
 pub use config::Config;
+pub use dry_run::{
+    DryRun,
+    DryRunReport,
+    Samples,
+};
+pub use holders::{
+    Holder,
+    HolderSnapshot,
+    HoldersFormat,
+};
+pub use prices::{
+    PriceImport,
+    PricePoint,
+    PriceSource,
+};
+pub use reindex::{
+    reindex,
+    Reconciliation,
+};
 pub use runtime::MintlakeRuntime;
 pub(crate) use runtime::ReceiptData;
+pub use shutdown::{
+    join_lake_handle,
+    listen_for_shutdown,
+    no_shutdown,
+    ShutdownSignal,
+};
+pub use snapshot::{
+    table_rows,
+    Snapshot,
+    SnapshotDiff,
+    SnapshotTable,
+    SNAPSHOT_TABLES,
+};
+pub use statements::{
+    Statement,
+    StatementLine,
+    StatementTotal,
+};
+pub use telemetry::shutdown_tracer;
 
 pub type LakeStreamer = tokio::sync::mpsc::Receiver<
     near_lake_framework::near_indexer_primitives::StreamerMessage,
@@ -1,57 +1,389 @@
-// use futures::StreamExt;
+use std::{
+    ops::RangeInclusive,
+    path::Path,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use chrono::{
+    NaiveDate,
+    NaiveDateTime,
+};
+use clap::Parser;
 use minterop_indexer::{
+    Cli,
+    Command,
     Config,
-    LakeHandle,
-    LakeStreamer,
+    ConfigOverrides,
+    HoldersFormat,
     MintlakeRuntime,
+    PriceSource,
+    Snapshot,
 };
-// use tokio_stream::wrappers::ReceiverStream;
 
-async fn init() -> (LakeHandle, LakeStreamer, MintlakeRuntime) {
-    if let Err(e) = dotenv::dotenv() {
-        panic!("Failed to execute `dotenv::dotenv`: {:?}", e);
+async fn run(cfg: &Config) -> Result<()> {
+    // initialize all the logging
+    cfg.init_logging().context("Failed to initialize logging")?;
+
+    // embedded migrations
+    cfg.migrate_db().context("Failed to migrate database")?;
+
+    // database connection
+    let rt = cfg.get_runtime().context("Failed to initialize runtime")?;
+    index_blocks(cfg, rt).await.map(|_| ())
+}
+
+/// Indexes the configured block range once, without moving the cursor of the
+/// live indexer or migrating the database it may be running against, and
+/// returns the timestamps of the first and last processed block.
+async fn replay(cfg: &Config) -> Result<Option<RangeInclusive<NaiveDateTime>>> {
+    cfg.init_logging().context("Failed to initialize logging")?;
+    let rt = cfg
+        .get_replay_runtime()
+        .context("Failed to initialize runtime")?;
+    index_blocks(cfg, rt).await
+}
+
+async fn index_blocks(
+    cfg: &Config,
+    rt: MintlakeRuntime,
+) -> Result<Option<RangeInclusive<NaiveDateTime>>> {
+    // stop pulling blocks on SIGTERM/SIGINT, finishing the in-flight block
+    let shutdown = minterop_indexer::listen_for_shutdown()
+        .context("Failed to install signal handlers")?;
+
+    // block source needs to be last to prevent buffer overflows
+    let (handle, streamer) = cfg.connect_blocks();
+
+    minterop_indexer::info!("Connected and ready to index!");
+    rt.handle_stream(streamer, shutdown).await;
+    minterop_indexer::join_lake_handle(handle).await;
+    minterop_indexer::shutdown_tracer();
+    Ok(rt.replayed_range())
+}
+
+async fn dry_run(cfg: &Config) -> Result<()> {
+    cfg.init_logging().context("Failed to initialize logging")?;
+    let (rt, dry_run) = cfg
+        .get_dry_run_runtime()
+        .context("Failed to initialize runtime")?;
+    let shutdown = minterop_indexer::listen_for_shutdown()
+        .context("Failed to install signal handlers")?;
+
+    let (handle, streamer) = cfg.connect_blocks();
+    rt.handle_stream(streamer, shutdown).await;
+    minterop_indexer::join_lake_handle(handle).await;
+    minterop_indexer::shutdown_tracer();
+
+    println!("{}", serde_json::to_string_pretty(&dry_run.report())?);
+    Ok(())
+}
+
+async fn backfill(
+    cfg: &Config,
+    job_id: &str,
+    contracts: &[String],
+    from: u64,
+    to: u64,
+) -> Result<()> {
+    cfg.init_logging().context("Failed to initialize logging")?;
+    cfg.migrate_db().context("Failed to migrate database")?;
+
+    let job = cfg.register_backfill(job_id, contracts, from, to)?;
+    if job.is_finished() {
+        println!("Backfill {} has already finished", job_id);
+        return Ok(());
     }
+    let rt = cfg
+        .get_backfill_runtime(&job)
+        .context("Failed to initialize runtime")?;
+    let shutdown = minterop_indexer::listen_for_shutdown()
+        .context("Failed to install signal handlers")?;
 
-    // get config
-    let cfg = match Config::from_env() {
-        Err(e) => panic!("Failed to load config from environment: {:?}", e),
-        Ok(cfg) => cfg,
-    };
+    let (handle, streamer) = cfg
+        .block_source()
+        .stream(job.resume_height(), Some(job.stop_height));
+    minterop_indexer::info!(
+        "Backfilling {:?} from {} to {}",
+        job.contracts,
+        job.resume_height(),
+        job.stop_height
+    );
+    rt.handle_stream(streamer, shutdown).await;
+    minterop_indexer::join_lake_handle(handle).await;
+    minterop_indexer::shutdown_tracer();
 
-    // initialize all the logging
-    if let Err(e) = cfg.init_logging() {
-        panic!("Failed to initialize logging: {:?}", e);
+    let job = cfg.backfill_job(job_id)?;
+    match job.synced_height {
+        Some(height) if job.is_finished() => {
+            println!("Backfill {} finished at {}", job_id, height)
+        }
+        Some(height) => println!("Backfill {} stopped at {}", job_id, height),
+        None => println!("Backfill {} has not processed any blocks", job_id),
     }
+    Ok(())
+}
 
-    // embedded migrations
-    if let Err(e) = cfg.migrate_db() {
-        panic!("Failed to migrate database: {:?}", e);
+async fn reindex(cfg: &Config, from: u64, to: u64, workers: u64) -> Result<()> {
+    cfg.init_logging().context("Failed to initialize logging")?;
+    cfg.migrate_db().context("Failed to migrate database")?;
+    let shutdown = minterop_indexer::listen_for_shutdown()
+        .context("Failed to install signal handlers")?;
+
+    let jobs =
+        minterop_indexer::reindex(cfg, from, to, workers, shutdown).await?;
+    minterop_indexer::shutdown_tracer();
+
+    let unfinished = jobs.iter().filter(|job| !job.is_finished()).count();
+    if unfinished > 0 {
+        println!(
+            "{} of {} workers stopped early, rerun to resume",
+            unfinished,
+            jobs.len()
+        );
+        return Ok(());
     }
 
-    // database connection
-    let rt = match cfg.get_runtime() {
-        Err(e) => panic!("Failed to initialize runtime: {:?}", e),
-        Ok(rt) => rt,
-    };
+    let fixed = cfg.reconcile(&jobs)?;
+    println!(
+        "Reindexed {} to {}, reconciled {} owners, {} transfers, {} \
+         listings and {} offers",
+        from, to, fixed.owners, fixed.transfers, fixed.listings, fixed.offers
+    );
+    println!(
+        "Expired {} offers, flagged {} sales, refreshed stats of {} \
+         contracts and {} candle hours, recorded terms of {} sales, found {} \
+         payout mismatches",
+        fixed.expired_offers,
+        fixed.flagged_sales,
+        fixed.stats,
+        fixed.candles,
+        fixed.sale_terms,
+        fixed.payout_mismatches
+    );
+    println!("Start the live indexer at {} to continue", to + 1);
+    Ok(())
+}
 
-    // S3 connection needs to be last to prevent buffer overflows
-    let (handle, streamer) = cfg.connect_s3();
+fn holders(
+    cfg: &Config,
+    contracts: &[String],
+    height: u64,
+    format: HoldersFormat,
+) -> Result<()> {
+    let snapshot = cfg.holders(contracts, height)?;
+    if snapshot.holders.is_empty() {
+        eprintln!("No holders of {:?} at {}", contracts, height);
+    }
+    print!("{}", snapshot.format(format)?);
+    Ok(())
+}
 
-    (handle, streamer, rt)
+fn statement(
+    cfg: &Config,
+    account: &str,
+    from: NaiveDate,
+    to: NaiveDate,
+    totals: bool,
+) -> Result<()> {
+    if from >= to {
+        anyhow::bail!("`--from` needs to be before `--to`");
+    }
+    let statement = cfg.statement(account, from, to)?;
+    if statement.lines.is_empty() {
+        eprintln!("No earnings of {} from {} to {}", account, from, to);
+    }
+    if totals {
+        print!("{}", statement.totals_to_csv());
+    } else {
+        print!("{}", statement.to_csv());
+    }
+    Ok(())
 }
 
-#[actix_rt::main]
-async fn main() {
-    let (handle, streamer, rt) = init().await;
-    minterop_indexer::info!("Connected, migrated, and ready to index!");
-    rt.handle_stream(streamer).await;
-    match handle.await {
-        Ok(Ok(())) => {}
-        Ok(Err(e)) => {
-            minterop_indexer::error!("Failed to join lake handle: {:?}", e)
+async fn prices(cfg: &Config, source: &PriceSource) -> Result<()> {
+    cfg.migrate_db().context("Failed to migrate database")?;
+    let imported = cfg.import_prices(source).await?;
+    println!(
+        "Imported {} prices from {}, revalued {} earnings and sales",
+        imported.prices, source, imported.revalued
+    );
+    Ok(())
+}
+
+async fn status(cfg: Config) -> Result<()> {
+    let (synced_height, chain_height) = cfg.sync_status().await?;
+    println!("synced height: {}", synced_height);
+    match chain_height {
+        Some(chain_height) => {
+            println!("chain height:  {}", chain_height);
+            println!(
+                "lag:           {} blocks",
+                chain_height.saturating_sub(synced_height)
+            );
         }
-        Err(e) => {
-            minterop_indexer::error!("Failed to join lake handle: {:?}", e)
+        None => println!("chain height:  unknown (NEAR_RPC_URL not set)"),
+    }
+    Ok(())
+}
+
+async fn snapshot(
+    cfg: &Config,
+    golden: &Path,
+    index: bool,
+    update: bool,
+) -> Result<()> {
+    // only rows of the indexed blocks, which keeps the snapshot independent
+    // of whatever has been in the database before
+    let range = if index { replay(cfg).await? } else { None };
+
+    let snapshot = cfg.snapshot(range.as_ref())?;
+    if update {
+        snapshot.save(golden)?;
+        println!("Updated {}", golden.display());
+        return Ok(());
+    }
+
+    let diff = Snapshot::load(golden)?.diff(&snapshot);
+    if !diff.is_empty() {
+        print!("{}", diff);
+        anyhow::bail!("Snapshot differs from {}", golden.display());
+    }
+    println!("Snapshot matches {}", golden.display());
+    Ok(())
+}
+
+async fn capture(
+    cfg: &Config,
+    stop_height: u64,
+    contracts: &[String],
+    out: &Path,
+) -> Result<()> {
+    cfg.init_logging().context("Failed to initialize logging")?;
+    let shutdown = minterop_indexer::listen_for_shutdown()
+        .context("Failed to install signal handlers")?;
+
+    let (handle, streamer) = cfg.connect_blocks();
+    let summary = minterop_indexer::capture(
+        streamer,
+        stop_height,
+        contracts,
+        out,
+        shutdown,
+    )
+    .await;
+    minterop_indexer::join_lake_handle(handle).await;
+
+    let summary = summary?;
+    println!(
+        "Captured {} receipts in {} blocks to {}",
+        summary.receipts,
+        summary.blocks,
+        out.display()
+    );
+    Ok(())
+}
+
+fn load_config(overrides: &ConfigOverrides) -> Result<Config> {
+    let cfg = Config::from_env_with(overrides)
+        .context("Failed to load config from environment")?;
+    cfg.validate().context("Invalid config")?;
+    Ok(cfg)
+}
+
+async fn execute(cli: Cli) -> Result<()> {
+    let mut overrides = cli.overrides;
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => run(&load_config(&overrides)?).await,
+        Command::Migrate => load_config(&overrides)?
+            .migrate_db()
+            .context("Failed to migrate database"),
+        Command::Replay {
+            from,
+            to,
+            contracts,
+            dry_run,
+        } => {
+            overrides.start_block_height = Some(from);
+            overrides.stop_block_height = Some(to);
+            if let Some(contracts) = contracts {
+                overrides.contract_filter = Some(contracts.join(","));
+            }
+            let cfg = load_config(&overrides)?;
+            if dry_run {
+                self::dry_run(&cfg).await
+            } else {
+                replay(&cfg).await.map(|_| ())
+            }
+        }
+        Command::Backfill {
+            job,
+            from,
+            to,
+            contracts,
+        } => {
+            let cfg = load_config(&overrides)?;
+            backfill(&cfg, &job, &contracts, from, to).await
+        }
+        Command::Reindex { from, to, workers } => {
+            reindex(&load_config(&overrides)?, from, to, workers).await
+        }
+        Command::Holders {
+            height,
+            contracts,
+            format,
+        } => holders(&load_config(&overrides)?, &contracts, height, format),
+        Command::Statement {
+            account,
+            from,
+            to,
+            totals,
+        } => statement(&load_config(&overrides)?, &account, from, to, totals),
+        Command::Prices { source } => {
+            prices(&load_config(&overrides)?, &source).await
+        }
+        Command::Status => status(load_config(&overrides)?).await,
+        Command::Snapshot {
+            golden,
+            from,
+            to,
+            update,
+        } => {
+            overrides.start_block_height =
+                from.or(overrides.start_block_height);
+            overrides.stop_block_height = to.or(overrides.stop_block_height);
+            let cfg = load_config(&overrides)?;
+            snapshot(&cfg, &golden, from.is_some(), update).await
+        }
+        Command::Capture {
+            from,
+            to,
+            contracts,
+            out,
+        } => {
+            overrides.start_block_height = Some(from);
+            overrides.stop_block_height = Some(to);
+            capture(&load_config(&overrides)?, to, &contracts, &out).await
         }
+        Command::CheckConfig => {
+            load_config(&overrides)?.print_summary();
+            println!("Config is valid");
+            Ok(())
+        }
+    }
+}
+
+#[actix_rt::main]
+async fn main() {
+    let cli = Cli::parse();
+
+    // a missing `.env` is fine, everything might be set via environment/flags
+    dotenv::dotenv().ok();
+
+    if let Err(e) = execute(cli).await {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(1);
     }
 }
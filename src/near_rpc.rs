@@ -0,0 +1,189 @@
+use std::str::FromStr;
+
+use anyhow::{
+    anyhow,
+    Result,
+};
+use hyper::{
+    Body,
+    Request,
+};
+use near_lake_framework::near_indexer_primitives::{
+    types::AccountId,
+    views::{
+        BlockView,
+        ChunkView,
+        ExecutionOutcomeWithIdView,
+        ReceiptView,
+        SignedTransactionView,
+    },
+    CryptoHash,
+};
+
+type Client = hyper::Client<
+    hyper_tls::HttpsConnector<hyper::client::HttpConnector>,
+    hyper::Body,
+>;
+
+/// Minimal JSON-RPC client for a NEAR node. Not to be confused with the
+/// `MinteropRpcConnector`, which talks to the minterop-consumer.
+#[derive(Clone)]
+pub(crate) struct NearRpcClient {
+    client: Client,
+    endpoint: hyper::Uri,
+}
+
+impl NearRpcClient {
+    pub fn new(endpoint: &str) -> Result<Self> {
+        let client =
+            hyper::Client::builder().build(hyper_tls::HttpsConnector::new());
+        let endpoint = hyper::Uri::from_str(endpoint)?;
+        Ok(Self { client, endpoint })
+    }
+
+    /// Calls an RPC method and returns the `result` field of the response.
+    pub async fn call(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        match self.request(method, params).await? {
+            Ok(result) => Ok(result),
+            Err(error) => {
+                Err(anyhow!("RPC call `{}` failed: {}", method, error))
+            }
+        }
+    }
+
+    /// Calls an RPC method and returns either the `result` or the `error`
+    /// field of the response.
+    async fn request(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<std::result::Result<serde_json::Value, serde_json::Value>> {
+        let body = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": "minterop",
+            "method": method,
+            "params": params,
+        });
+        let req = Request::post(self.endpoint.clone())
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))?;
+
+        let res = self.client.request(req).await?;
+        let bytes = hyper::body::to_bytes(res.into_body()).await?;
+        let mut res = serde_json::from_slice::<serde_json::Value>(&bytes)?;
+
+        if let Some(error) = res.get_mut("error") {
+            return Ok(Err(error.take()));
+        }
+        match res.get_mut("result") {
+            Some(result) => Ok(Ok(result.take())),
+            None => Err(anyhow!("RPC call `{}` returned no result", method)),
+        }
+    }
+
+    /// Queries the height of the latest final block.
+    pub async fn final_block_height(&self) -> Result<u64> {
+        let block = self
+            .call("block", serde_json::json!({ "finality": "final" }))
+            .await?;
+        block["header"]["height"]
+            .as_u64()
+            .ok_or_else(|| anyhow!("Block without height: {}", block))
+    }
+
+    /// Queries a block by `{ "finality": .. }` or `{ "block_id": .. }`.
+    /// Returns `None` for heights that have been skipped by the chain.
+    pub async fn block(
+        &self,
+        params: serde_json::Value,
+    ) -> Result<Option<BlockView>> {
+        match self.request("block", params).await? {
+            Ok(block) => Ok(Some(serde_json::from_value(block)?)),
+            Err(error) if error["cause"]["name"] == "UNKNOWN_BLOCK" => Ok(None),
+            Err(error) => Err(anyhow!("RPC call `block` failed: {}", error)),
+        }
+    }
+
+    pub async fn chunk(&self, chunk_hash: &CryptoHash) -> Result<ChunkView> {
+        let chunk = self
+            .call("chunk", serde_json::json!({ "chunk_id": chunk_hash }))
+            .await?;
+        Ok(serde_json::from_value(chunk)?)
+    }
+
+    /// Status of a transaction, including the outcomes of all receipts that
+    /// have been executed so far.
+    pub async fn tx_status(
+        &self,
+        tx_hash: &CryptoHash,
+        signer_id: &AccountId,
+    ) -> Result<TxStatusView> {
+        let status = self
+            .call(
+                "EXPERIMENTAL_tx_status",
+                serde_json::json!([tx_hash, signer_id]),
+            )
+            .await?;
+        Ok(serde_json::from_value(status)?)
+    }
+
+    /// Metadata of a fungible token contract, via its `ft_metadata` view
+    /// method.
+    pub async fn ft_metadata(&self, contract_id: &str) -> Result<FtMetadata> {
+        let result = self
+            .call(
+                "query",
+                serde_json::json!({
+                    "request_type": "call_function",
+                    "finality": "final",
+                    "account_id": contract_id,
+                    "method_name": "ft_metadata",
+                    // `{}`
+                    "args_base64": "e30=",
+                }),
+            )
+            .await?;
+        // failing view calls are reported within the result
+        if let Some(error) = result.get("error") {
+            return Err(anyhow!(
+                "`ft_metadata` of {} failed: {}",
+                contract_id,
+                error
+            ));
+        }
+        let bytes =
+            serde_json::from_value::<Vec<u8>>(result["result"].clone())?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+/// The part of NEP-148 metadata needed to normalize amounts
+#[derive(serde::Deserialize, Debug)]
+pub(crate) struct FtMetadata {
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+/// Response of `EXPERIMENTAL_tx_status`. `FinalExecutionOutcomeWithReceiptView`
+/// uses `#[serde(flatten)]`, which doesn't work with `arbitrary_precision`.
+#[derive(serde::Deserialize, Debug)]
+pub(crate) struct TxStatusView {
+    /// `NotStarted` and `Started` are strings, final states are objects
+    pub status: serde_json::Value,
+    pub transaction: SignedTransactionView,
+    pub transaction_outcome: ExecutionOutcomeWithIdView,
+    pub receipts_outcome: Vec<ExecutionOutcomeWithIdView>,
+    #[serde(default)]
+    pub receipts: Vec<ReceiptView>,
+}
+
+impl TxStatusView {
+    /// All receipts have been executed
+    pub fn is_final(&self) -> bool {
+        self.status.is_object()
+    }
+}
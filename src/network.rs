@@ -0,0 +1,108 @@
+use anyhow::{
+    bail,
+    Result,
+};
+
+/// The NEAR network that the indexer runs against. Used to fill in consistent
+/// defaults for everything that differs between networks.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    Mainnet,
+    Testnet,
+}
+
+/// Defaults for a network, each of which can be overridden via config.
+pub(crate) struct NetworkPreset {
+    pub(crate) s3_bucket_name: &'static str,
+    pub(crate) s3_region_name: &'static str,
+    pub(crate) near_rpc_url: &'static str,
+    pub(crate) mintbase_root: &'static str,
+    pub(crate) market_ids: &'static [&'static str],
+    pub(crate) paras_marketplace_id: &'static str,
+}
+
+const MAINNET: NetworkPreset = NetworkPreset {
+    s3_bucket_name: "near-lake-data-mainnet",
+    s3_region_name: "eu-central-1",
+    near_rpc_url: "https://rpc.mainnet.near.org",
+    mintbase_root: "mintbase1.near",
+    market_ids: &["market.mintbase1.near", "simple.market.mintbase1.near"],
+    paras_marketplace_id: "marketplace.paras.near",
+};
+
+const TESTNET: NetworkPreset = NetworkPreset {
+    s3_bucket_name: "near-lake-data-testnet",
+    s3_region_name: "eu-central-1",
+    near_rpc_url: "https://rpc.testnet.near.org",
+    mintbase_root: "mintspace2.testnet",
+    market_ids: &[
+        "market.mintspace2.testnet",
+        "market-v2-beta.mintspace2.testnet",
+    ],
+    paras_marketplace_id: "paras-marketplace-v2.testnet",
+};
+
+impl Network {
+    pub(crate) fn preset(&self) -> &'static NetworkPreset {
+        match self {
+            Network::Mainnet => &MAINNET,
+            Network::Testnet => &TESTNET,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+        }
+    }
+
+    /// Top-level account of the network, which all named accounts end with.
+    fn top_level_account(&self) -> &'static str {
+        match self {
+            Network::Mainnet => ".near",
+            Network::Testnet => ".testnet",
+        }
+    }
+
+    fn other(&self) -> Network {
+        match self {
+            Network::Mainnet => Network::Testnet,
+            Network::Testnet => Network::Mainnet,
+        }
+    }
+
+    /// Rejects named accounts of the other network. Implicit (hex) accounts
+    /// cannot be attributed to a network and are always accepted.
+    pub(crate) fn check_account_id(&self, field: &str, id: &str) -> Result<()> {
+        if id.ends_with(self.other().top_level_account()) {
+            bail!(
+                "{} ({}) is a {} account, but NETWORK is {}",
+                field,
+                id,
+                self.other().name(),
+                self.name()
+            );
+        }
+        Ok(())
+    }
+
+    /// Rejects lake buckets and RPC endpoints of the other network.
+    pub(crate) fn check_resource(
+        &self,
+        field: &str,
+        value: &str,
+    ) -> Result<()> {
+        if value.contains(self.other().name()) {
+            bail!(
+                "{} ({}) refers to {}, but NETWORK is {}",
+                field,
+                value,
+                self.other().name(),
+                self.name()
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    path::PathBuf,
+    str::FromStr,
+};
+
+use anyhow::{
+    anyhow,
+    bail,
+    Context,
+    Result,
+};
+use chrono::NaiveDateTime;
+use diesel::{
+    sql_types::{
+        Text,
+        Timestamp,
+    },
+    Connection,
+    PgConnection,
+    RunQueryDsl,
+};
+
+/// Where USD prices of currencies are imported from. Anything that starts with
+/// `http://` or `https://` is fetched, everything else is read as a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PriceSource {
+    /// CSV file with a `currency,timestamp,usd_price` header
+    Csv(PathBuf),
+    /// Endpoint serving a JSON array of objects with the same fields
+    Http(String),
+}
+
+impl FromStr for PriceSource {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(PriceSource::Http(s.to_string()))
+        } else {
+            Ok(PriceSource::Csv(PathBuf::from(s)))
+        }
+    }
+}
+
+impl std::fmt::Display for PriceSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PriceSource::Csv(path) => write!(f, "{}", path.display()),
+            PriceSource::Http(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+/// USD price of a currency (`near` or `ft::<contract>`) at a time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PricePoint {
+    pub currency: String,
+    pub timestamp: NaiveDateTime,
+    /// Decimal string, kept as such to not lose precision
+    pub usd_price: String,
+}
+
+/// Outcome of an import
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PriceImport {
+    pub prices: u64,
+    /// Earnings and sales whose USD value has been recomputed
+    pub revalued: u64,
+}
+
+impl PriceSource {
+    pub async fn fetch(&self) -> Result<Vec<PricePoint>> {
+        match self {
+            PriceSource::Csv(path) => {
+                let content = tokio::fs::read_to_string(path)
+                    .await
+                    .with_context(|| {
+                        format!("Failed to read prices from {}", path.display())
+                    })?;
+                parse_csv(&content)
+            }
+            PriceSource::Http(url) => {
+                let bytes = fetch_http(url)
+                    .await
+                    .with_context(|| format!("Failed to fetch {}", url))?;
+                parse_json(&bytes)
+            }
+        }
+    }
+}
+
+async fn fetch_http(url: &str) -> Result<hyper::body::Bytes> {
+    let client =
+        hyper::Client::builder().build(hyper_tls::HttpsConnector::new());
+    let res = client.get(hyper::Uri::from_str(url)?).await?;
+    if !res.status().is_success() {
+        bail!("Price feed responded with {}", res.status());
+    }
+    Ok(hyper::body::to_bytes(res.into_body()).await?)
+}
+
+fn parse_csv(content: &str) -> Result<Vec<PricePoint>> {
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    match lines.next() {
+        Some((_, header))
+            if header.trim() == "currency,timestamp,usd_price" => {}
+        _ => bail!("Prices need a `currency,timestamp,usd_price` header"),
+    }
+
+    lines
+        .map(|(i, line)| {
+            let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+            let point = match fields.as_slice() {
+                [currency, timestamp, usd_price] => {
+                    price_point(currency, timestamp, usd_price)
+                }
+                _ => Err(anyhow!("Expected 3 fields")),
+            };
+            point.with_context(|| format!("Invalid price on line {}", i + 1))
+        })
+        .collect()
+}
+
+fn parse_json(bytes: &[u8]) -> Result<Vec<PricePoint>> {
+    #[derive(serde::Deserialize)]
+    struct JsonPricePoint {
+        currency: String,
+        timestamp: String,
+        /// Number or string
+        usd_price: serde_json::Value,
+    }
+
+    let points = serde_json::from_slice::<Vec<JsonPricePoint>>(bytes)
+        .context("Prices need to be a JSON array")?;
+    points
+        .into_iter()
+        .enumerate()
+        .map(|(i, point)| {
+            let usd_price = match point.usd_price {
+                serde_json::Value::String(s) => s,
+                serde_json::Value::Number(n) => n.to_string(),
+                other => other.to_string(),
+            };
+            price_point(&point.currency, &point.timestamp, &usd_price)
+                .with_context(|| format!("Invalid price at index {}", i))
+        })
+        .collect()
+}
+
+fn price_point(
+    currency: &str,
+    timestamp: &str,
+    usd_price: &str,
+) -> Result<PricePoint> {
+    if !crate::currencies::is_valid_currency(currency) {
+        bail!(
+            "Currency needs to be `near` or `ft::<contract>`: {}",
+            currency
+        );
+    }
+    if !is_decimal(usd_price) {
+        bail!("Price needs to be a non-negative decimal: {}", usd_price);
+    }
+    Ok(PricePoint {
+        currency: currency.to_string(),
+        timestamp: parse_timestamp(timestamp)?,
+        usd_price: usd_price.to_string(),
+    })
+}
+
+/// RFC 3339, or the same without offset for UTC
+fn parse_timestamp(s: &str) -> Result<NaiveDateTime> {
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|t| t.naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f"))
+        .with_context(|| format!("Invalid timestamp: {}", s))
+}
+
+fn is_decimal(s: &str) -> bool {
+    let (int, frac) = s.split_once('.').unwrap_or((s, "0"));
+    !int.is_empty()
+        && !frac.is_empty()
+        && int.chars().chain(frac.chars()).all(|c| c.is_ascii_digit())
+}
+
+/// Upserts the prices, and revalues the earnings and sales that they apply
+/// to, all in one transaction.
+pub(crate) fn import(
+    pg_string: &str,
+    source: &str,
+    points: &[PricePoint],
+) -> Result<PriceImport> {
+    // time range of the imported prices per currency
+    let mut ranges = BTreeMap::<&str, (NaiveDateTime, NaiveDateTime)>::new();
+    for point in points {
+        let range = ranges
+            .entry(&point.currency)
+            .or_insert((point.timestamp, point.timestamp));
+        range.0 = range.0.min(point.timestamp);
+        range.1 = range.1.max(point.timestamp);
+    }
+
+    let conn = PgConnection::establish(pg_string)?;
+    conn.transaction(|| {
+        for point in points {
+            diesel::sql_query(
+                "INSERT INTO currency_prices \
+                 (currency, timestamp, usd_price, source) \
+                 VALUES ($1, $2, $3::NUMERIC, $4) \
+                 ON CONFLICT (currency, timestamp) DO UPDATE \
+                 SET usd_price = excluded.usd_price, source = excluded.source",
+            )
+            .bind::<Text, _>(&point.currency)
+            .bind::<Timestamp, _>(point.timestamp)
+            .bind::<Text, _>(&point.usd_price)
+            .bind::<Text, _>(source)
+            .execute(&conn)
+            .with_context(|| {
+                format!(
+                    "Failed to insert price of {} at {}",
+                    point.currency, point.timestamp
+                )
+            })?;
+        }
+
+        let mut revalued = 0;
+        for (currency, (since, until)) in ranges {
+            revalued += diesel::select(crate::schema::revalue_in_usd(
+                currency, since, until,
+            ))
+            .get_result::<i64>(&conn)
+            .with_context(|| format!("Failed to revalue {}", currency))?;
+        }
+
+        Ok(PriceImport {
+            prices: points.len() as u64,
+            revalued: revalued as u64,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(h: u32, m: u32) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2022, 8, 1)
+            .unwrap()
+            .and_hms_opt(h, m, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let csv = "currency,timestamp,usd_price\n\
+                   near,2022-08-01T12:00:00Z,2.5\n\
+                   \n\
+                   ft::usdc.near,2022-08-01T13:00:00,1\n";
+        assert_eq!(
+            parse_csv(csv).unwrap(),
+            vec![
+                PricePoint {
+                    currency: "near".to_string(),
+                    timestamp: at(12, 0),
+                    usd_price: "2.5".to_string(),
+                },
+                PricePoint {
+                    currency: "ft::usdc.near".to_string(),
+                    timestamp: at(13, 0),
+                    usd_price: "1".to_string(),
+                },
+            ]
+        );
+
+        let err =
+            parse_csv("currency,timestamp,usd_price\nnear,x,1\n").unwrap_err();
+        assert!(format!("{:#}", err).contains("line 2"));
+        assert!(parse_csv("near,2022-08-01T12:00:00,1\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let json = br#"[
+            {"currency": "near", "timestamp": "2022-08-01T12:00:00+00:00",
+             "usd_price": 2.5},
+            {"currency": "near", "timestamp": "2022-08-01T14:00:00+02:00",
+             "usd_price": "3.25"}
+        ]"#;
+        let points = parse_json(json).unwrap();
+        assert_eq!(points[0].usd_price, "2.5");
+        assert_eq!(points[1].timestamp, at(12, 0));
+        assert_eq!(points[1].usd_price, "3.25");
+    }
+
+    #[test]
+    fn test_is_decimal() {
+        assert!(is_decimal("1"));
+        assert!(is_decimal("0.0001"));
+        assert!(!is_decimal("-1"));
+        assert!(!is_decimal("1."));
+        assert!(!is_decimal("1e3"));
+        assert!(!is_decimal("1; DROP TABLE currency_prices"));
+    }
+
+    #[test]
+    fn test_price_source_from_str() {
+        assert_eq!(
+            "https://prices.example.com/near".parse::<PriceSource>(),
+            Ok(PriceSource::Http("https://prices.example.com/near".into()))
+        );
+        assert_eq!(
+            "prices.csv".parse::<PriceSource>(),
+            Ok(PriceSource::Csv(PathBuf::from("prices.csv")))
+        );
+    }
+}
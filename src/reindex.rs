@@ -0,0 +1,595 @@
+use std::collections::BTreeSet;
+
+use anyhow::{
+    Context,
+    Result,
+};
+use chrono::{
+    Duration,
+    NaiveDateTime,
+};
+use diesel::{
+    sql_types::{
+        Array,
+        BigInt,
+        Text,
+        Timestamp,
+    },
+    Connection,
+    PgConnection,
+    RunQueryDsl,
+};
+use minterop_data::db_rows::{
+    NFT_ACTIVITY_KIND_BURN,
+    NFT_ACTIVITY_KIND_LIST,
+    NFT_ACTIVITY_KIND_MINT,
+    NFT_ACTIVITY_KIND_REVOKE,
+    NFT_ACTIVITY_KIND_REVOKE_ALL,
+    NFT_ACTIVITY_KIND_SOLD,
+    NFT_ACTIVITY_KIND_TRANSFER,
+    NFT_ACTIVITY_KIND_UNLIST,
+};
+
+use crate::{
+    shutdown::ShutdownSignal,
+    BackfillJob,
+    Config,
+};
+
+/// Kind of the activities that `expire_nft_offers` records
+const NFT_ACTIVITY_KIND_EXPIRE_OFFER: &str = "expire_offer";
+/// Tokens, contracts or sales that `reconcile` handles per statement
+const RECONCILE_BATCH_SIZE: usize = 1000;
+/// How far wash-trade flags look back, see `flag_nft_sale`
+const FLAG_LOOKBACK_DAYS: i64 = 7;
+
+/// Rows changed by `reconcile`, and rollups it recomputed
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Reconciliation {
+    pub owners: usize,
+    pub transfers: usize,
+    pub listings: usize,
+    pub offers: usize,
+    pub expired_offers: i64,
+    pub flagged_sales: i64,
+    pub stats: i64,
+    pub candles: i64,
+    pub sale_terms: i64,
+    pub payout_mismatches: i64,
+}
+
+#[derive(QueryableByName)]
+struct ScopedToken {
+    #[sql_type = "Text"]
+    nft_contract_id: String,
+    #[sql_type = "Text"]
+    token_id: String,
+}
+
+#[derive(QueryableByName)]
+struct ScopedSale {
+    #[sql_type = "Text"]
+    receipt_id: String,
+    #[sql_type = "Timestamp"]
+    timestamp: NaiveDateTime,
+}
+
+#[derive(QueryableByName)]
+struct RollupCount {
+    #[sql_type = "BigInt"]
+    n: i64,
+}
+
+/// Indexes `from..=to` with `workers` lake streamers in parallel, each working
+/// on its own part of the range. Every part is a backfill job for all
+/// contracts, such that an interrupted reindex resumes where each worker
+/// stopped when run again with the same range and number of workers.
+///
+/// Returns the jobs of all parts. Once all of them are finished, `reconcile`
+/// needs to run to fix state that depends on the order of blocks.
+pub async fn reindex(
+    cfg: &Config,
+    from: u64,
+    to: u64,
+    workers: u64,
+    shutdown: ShutdownSignal,
+) -> Result<Vec<BackfillJob>> {
+    anyhow::ensure!(from <= to, "Invalid range {}..={}", from, to);
+    let parts = split_range(from, to, workers);
+
+    // one pool for all workers, which is sized for a single runtime
+    let pool = cfg.db_pool(false);
+    let mut runs = Vec::new();
+    for (start, stop) in parts.iter().copied() {
+        let job = cfg.register_backfill(
+            &worker_job_id(start, stop),
+            &[],
+            start,
+            stop,
+        )?;
+        if job.is_finished() {
+            crate::info!("Reindex of {}..={} already finished", start, stop);
+            continue;
+        }
+        let rt = cfg.backfill_runtime_with(&job, pool.clone())?;
+        runs.push((job, rt));
+    }
+
+    futures::future::join_all(runs.iter().map(|(job, rt)| {
+        let shutdown = shutdown.clone();
+        async move {
+            crate::info!(
+                "Reindexing {}..={}",
+                job.resume_height(),
+                job.stop_height
+            );
+            let (handle, streamer) = cfg
+                .block_source()
+                .stream(job.resume_height(), Some(job.stop_height));
+            rt.handle_stream(streamer, shutdown).await;
+            crate::join_lake_handle(handle).await;
+        }
+    }))
+    .await;
+
+    parts
+        .iter()
+        .map(|(start, stop)| cfg.backfill_job(&worker_job_id(*start, *stop)))
+        .collect()
+}
+
+fn worker_job_id(start: u64, stop: u64) -> String {
+    format!("reindex-{}-{}", start, stop)
+}
+
+/// Splits `from..=to` into at most `parts` contiguous ranges of (almost)
+/// equal size. The range must not be empty.
+fn split_range(from: u64, to: u64, parts: u64) -> Vec<(u64, u64)> {
+    let len = to - from + 1;
+    let parts = parts.clamp(1, len);
+    let (size, rest) = (len / parts, len % parts);
+
+    let mut ranges = Vec::with_capacity(parts as usize);
+    let mut start = from;
+    for i in 0..parts {
+        // the first parts take one block of the remainder each
+        let stop = start + size - 1 + u64::from(i < rest);
+        ranges.push((start, stop));
+        start = stop + 1;
+    }
+    ranges
+}
+
+/// Fixes state that is only correct if blocks are processed in order, by
+/// deriving it from `nft_activities`:
+///
+/// - The owner of a token is the receiver of its latest mint or transfer,
+///   and the last transfer is the latest transfer.
+/// - A listing that is still active has ended with the first event after its
+///   creation that ends listings: A transfer, burn or revoke on the token
+///   invalidates it, as does a new listing by the same market. An unlist by
+///   the same market unlists it.
+/// - The same events invalidate offers, but only those made before. Offers
+///   invalidated before they have been made are active again, and offers
+///   that have been invalidated before they expired are not expired.
+///
+/// Events within the same block are ordered by receipt ID, as the indexer
+/// doesn't know their order within the block either.
+///
+/// Afterwards, the rollups touched by the workers are recomputed, as they
+/// refreshed them with partial data: Offer expiry as of the latest activity,
+/// wash-trade flags, statistics, price candles, sale terms and payout
+/// verifications.
+///
+/// Only tokens with activities in the timestamp range synced by `jobs` are
+/// reconciled, together with their contracts and sales since the start of the
+/// range. The work is split into batches of `RECONCILE_BATCH_SIZE`, each in
+/// its own transaction.
+pub(crate) fn reconcile(
+    pg_string: &str,
+    jobs: &[BackfillJob],
+) -> Result<Reconciliation> {
+    let mut fixed = Reconciliation::default();
+    let (since, until) = match reindexed_range(jobs) {
+        Some(range) => range,
+        None => return Ok(fixed),
+    };
+    let conn = PgConnection::establish(pg_string)?;
+
+    let tokens = diesel::sql_query(
+        "SELECT DISTINCT nft_contract_id, token_id FROM nft_activities
+         WHERE timestamp BETWEEN $1 AND $2",
+    )
+    .bind::<Timestamp, _>(since)
+    .bind::<Timestamp, _>(until)
+    .load::<ScopedToken>(&conn)
+    .context("Failed to query reindexed tokens")?;
+
+    let mut sales = Vec::new();
+    for batch in tokens.chunks(RECONCILE_BATCH_SIZE) {
+        let contracts = batch
+            .iter()
+            .map(|token| token.nft_contract_id.clone())
+            .collect::<Vec<_>>();
+        let token_ids = batch
+            .iter()
+            .map(|token| token.token_id.clone())
+            .collect::<Vec<_>>();
+        let execute = |query: &str, msg: &'static str| {
+            diesel::sql_query(query)
+                .bind::<Array<Text>, _>(&contracts)
+                .bind::<Array<Text>, _>(&token_ids)
+                .execute(&conn)
+                .context(msg)
+        };
+
+        conn.transaction::<_, anyhow::Error, _>(|| {
+            fixed.owners += execute(
+                &reconcile_owners_query(),
+                "Failed to reconcile token owners",
+            )?;
+            fixed.transfers += execute(
+                &reconcile_transfers_query(),
+                "Failed to reconcile last transfers",
+            )?;
+            fixed.listings += execute(
+                &reconcile_listings_query(),
+                "Failed to reconcile listings",
+            )?;
+            fixed.offers += execute(
+                &reactivate_offers_query(),
+                "Failed to reactivate offers",
+            )? + execute(
+                &reconcile_offers_query(),
+                "Failed to reconcile offers",
+            )?;
+            execute(
+                &remove_expiries_query(),
+                "Failed to remove expiries of invalidated offers",
+            )?;
+            Ok(())
+        })?;
+
+        sales.extend(
+            diesel::sql_query(scoped_sales_query())
+                .bind::<Array<Text>, _>(&contracts)
+                .bind::<Array<Text>, _>(&token_ids)
+                .bind::<Timestamp, _>(since)
+                .load::<ScopedSale>(&conn)
+                .context("Failed to query reindexed sales")?,
+        );
+    }
+
+    let contracts = tokens
+        .iter()
+        .map(|token| token.nft_contract_id.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    let receipts = |until: Option<NaiveDateTime>| {
+        sales
+            .iter()
+            .filter(|sale| until.map_or(true, |until| sale.timestamp <= until))
+            .map(|sale| sale.receipt_id.clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>()
+    };
+    // round trips look back a week, so later sales may depend on the range
+    let flagged = receipts(Some(until + Duration::days(FLAG_LOOKBACK_DAYS)));
+    let sold = receipts(None);
+
+    // flags first, as the statistics exclude flagged sales
+    fixed.expired_offers = rollup(
+        &conn,
+        &expire_offers_query(),
+        &contracts,
+        "Failed to expire offers",
+    )?;
+    fixed.flagged_sales =
+        rollup(&conn, &flag_sales_query(), &flagged, "Failed to flag sales")?;
+    fixed.stats = rollup(
+        &conn,
+        &refresh_stats_query(),
+        &contracts,
+        "Failed to refresh stats",
+    )?;
+    fixed.candles = rollup(
+        &conn,
+        &refresh_candles_query(),
+        &flagged,
+        "Failed to refresh candles",
+    )?;
+    fixed.sale_terms = rollup(
+        &conn,
+        &record_sale_terms_query(),
+        &sold,
+        "Failed to record sale terms",
+    )?;
+    fixed.payout_mismatches = rollup(
+        &conn,
+        &verify_payouts_query(),
+        &sold,
+        "Failed to verify payouts",
+    )?;
+    Ok(fixed)
+}
+
+/// Timestamps of the first and last block synced by any of `jobs`
+fn reindexed_range(
+    jobs: &[BackfillJob],
+) -> Option<(NaiveDateTime, NaiveDateTime)> {
+    let since = jobs.iter().filter_map(|job| job.first_timestamp).min()?;
+    let until = jobs.iter().filter_map(|job| job.synced_timestamp).max()?;
+    Some((since, until))
+}
+
+/// Runs `query`, which counts as `n` what it did for the values bound as
+/// `$1`, on batches of `values` and sums up the counts
+fn rollup(
+    conn: &PgConnection,
+    query: &str,
+    values: &[String],
+    msg: &'static str,
+) -> Result<i64> {
+    let mut total = 0;
+    for batch in values.chunks(RECONCILE_BATCH_SIZE) {
+        total += diesel::sql_query(query)
+            .bind::<Array<Text>, _>(batch)
+            .get_result::<RollupCount>(conn)
+            .context(msg)?
+            .n;
+    }
+    Ok(total)
+}
+
+/// Tokens of the batch, bound as `$1` and `$2`
+const SCOPE: &str = "WITH scope AS (
+   SELECT * FROM unnest($1::TEXT[], $2::TEXT[])
+     AS s(nft_contract_id, token_id)
+ )";
+
+fn reconcile_owners_query() -> String {
+    format!(
+        "{scope}
+         UPDATE nft_tokens t SET owner = latest.action_receiver
+         FROM (
+           SELECT DISTINCT ON (a.nft_contract_id, a.token_id)
+             a.nft_contract_id, a.token_id, a.action_receiver
+           FROM nft_activities a
+           JOIN scope s
+             ON s.nft_contract_id = a.nft_contract_id
+             AND s.token_id = a.token_id
+           WHERE a.kind IN ('{mint}', '{transfer}')
+             AND a.action_receiver IS NOT NULL
+           ORDER BY a.nft_contract_id, a.token_id, a.timestamp DESC,
+             a.kind = '{mint}', a.receipt_id DESC
+         ) latest
+         WHERE t.nft_contract_id = latest.nft_contract_id
+           AND t.token_id = latest.token_id
+           AND t.owner IS DISTINCT FROM latest.action_receiver",
+        scope = SCOPE,
+        mint = NFT_ACTIVITY_KIND_MINT,
+        transfer = NFT_ACTIVITY_KIND_TRANSFER,
+    )
+}
+
+fn reconcile_transfers_query() -> String {
+    format!(
+        "{scope}
+         UPDATE nft_tokens t SET
+           last_transfer_timestamp = latest.timestamp,
+           last_transfer_receipt_id = latest.receipt_id
+         FROM (
+           SELECT DISTINCT ON (a.nft_contract_id, a.token_id)
+             a.nft_contract_id, a.token_id, a.timestamp, a.receipt_id
+           FROM nft_activities a
+           JOIN scope s
+             ON s.nft_contract_id = a.nft_contract_id
+             AND s.token_id = a.token_id
+           WHERE a.kind = '{transfer}'
+           ORDER BY a.nft_contract_id, a.token_id, a.timestamp DESC,
+             a.receipt_id DESC
+         ) latest
+         WHERE t.nft_contract_id = latest.nft_contract_id
+           AND t.token_id = latest.token_id
+           AND t.last_transfer_receipt_id
+             IS DISTINCT FROM latest.receipt_id",
+        scope = SCOPE,
+        transfer = NFT_ACTIVITY_KIND_TRANSFER,
+    )
+}
+
+fn reconcile_listings_query() -> String {
+    format!(
+        "{scope}
+         UPDATE nft_listings l SET
+           unlisted_at = CASE WHEN ended.kind = '{unlist}'
+             THEN ended.timestamp END,
+           invalidated_at = CASE WHEN ended.kind <> '{unlist}'
+             THEN ended.timestamp END
+         FROM (
+           SELECT DISTINCT ON (
+             l.nft_contract_id, l.token_id, l.market_id, l.approval_id
+           )
+             l.nft_contract_id, l.token_id, l.market_id, l.approval_id,
+             a.kind, a.timestamp
+           FROM nft_listings l
+           JOIN scope s
+             ON s.nft_contract_id = l.nft_contract_id
+             AND s.token_id = l.token_id
+           JOIN nft_activities a
+             ON a.nft_contract_id = l.nft_contract_id
+             AND a.token_id = l.token_id
+             AND a.timestamp > l.created_at
+           WHERE l.accepted_at IS NULL
+             AND l.unlisted_at IS NULL
+             AND l.invalidated_at IS NULL
+             AND (
+               a.kind IN ('{transfer}', '{burn}', '{revoke}', '{revoke_all}')
+               OR (
+                 a.kind IN ('{list}', '{unlist}')
+                 AND a.action_receiver = l.market_id
+               )
+             )
+           ORDER BY l.nft_contract_id, l.token_id, l.market_id,
+             l.approval_id, a.timestamp, a.receipt_id
+         ) ended
+         WHERE l.nft_contract_id = ended.nft_contract_id
+           AND l.token_id = ended.token_id
+           AND l.market_id = ended.market_id
+           AND l.approval_id = ended.approval_id",
+        scope = SCOPE,
+        transfer = NFT_ACTIVITY_KIND_TRANSFER,
+        burn = NFT_ACTIVITY_KIND_BURN,
+        revoke = NFT_ACTIVITY_KIND_REVOKE,
+        revoke_all = NFT_ACTIVITY_KIND_REVOKE_ALL,
+        list = NFT_ACTIVITY_KIND_LIST,
+        unlist = NFT_ACTIVITY_KIND_UNLIST,
+    )
+}
+
+fn reactivate_offers_query() -> String {
+    format!(
+        "{scope}
+         UPDATE nft_offers o SET invalidated_at = NULL
+         FROM scope s
+         WHERE s.nft_contract_id = o.nft_contract_id
+           AND s.token_id = o.token_id
+           AND o.invalidated_at < o.offered_at",
+        scope = SCOPE,
+    )
+}
+
+fn reconcile_offers_query() -> String {
+    // offers have no natural key that covers all market versions
+    format!(
+        "{scope}
+         UPDATE nft_offers o SET
+           invalidated_at = ended.timestamp,
+           expired_at = NULL
+         FROM (
+           SELECT DISTINCT ON (o.ctid) o.ctid AS offer, a.timestamp
+           FROM nft_offers o
+           JOIN scope s
+             ON s.nft_contract_id = o.nft_contract_id
+             AND s.token_id = o.token_id
+           JOIN nft_activities a
+             ON a.nft_contract_id = o.nft_contract_id
+             AND a.token_id = o.token_id
+             AND a.timestamp > o.offered_at
+           WHERE o.accepted_at IS NULL
+             AND o.withdrawn_at IS NULL
+             AND o.outbid_at IS NULL
+             AND o.invalidated_at IS NULL
+             AND (o.expired_at IS NULL OR a.timestamp < o.expired_at)
+             AND (
+               a.kind IN ('{transfer}', '{burn}', '{revoke}', '{revoke_all}')
+               OR (a.kind = '{list}' AND a.action_receiver = o.market_id)
+             )
+           ORDER BY o.ctid, a.timestamp, a.receipt_id
+         ) ended
+         WHERE o.ctid = ended.offer",
+        scope = SCOPE,
+        transfer = NFT_ACTIVITY_KIND_TRANSFER,
+        burn = NFT_ACTIVITY_KIND_BURN,
+        revoke = NFT_ACTIVITY_KIND_REVOKE,
+        revoke_all = NFT_ACTIVITY_KIND_REVOKE_ALL,
+        list = NFT_ACTIVITY_KIND_LIST,
+    )
+}
+
+fn remove_expiries_query() -> String {
+    format!(
+        "{scope}
+         DELETE FROM nft_activities a
+         USING scope s
+         WHERE s.nft_contract_id = a.nft_contract_id
+           AND s.token_id = a.token_id
+           AND a.kind = '{expire_offer}'
+           AND NOT EXISTS (
+             SELECT 1 FROM nft_offers o
+             WHERE o.receipt_id = a.receipt_id AND o.expired_at IS NOT NULL
+           )",
+        scope = SCOPE,
+        expire_offer = NFT_ACTIVITY_KIND_EXPIRE_OFFER,
+    )
+}
+
+/// Sales of the tokens in the batch since `$3`
+fn scoped_sales_query() -> String {
+    format!(
+        "{scope}
+         SELECT DISTINCT a.receipt_id, a.timestamp
+         FROM nft_activities a
+         JOIN scope s
+           ON s.nft_contract_id = a.nft_contract_id
+           AND s.token_id = a.token_id
+         WHERE a.kind = '{sale}' AND a.timestamp >= $3",
+        scope = SCOPE,
+        sale = NFT_ACTIVITY_KIND_SOLD,
+    )
+}
+
+fn expire_offers_query() -> String {
+    "SELECT expire_nft_offers(
+       (SELECT max(timestamp) FROM nft_activities), $1
+     )::BIGINT AS n"
+        .to_string()
+}
+
+fn flag_sales_query() -> String {
+    "SELECT coalesce(sum(flag_nft_sale(receipt_id)), 0)::BIGINT AS n
+     FROM unnest($1::TEXT[]) AS s(receipt_id)"
+        .to_string()
+}
+
+fn refresh_stats_query() -> String {
+    "SELECT count(refresh_nft_stats(nft_contract_id, as_of)) AS n
+     FROM (
+       SELECT nft_contract_id, max(timestamp) AS as_of
+       FROM nft_activities
+       WHERE nft_contract_id = ANY($1)
+       GROUP BY nft_contract_id
+     ) c"
+    .to_string()
+}
+
+fn refresh_candles_query() -> String {
+    format!(
+        "SELECT count(refresh_nft_candles(nft_contract_id, hour)) AS n
+         FROM (
+           SELECT DISTINCT
+             nft_contract_id, date_trunc('hour', timestamp) AS hour
+           FROM nft_activities
+           WHERE kind = '{sale}' AND receipt_id = ANY($1)
+         ) h",
+        sale = NFT_ACTIVITY_KIND_SOLD,
+    )
+}
+
+fn record_sale_terms_query() -> String {
+    "SELECT coalesce(sum(record_nft_sale_terms(receipt_id)), 0)::BIGINT AS n
+     FROM unnest($1::TEXT[]) AS s(receipt_id)"
+        .to_string()
+}
+
+fn verify_payouts_query() -> String {
+    "SELECT coalesce(sum(verify_nft_payout(receipt_id)), 0)::BIGINT AS n
+     FROM unnest($1::TEXT[]) AS s(receipt_id)"
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_range() {
+        assert_eq!(split_range(10, 19, 2), vec![(10, 14), (15, 19)]);
+        assert_eq!(split_range(10, 20, 3), vec![(10, 13), (14, 17), (18, 20)]);
+        // never more parts than blocks
+        assert_eq!(split_range(10, 11, 4), vec![(10, 10), (11, 11)]);
+        assert_eq!(split_range(10, 10, 0), vec![(10, 10)]);
+    }
+}
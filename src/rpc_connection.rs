@@ -1,4 +1,15 @@
-use std::str::FromStr;
+use std::{
+    future::Future,
+    str::FromStr,
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+    },
+    time::Duration,
+};
 
 use anyhow::Result;
 use hyper::{
@@ -16,6 +27,27 @@ type Client = hyper::Client<
 pub(crate) struct MinteropRpcConnector {
     client: Client,
     endpoint: hyper::Uri,
+    pending: Arc<PendingDispatches>,
+    /// Records messages instead of sending them
+    dry_run: Option<crate::DryRun>,
+}
+
+/// Counts detached dispatches, such that they can be awaited on shutdown.
+#[derive(Default)]
+struct PendingDispatches {
+    count: AtomicUsize,
+    done: tokio::sync::Notify,
+}
+
+/// Decrements the pending count when dropped, even if the dispatch panics.
+struct PendingGuard(Arc<PendingDispatches>);
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.done.notify_waiters();
+        }
+    }
 }
 
 impl MinteropRpcConnector {
@@ -23,20 +55,89 @@ impl MinteropRpcConnector {
         let client =
             hyper::Client::builder().build(hyper_tls::HttpsConnector::new());
         let endpoint = hyper::Uri::from_str(endpoint)?;
-        Ok(Self { client, endpoint })
+        Ok(Self {
+            client,
+            endpoint,
+            pending: Default::default(),
+            dry_run: None,
+        })
+    }
+
+    /// Records all messages to `dry_run` instead of sending them
+    pub fn with_dry_run(self, dry_run: crate::DryRun) -> Self {
+        Self {
+            dry_run: Some(dry_run),
+            ..self
+        }
+    }
+
+    /// Returns `true` if the message has been recorded by a dry run, and must
+    /// not be sent
+    fn record_dry_run(&self, method: &str, msg: &RpcMessage) -> bool {
+        match &self.dry_run {
+            Some(dry_run) => {
+                dry_run.record_rpc(method, msg);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Runs a request in the background, without blocking transaction
+    /// processing. Unlike a plain spawn, the request is tracked and will be
+    /// awaited by `flush`.
+    pub fn dispatch<F>(&self, request: F)
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        self.pending.count.fetch_add(1, Ordering::SeqCst);
+        let guard = PendingGuard(self.pending.clone());
+        actix_rt::spawn(async move {
+            request.await;
+            drop(guard);
+        });
     }
 
+    /// Waits for all dispatched requests to finish, giving up after `timeout`.
+    pub async fn flush(&self, timeout: Duration) {
+        let all_done = async {
+            loop {
+                // registering before checking, so no notification is missed
+                let notified = self.pending.done.notified();
+                if self.pending.count.load(Ordering::SeqCst) == 0 {
+                    return;
+                }
+                notified.await;
+            }
+        };
+
+        let pending = self.pending.count.load(Ordering::SeqCst);
+        if pending > 0 {
+            crate::info!("Flushing {} pending RPC dispatches", pending);
+        }
+        if tokio::time::timeout(timeout, all_done).await.is_err() {
+            crate::warn!(
+                "Dropping {} RPC dispatches that did not finish within {:?}",
+                self.pending.count.load(Ordering::SeqCst),
+                timeout
+            );
+        }
+    }
+
+    #[tracing::instrument(target = "minterop", skip(self))]
     pub async fn contract(&self, contract_id: String, refresh: bool) {
-        let req = post_json(
-            &self.endpoint.to_string(),
-            &RpcMessage::HandleContractPayload {
-                contract_id: contract_id.clone(),
-                refresh: Some(refresh),
-            },
-        );
+        let msg = RpcMessage::HandleContractPayload {
+            contract_id: contract_id.clone(),
+            refresh: Some(refresh),
+        };
+        if self.record_dry_run("contract", &msg) {
+            return;
+        }
+
+        let req = post_json(&self.endpoint.to_string(), &msg);
 
         crate::debug!("req: {:?}", req);
-        let res = self.client.request(req).await;
+        let res = self.request(req).await;
         crate::debug!("res: {:?}", res);
 
         if let Err(e) = res {
@@ -48,6 +149,7 @@ impl MinteropRpcConnector {
         }
     }
 
+    #[tracing::instrument(target = "minterop", skip(self))]
     pub async fn token(
         &self,
         contract_id: String,
@@ -55,18 +157,20 @@ impl MinteropRpcConnector {
         minter: Option<String>,
         refresh: Option<bool>,
     ) {
-        let req = post_json(
-            &self.endpoint.to_string(),
-            &RpcMessage::HandleTokenPayload {
-                contract_id: contract_id.clone(),
-                token_ids: token_ids.clone(),
-                minter,
-                refresh,
-            },
-        );
+        let msg = RpcMessage::HandleTokenPayload {
+            contract_id: contract_id.clone(),
+            token_ids: token_ids.clone(),
+            minter,
+            refresh,
+        };
+        if self.record_dry_run("token", &msg) {
+            return;
+        }
+
+        let req = post_json(&self.endpoint.to_string(), &msg);
 
         crate::debug!("req: {:?}", req);
-        let res = self.client.request(req).await;
+        let res = self.request(req).await;
         crate::debug!("res: {:?}", res);
 
         if let Err(e) = res {
@@ -80,6 +184,11 @@ impl MinteropRpcConnector {
     }
 
     #[allow(clippy::too_many_arguments)] // Forgive me father for I have sinned
+    #[tracing::instrument(
+        target = "minterop",
+        skip_all,
+        fields(contract_id = %contract_id, metadata_id)
+    )]
     pub async fn create_metadata(
         &self,
         contract_id: String,
@@ -96,28 +205,30 @@ impl MinteropRpcConnector {
         is_locked: bool,
         creator: String,
     ) {
-        let req = post_json(
-            &self.endpoint.to_string(),
-            &RpcMessage::HandleMetadataPayload {
-                contract_id: contract_id.clone(),
-                metadata_id,
-                minters_allowlist,
-                unique_minters,
-                price: price.to_string(),
-                ft_contract_id,
-                royalties,
-                royalty_percent,
-                max_supply,
-                starts_at,
-                expires_at,
-                is_locked,
-                refresh: None,
-                creator,
-            },
-        );
+        let msg = RpcMessage::HandleMetadataPayload {
+            contract_id: contract_id.clone(),
+            metadata_id,
+            minters_allowlist,
+            unique_minters,
+            price: price.to_string(),
+            ft_contract_id,
+            royalties,
+            royalty_percent,
+            max_supply,
+            starts_at,
+            expires_at,
+            is_locked,
+            refresh: None,
+            creator,
+        };
+        if self.record_dry_run("create_metadata", &msg) {
+            return;
+        }
+
+        let req = post_json(&self.endpoint.to_string(), &msg);
 
         crate::debug!("req: {:?}", req);
-        let res = self.client.request(req).await;
+        let res = self.request(req).await;
         crate::debug!("res: {:?}", res);
 
         if let Err(e) = res {
@@ -130,6 +241,7 @@ impl MinteropRpcConnector {
         }
     }
 
+    #[tracing::instrument(target = "minterop", skip(self))]
     pub async fn sale(
         &self,
         contract_id: String,
@@ -137,18 +249,20 @@ impl MinteropRpcConnector {
         new_owner_id: String,
         receipt_id: String,
     ) {
-        let req = post_json(
-            &self.endpoint.to_string(),
-            &RpcMessage::HandleSalePayload {
-                contract_id: contract_id.clone(),
-                token_id: token_id.clone(),
-                new_owner_id: new_owner_id.clone(),
-                receipt_id,
-            },
-        );
+        let msg = RpcMessage::HandleSalePayload {
+            contract_id: contract_id.clone(),
+            token_id: token_id.clone(),
+            new_owner_id: new_owner_id.clone(),
+            receipt_id,
+        };
+        if self.record_dry_run("sale", &msg) {
+            return;
+        }
+
+        let req = post_json(&self.endpoint.to_string(), &msg);
 
         crate::debug!("req: {:?}", req);
-        let res = self.client.request(req).await;
+        let res = self.request(req).await;
         crate::debug!("res: {:?}", res);
 
         if let Err(e) = res {
@@ -161,6 +275,15 @@ impl MinteropRpcConnector {
             )
         }
     }
+
+    /// Sends the request, propagating the current trace context via headers
+    async fn request(
+        &self,
+        mut req: Request<Body>,
+    ) -> hyper::Result<hyper::Response<Body>> {
+        crate::telemetry::inject_trace_context(&mut req);
+        self.client.request(req).await
+    }
 }
 
 fn post_json<T: serde::Serialize>(uri: &str, body: &T) -> Request<Body> {
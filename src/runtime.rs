@@ -1,21 +1,65 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ops::RangeInclusive,
+    time::Duration,
+};
+
 use near_lake_framework::near_indexer_primitives::{
     types::AccountId,
     views::{
+        BlockHeaderView,
         ReceiptEnumView,
         StateChangeValueView,
     },
     IndexerExecutionOutcomeWithReceipt,
     StreamerMessage,
 };
+use tracing::Instrument;
 
 use crate::{
+    block_source::Finality,
+    bulk::{
+        BulkBuffer,
+        BulkMode,
+    },
+    currencies::Currencies,
     database::DbConnPool,
+    dry_run::DryRun,
     handlers::TrackedAction,
     logging::HandleErr,
     rpc_connection::MinteropRpcConnector,
+    shutdown::ShutdownSignal,
+    stats::StaleStats,
     LakeStreamer,
 };
 
+/// Time given to detached RPC dispatches to finish when the stream ends.
+const RPC_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the progress of indexing is persisted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) enum Cursor {
+    /// `blocks.synced_height`, from which the live indexer resumes
+    #[default]
+    Live,
+    /// Row of a job in `backfill_jobs`, which leaves the live cursor alone
+    Backfill(String),
+    /// Nowhere, replays of a range neither move the live cursor nor resume
+    Replay,
+}
+
+/// Where a cursor moves to after a processed block
+#[derive(Debug, Clone)]
+pub(crate) struct CursorPosition {
+    pub(crate) height: u64,
+    pub(crate) hash: String,
+    /// Timestamps of the first and the last block processed by this run
+    pub(crate) replayed: Option<RangeInclusive<chrono::NaiveDateTime>>,
+    /// Whether the stop height has been reached
+    pub(crate) finished: bool,
+}
+
 /// Holding all the data needed to handle blocks
 pub struct MintlakeRuntime {
     // TODO: latest block for skip checks (later)
@@ -24,33 +68,75 @@ pub struct MintlakeRuntime {
     pub(crate) minterop_rpc: MinteropRpcConnector,
     pub(crate) mintbase_root: String,
     pub(crate) paras_marketplace_id: String,
+    pub(crate) market_ids: Vec<String>,
+    pub(crate) market_versions: HashMap<String, Vec<String>>,
     pub(crate) contract_filter: Option<Vec<String>>,
+    pub(crate) contract_denylist: Vec<String>,
+    pub(crate) finality: Finality,
+    /// Height and hash of the last processed block
+    pub(crate) last_block: RefCell<Option<(u64, String)>>,
+    /// Timestamps of the first and last processed block
+    pub(crate) replayed: RefCell<Option<RangeInclusive<chrono::NaiveDateTime>>>,
+    pub(crate) cursor: Cursor,
+    /// Buffers writes and flushes them every few blocks, for backfills
+    pub(crate) bulk: Option<BulkMode>,
+    /// Records writes instead of executing them
+    pub(crate) dry_run: Option<DryRun>,
+    /// Statistics and price candles that need to be refreshed with the block
+    pub(crate) stats: StaleStats,
+    /// Registers FT currencies as they are encountered
+    pub(crate) currencies: Currencies,
 }
 
 impl MintlakeRuntime {
-    /// Listen to a stream of blocks, and process all the contained data.
-    pub async fn handle_stream(&self, stream: LakeStreamer) {
+    /// Timestamps of the first and last block processed so far, to scope
+    /// snapshots to the replayed blocks
+    pub fn replayed_range(
+        &self,
+    ) -> Option<RangeInclusive<chrono::NaiveDateTime>> {
+        self.replayed.borrow().clone()
+    }
+
+    /// Listen to a stream of blocks, and process all the contained data. Once
+    /// `shutdown` fires, the in-flight block is finished and pending RPC
+    /// dispatches are flushed before returning.
+    pub async fn handle_stream(
+        &self,
+        stream: LakeStreamer,
+        shutdown: ShutdownSignal,
+    ) {
+        // backfills and replays must not touch the journal of the live
+        // indexer, and dry runs nothing at all
+        if self.cursor == Cursor::Live && self.dry_run.is_none() {
+            if let Err(e) = self.init_fork_detection().await {
+                crate::error!("{:?}", e);
+                return;
+            }
+        }
+
         match (self.stop_block_height, self.contract_filter.clone()) {
-            (Some(0), None) => {
-                self.handle_stream_unbounded_unfiltered(stream).await
+            (Some(0), None) | (None, None) => {
+                self.handle_stream_unbounded_unfiltered(stream, shutdown)
+                    .await
             }
             (Some(h), None) => {
-                self.handle_stream_bounded_unfiltered(stream, h).await
-            }
-            (None, None) => {
-                self.handle_stream_unbounded_unfiltered(stream).await
-            }
-            (Some(0), Some(filter)) => {
-                self.handle_stream_unbounded_filtered(stream, &filter).await
+                self.handle_stream_bounded_unfiltered(stream, h, shutdown)
+                    .await
             }
-            (Some(h), Some(filter)) => {
-                self.handle_stream_bounded_filtered(stream, h, &filter)
+            (Some(0), Some(filter)) | (None, Some(filter)) => {
+                self.handle_stream_unbounded_filtered(stream, &filter, shutdown)
                     .await
             }
-            (None, Some(filter)) => {
-                self.handle_stream_unbounded_filtered(stream, &filter).await
+            (Some(h), Some(filter)) => {
+                self.handle_stream_bounded_filtered(
+                    stream, h, &filter, shutdown,
+                )
+                .await
             }
         }
+
+        self.flush_pending_blocks().await;
+        self.minterop_rpc.flush(RPC_DRAIN_TIMEOUT).await;
     }
 
     /// Handles the stream of blocks until a specified height and then exits.
@@ -59,20 +145,26 @@ impl MintlakeRuntime {
         &self,
         mut stream: LakeStreamer,
         stop_height: u64,
+        mut shutdown: ShutdownSignal,
     ) {
         crate::info!("Running bounded indexer to height {}", stop_height);
 
-        #[allow(unused_assignments)]
-        let mut height = 0;
-        while let Some(msg) = stream.recv().await {
-            height = self.handle_msg_unfiltered(msg).await;
-            if height > stop_height {
+        while let Some(msg) = next_block(&mut stream, &mut shutdown).await {
+            // the lake keeps streaming beyond the stop height, but forks may
+            // still revisit lower heights
+            if msg.block.header.height > stop_height {
                 crate::info!(
                     "Finished running indexer to height, {}",
                     stop_height
                 );
                 return;
             }
+            if !self.accept_block(&msg.block.header).await {
+                return;
+            }
+            if !self.handle_msg_unfiltered(msg).await {
+                return;
+            }
         }
     }
 
@@ -80,11 +172,17 @@ impl MintlakeRuntime {
     async fn handle_stream_unbounded_unfiltered(
         &self,
         mut stream: LakeStreamer,
+        mut shutdown: ShutdownSignal,
     ) {
         crate::info!("Running unbouned indexer");
 
-        while let Some(msg) = stream.recv().await {
-            self.handle_msg_unfiltered(msg).await;
+        while let Some(msg) = next_block(&mut stream, &mut shutdown).await {
+            if !self.accept_block(&msg.block.header).await {
+                return;
+            }
+            if !self.handle_msg_unfiltered(msg).await {
+                return;
+            }
         }
     }
 
@@ -95,20 +193,26 @@ impl MintlakeRuntime {
         mut stream: LakeStreamer,
         stop_height: u64,
         filter: &[String],
+        mut shutdown: ShutdownSignal,
     ) {
         crate::info!("Running bounded indexer to height {}", stop_height);
 
-        #[allow(unused_assignments)]
-        let mut height = 0;
-        while let Some(msg) = stream.recv().await {
-            height = self.handle_msg_filtered(msg, filter).await;
-            if height > stop_height {
+        while let Some(msg) = next_block(&mut stream, &mut shutdown).await {
+            // the lake keeps streaming beyond the stop height, but forks may
+            // still revisit lower heights
+            if msg.block.header.height > stop_height {
                 crate::info!(
                     "Finished running indexer to height, {}",
                     stop_height
                 );
                 return;
             }
+            if !self.accept_block(&msg.block.header).await {
+                return;
+            }
+            if !self.handle_msg_filtered(msg, filter).await {
+                return;
+            }
         }
     }
 
@@ -117,19 +221,32 @@ impl MintlakeRuntime {
         &self,
         mut stream: LakeStreamer,
         filter: &[String],
+        mut shutdown: ShutdownSignal,
     ) {
         crate::info!("Running unbouned indexer");
 
-        while let Some(msg) = stream.recv().await {
-            self.handle_msg_filtered(msg, filter).await;
+        while let Some(msg) = next_block(&mut stream, &mut shutdown).await {
+            if !self.accept_block(&msg.block.header).await {
+                return;
+            }
+            if !self.handle_msg_filtered(msg, filter).await {
+                return;
+            }
         }
     }
 
     /// Handles a streamer message (which is mostly synonymous to a block) by
     /// getting all transactions, filtering for only those that are successful
     /// and have logs, and then spawn tasks that process them asynchronously.
-    async fn handle_msg_unfiltered(&self, msg: StreamerMessage) -> u64 {
+    /// Returns `false` if indexing needs to stop.
+    #[tracing::instrument(
+        target = "minterop",
+        skip_all,
+        fields(height = msg.block.header.height)
+    )]
+    async fn handle_msg_unfiltered(&self, msg: StreamerMessage) -> bool {
         let height = msg.block.header.height;
+        let hash = msg.block.header.hash.to_string();
         if height % 10 == 0 {
             crate::info!("Processing block {}", height);
         }
@@ -156,6 +273,7 @@ impl MintlakeRuntime {
                     for action in actions {
                         if let Some(action) = TrackedAction::try_new(
                             &tx.receipt.receiver_id,
+                            &tx.receipt.predecessor_id,
                             timestamp,
                             &tx.receipt.receipt_id,
                             action,
@@ -167,13 +285,19 @@ impl MintlakeRuntime {
 
                 // check for logs that we might wish to process
                 if let Some((tx, logs)) =
-                    filter_and_split_receipt(timestamp, tx)
+                    filter_and_split_receipt(height, timestamp, tx)
                 {
-                    log_data.push((tx, logs));
+                    if !self.is_denied(&tx.receiver) {
+                        log_data.push((tx, logs));
+                    }
                 }
             }
         }
 
+        if !self.flush_for_token_reads(&log_data).await {
+            return false;
+        }
+
         // log processing
         let mut handles = log_data
             .into_iter()
@@ -182,8 +306,10 @@ impl MintlakeRuntime {
                 // establish a new connection on every transaction. That's what
                 // we want here
                 let rt = self.tx_processing_runtime();
-                #[allow(clippy::redundant_async_block)]
-                actix_rt::spawn(async move { handle_tx(&rt, tx, logs).await })
+                actix_rt::spawn(
+                    async move { handle_tx(&rt, tx, logs).await }
+                        .in_current_span(),
+                )
             })
             .collect::<Vec<_>>();
 
@@ -193,8 +319,10 @@ impl MintlakeRuntime {
                 .into_iter()
                 .map(|action| {
                     let rt = self.tx_processing_runtime();
-                    #[allow(clippy::redundant_async_block)]
-                    actix_rt::spawn(async move { action.process(&rt).await })
+                    actix_rt::spawn(
+                        async move { action.process(&rt).await }
+                            .in_current_span(),
+                    )
                 })
                 .collect(),
         );
@@ -210,17 +338,23 @@ impl MintlakeRuntime {
             });
         }
 
-        update_db_blockheight(&self.pg_connection, height).await;
-        height
+        self.touch_block(timestamp);
+        self.commit_block(height, hash).await
     }
 
     /// The same as `handle_msg_unfiltered, but applies `
+    #[tracing::instrument(
+        target = "minterop",
+        skip_all,
+        fields(height = msg.block.header.height)
+    )]
     async fn handle_msg_filtered(
         &self,
         msg: StreamerMessage,
         filter: &[String],
-    ) -> u64 {
+    ) -> bool {
         let height = msg.block.header.height;
+        let hash = msg.block.header.hash.to_string();
         if height % 10 == 0 {
             crate::info!("Processing block {}", height);
         }
@@ -234,6 +368,7 @@ impl MintlakeRuntime {
 
         let mut state_change_data = Vec::new();
         let mut log_data = Vec::new();
+        let mut tracked_actions: Vec<TrackedAction> = Vec::new();
         for shard in shards {
             shard
                 //FIXME: filter by account_id
@@ -257,29 +392,70 @@ impl MintlakeRuntime {
                 .into_iter()
                 .filter(is_success)
             {
+                // check actions that we track on filtered accounts
+                if let ReceiptEnumView::Action { ref actions, .. } =
+                    tx.receipt.receipt
+                {
+                    if filter.contains(&tx.receipt.receiver_id.to_string()) {
+                        for action in actions {
+                            if let Some(action) = TrackedAction::try_new(
+                                &tx.receipt.receiver_id,
+                                &tx.receipt.predecessor_id,
+                                timestamp,
+                                &tx.receipt.receipt_id,
+                                action,
+                            ) {
+                                tracked_actions.push(action);
+                            }
+                        }
+                    }
+                }
+
                 if let Some((tx, logs)) =
-                    filter_and_split_receipt(timestamp, tx)
+                    filter_and_split_receipt(height, timestamp, tx)
                 {
-                    if filter.contains(&tx.receiver.to_string()) {
+                    if filter.contains(&tx.receiver.to_string())
+                        && !self.is_denied(&tx.receiver)
+                    {
                         log_data.push((tx, logs));
                     }
                 }
             }
         }
 
+        if !self.flush_for_token_reads(&log_data).await {
+            return false;
+        }
+
         // log processing
-        let handles = log_data
+        let mut handles = log_data
             .into_iter()
             .map(|(tx, logs)| {
                 // This clone internally clones an Arc, and thus doesn't
                 // establish a new connection on every transaction. That's what
                 // we want here
                 let rt = self.tx_processing_runtime();
-                #[allow(clippy::redundant_async_block)]
-                actix_rt::spawn(async move { handle_tx(&rt, tx, logs).await })
+                actix_rt::spawn(
+                    async move { handle_tx(&rt, tx, logs).await }
+                        .in_current_span(),
+                )
             })
             .collect::<Vec<_>>();
 
+        // tracked action processing
+        handles.append(
+            &mut tracked_actions
+                .into_iter()
+                .map(|action| {
+                    let rt = self.tx_processing_runtime();
+                    actix_rt::spawn(
+                        async move { action.process(&rt).await }
+                            .in_current_span(),
+                    )
+                })
+                .collect(),
+        );
+
         // Since this method is meant to retroactively update/index smart
         // contracts with deviating structure, we do not process state changes
         // here. If state changes are buggy and need to be reprocessed, this
@@ -296,8 +472,240 @@ impl MintlakeRuntime {
             });
         }
 
-        update_db_blockheight(&self.pg_connection, height).await;
-        height
+        self.touch_block(timestamp);
+        self.commit_block(height, hash).await
+    }
+
+    /// Following optimistic heads picks up where the last run left, such that
+    /// forks across restarts are detected. For final blocks, nothing needs to
+    /// be journaled.
+    async fn init_fork_detection(&self) -> anyhow::Result<()> {
+        match self.finality {
+            Finality::Final => {
+                crate::database::disable_journal(&self.pg_connection).await;
+            }
+            Finality::Optimistic => {
+                let synced_block =
+                    crate::database::query_synced_block(&self.pg_connection)
+                        .await?;
+                self.last_block.replace(synced_block);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the block builds on top of the last one, and rolls back
+    /// orphaned blocks if it doesn't. Returns `false` if that isn't possible,
+    /// in which case indexing needs to stop to not write inconsistent data.
+    async fn accept_block(&self, header: &BlockHeaderView) -> bool {
+        let hash = header.hash.to_string();
+        let prev_hash = header.prev_hash.to_string();
+        let last_block = self.last_block.borrow().clone();
+        let link = link_block(
+            last_block.as_ref().map(|(h, hash)| (*h, hash.as_str())),
+            header.height,
+            header.prev_height,
+            &prev_hash,
+        );
+
+        if let BlockLink::Fork { fork_height } = link {
+            crate::warn!(
+                "Block {} ({}) does not build on {:?}, forked at {}",
+                header.height,
+                hash,
+                last_block,
+                fork_height
+            );
+            if self.finality == Finality::Final {
+                crate::error!("Cannot roll back final blocks, stopping");
+                return false;
+            }
+
+            let known_hash = crate::database::query_block_hash(
+                &self.pg_connection,
+                fork_height,
+            )
+            .await;
+            if known_hash.as_deref() != Some(prev_hash.as_str()) {
+                crate::error!(
+                    "Fork at {} is deeper than the journal, stopping",
+                    fork_height
+                );
+                return false;
+            }
+
+            match crate::database::rollback_to_height(
+                &self.pg_connection,
+                fork_height,
+            )
+            .await
+            {
+                Ok(reverted) => crate::info!(
+                    "Rolled back {} writes above height {}",
+                    reverted,
+                    fork_height
+                ),
+                Err(e) => {
+                    crate::error!("{:?}", e);
+                    return false;
+                }
+            }
+            self.last_block.replace(Some((fork_height, prev_hash)));
+        }
+
+        if self.finality == Finality::Optimistic {
+            crate::database::begin_journaled_block(
+                &self.pg_connection,
+                header.height,
+                hash,
+            )
+            .await;
+        }
+        true
+    }
+
+    /// Persists the height of a processed block. In bulk mode, this only
+    /// happens along with flushing the buffered writes every few blocks.
+    /// Returns `false` if indexing needs to stop.
+    async fn commit_block(&self, height: u64, hash: String) -> bool {
+        self.last_block.replace(Some((height, hash.clone())));
+        if self.bulk.is_none() {
+            crate::stats::refresh(
+                &self.stats,
+                &self.pg_connection,
+                self.dry_run.as_ref(),
+            )
+            .await;
+        }
+        if let Some(dry_run) = &self.dry_run {
+            dry_run.record_block();
+            return true;
+        }
+        let bulk = match &self.bulk {
+            None => {
+                self.update_cursor(height, hash).await;
+                return true;
+            }
+            Some(bulk) => bulk,
+        };
+
+        let finished = self.is_stop_height(height);
+        if !bulk.block_done() && !finished {
+            return true;
+        }
+        match bulk
+            .flush(
+                &self.minterop_rpc,
+                &self.stats,
+                &self.cursor,
+                self.position(height, hash),
+            )
+            .await
+        {
+            Ok(()) => true,
+            Err(e) => {
+                crate::error!("{:?}", e);
+                false
+            }
+        }
+    }
+
+    /// In bulk mode, handlers that read or update tokens need to see those of
+    /// earlier blocks, so these are flushed first. Returns `false` if the
+    /// flush failed.
+    async fn flush_for_token_reads(
+        &self,
+        log_data: &[(ReceiptData, Vec<String>)],
+    ) -> bool {
+        let reads_tokens = log_data
+            .iter()
+            .flat_map(|(_, logs)| logs)
+            .any(|log| crate::bulk::reads_tokens(log));
+        if self.bulk.is_none() || !reads_tokens {
+            return true;
+        }
+        self.flush_pending_blocks().await
+    }
+
+    /// Flushes the writes of blocks that have been processed since the last
+    /// flush in bulk mode, e.g. when the stream ended early. Returns `false`
+    /// if the flush failed.
+    async fn flush_pending_blocks(&self) -> bool {
+        let bulk = match &self.bulk {
+            Some(bulk) if bulk.has_pending() => bulk,
+            _ => return true,
+        };
+        let last_block = self.last_block.borrow().clone();
+        if let Some((height, hash)) = last_block {
+            if let Err(e) = bulk
+                .flush(
+                    &self.minterop_rpc,
+                    &self.stats,
+                    &self.cursor,
+                    self.position(height, hash),
+                )
+                .await
+            {
+                crate::error!("{:?}", e);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Persists the height of a processed block
+    async fn update_cursor(&self, height: u64, hash: String) {
+        match &self.cursor {
+            Cursor::Live => {
+                update_db_blockheight(&self.pg_connection, height, hash).await
+            }
+            Cursor::Backfill(job_id) => {
+                update_backfill_cursor(
+                    &self.pg_connection,
+                    job_id,
+                    self.position(height, hash),
+                )
+                .await
+            }
+            Cursor::Replay => {}
+        }
+    }
+
+    fn position(&self, height: u64, hash: String) -> CursorPosition {
+        CursorPosition {
+            height,
+            hash,
+            replayed: self.replayed_range(),
+            finished: self.is_stop_height(height),
+        }
+    }
+
+    fn touch_block(&self, timestamp: chrono::NaiveDateTime) {
+        // backfills, replays and reindex workers run next to other runtimes,
+        // so their latest block says nothing about which offers ran out.
+        // `reconcile` expires offers once a reindex is done.
+        if self.cursor == Cursor::Live {
+            self.stats.touch_block(timestamp);
+        }
+        let mut replayed = self.replayed.borrow_mut();
+        *replayed = Some(match replayed.take() {
+            Some(range) => {
+                (*range.start()).min(timestamp)..=(*range.end()).max(timestamp)
+            }
+            None => timestamp..=timestamp,
+        });
+    }
+
+    fn is_stop_height(&self, height: u64) -> bool {
+        self.stop_block_height
+            .filter(|h| *h != 0)
+            .map_or(false, |h| height >= h)
+    }
+
+    fn is_denied(&self, contract_id: &AccountId) -> bool {
+        self.contract_denylist
+            .iter()
+            .any(|denied| denied == contract_id.as_str())
     }
 
     fn tx_processing_runtime(&self) -> TxProcessingRuntime {
@@ -306,20 +714,35 @@ impl MintlakeRuntime {
             minterop_rpc: self.minterop_rpc.clone(),
             mintbase_root: self.mintbase_root.clone(),
             paras_marketplace_id: self.paras_marketplace_id.clone(),
+            market_ids: self.market_ids.clone(),
+            market_versions: self.market_versions.clone(),
+            bulk: self.bulk.as_ref().map(|bulk| bulk.buffer.clone()),
+            dry_run: self.dry_run.clone(),
+            stats: self.stats.clone(),
+            currencies: self.currencies.clone(),
         }
     }
 }
 
 /// Handles a transaction by filtering all logs for being an event log and
 /// processing those in order.
+#[tracing::instrument(
+    target = "minterop",
+    skip_all,
+    fields(receipt_id = %tx.id, receiver = %tx.receiver)
+)]
 async fn handle_tx(
     rt: &TxProcessingRuntime,
     tx: ReceiptData,
     logs: Vec<String>,
 ) {
-    for log in logs.into_iter() {
+    for (log_index, log) in logs.into_iter().enumerate() {
+        let tx = ReceiptData {
+            log_index,
+            ..tx.clone()
+        };
         if log.starts_with("EVENT_JSON:") {
-            handle_log(rt, tx.clone(), log).await;
+            handle_log(rt, tx, log).await;
         } else if tx.receiver.as_str() == rt.paras_marketplace_id.as_str() {
             crate::handlers::paras::handle_paras_market_log(rt, &tx, &log)
                 .await;
@@ -330,6 +753,11 @@ async fn handle_tx(
 // TODO: we might wish to move this
 /// Parses standard, version, and event type out of an event logs, selects an
 /// appropriate handler function, and passes the data.
+#[tracing::instrument(
+    target = "minterop",
+    skip_all,
+    fields(receipt_id = %tx.id, standard, version, event)
+)]
 async fn handle_log(rt: &TxProcessingRuntime, tx: ReceiptData, log: String) {
     use crate::handlers::*;
 
@@ -342,6 +770,33 @@ async fn handle_log(rt: &TxProcessingRuntime, tx: ReceiptData, log: String) {
             Some(event) => sanitize_event(event),
         };
 
+    let span = tracing::Span::current();
+    span.record("standard", standard.as_str());
+    span.record("version", version.as_str());
+    span.record("event", event.as_str());
+
+    if standard == "mb_market"
+        && !rt.market_ids.is_empty()
+        && !rt.market_ids.iter().any(|id| id == tx.receiver.as_str())
+    {
+        crate::warn!("Got market event from unknown market: {}", tx.receiver);
+    }
+    if let Some(versions) = rt.market_versions.get(tx.receiver.as_str()) {
+        if !versions.contains(&version) {
+            crate::debug!(
+                "Skipping {} event of untracked version {} ({:?})",
+                event,
+                version,
+                tx
+            );
+            return;
+        }
+    }
+
+    if let Some(dry_run) = &rt.dry_run {
+        dry_run.record_event(&standard, &version, &event);
+    }
+
     match (standard.as_str(), version.as_str(), event.as_str()) {
         // ------------ nft_core
         ("nep171", "1.0.0", "nft_mint")
@@ -451,6 +906,12 @@ pub(crate) struct TxProcessingRuntime {
     pub(crate) minterop_rpc: MinteropRpcConnector,
     pub(crate) mintbase_root: String,
     pub(crate) paras_marketplace_id: String,
+    pub(crate) market_ids: Vec<String>,
+    pub(crate) market_versions: HashMap<String, Vec<String>>,
+    pub(crate) bulk: Option<BulkBuffer>,
+    pub(crate) dry_run: Option<DryRun>,
+    pub(crate) stats: StaleStats,
+    pub(crate) currencies: Currencies,
 }
 
 #[derive(Debug, Clone)]
@@ -460,12 +921,15 @@ pub(crate) struct ReceiptData {
     pub(crate) sender_pk: Option<String>,
     pub(crate) receiver: AccountId,
     pub(crate) timestamp: chrono::NaiveDateTime,
-    // pub(crate) block_height: u64,
+    pub(crate) block_height: u64,
+    /// Position of the log that is being handled among those of the receipt
+    pub(crate) log_index: usize,
 }
 
 // This function assumes that the success status has already been checked. If
 // failed to check this beforehand, invalid logs will be indexed.
 fn filter_and_split_receipt(
+    block_height: u64,
     timestamp: chrono::NaiveDateTime,
     tx: IndexerExecutionOutcomeWithReceipt,
 ) -> Option<(ReceiptData, Vec<String>)> {
@@ -486,22 +950,85 @@ fn filter_and_split_receipt(
                 },
                 receiver: tx.receipt.receiver_id,
                 timestamp,
+                block_height,
+                log_index: 0,
             },
             tx.execution_outcome.outcome.logs,
         )),
     }
 }
 
+/// Pulls the next block from the stream, unless shutdown has been requested.
+/// Blocks are only ever taken in between processing, such that the in-flight
+/// block is always finished and its height persisted.
+pub(crate) async fn next_block(
+    stream: &mut LakeStreamer,
+    shutdown: &mut ShutdownSignal,
+) -> Option<StreamerMessage> {
+    if *shutdown.borrow() {
+        return None;
+    }
+
+    tokio::select! {
+        biased;
+        // a closed channel disables this branch, we then run without signals
+        Ok(()) = shutdown.changed() => {
+            crate::info!("Stopping to pull blocks for shutdown");
+            None
+        }
+        msg = stream.recv() => msg,
+    }
+}
+
+/// How a block relates to the last processed one
+#[derive(Debug, PartialEq, Eq)]
+enum BlockLink {
+    /// First block, or built on top of the last one
+    Extends,
+    /// Blocks in between have not been seen, e.g. when reading captured
+    /// blocks. These cannot be checked.
+    Gap,
+    /// Built on an earlier block at `fork_height`, all processed blocks above
+    /// are orphaned
+    Fork { fork_height: u64 },
+}
+
+fn link_block(
+    last_block: Option<(u64, &str)>,
+    height: u64,
+    prev_height: Option<u64>,
+    prev_hash: &str,
+) -> BlockLink {
+    let (last_height, last_hash) = match last_block {
+        None => return BlockLink::Extends,
+        Some(last_block) => last_block,
+    };
+    if prev_hash == last_hash {
+        return BlockLink::Extends;
+    }
+
+    let prev_height = prev_height.unwrap_or_else(|| height.saturating_sub(1));
+    if prev_height > last_height {
+        BlockLink::Gap
+    } else {
+        BlockLink::Fork {
+            fork_height: prev_height,
+        }
+    }
+}
+
 /// The database should always know the last synced block, to forward to
 /// frontend for quick health checks, and in perspective to get the starting
 /// block height from the database. This function handles that insert.
-async fn update_db_blockheight(db: &DbConnPool, height: u64) {
+#[tracing::instrument(target = "minterop", skip(db))]
+async fn update_db_blockheight(db: &DbConnPool, height: u64, hash: String) {
     use actix_diesel::dsl::AsyncRunQueryDsl;
     use diesel::ExpressionMethods;
-    use minterop_data::schema::blocks::dsl::*;
+
+    use crate::schema::blocks::dsl::*;
 
     diesel::update(blocks)
-        .set(synced_height.eq(height as i64))
+        .set((synced_height.eq(height as i64), synced_hash.eq(Some(hash))))
         .execute_async(db)
         .await
         .handle_err(|e| {
@@ -513,6 +1040,58 @@ async fn update_db_blockheight(db: &DbConnPool, height: u64) {
         });
 }
 
+/// Progress of a backfill job, which is marked as finished once it reached its
+/// stop height. The timestamp of the first processed block is only set once,
+/// such that resumed jobs keep it.
+#[tracing::instrument(target = "minterop", skip(db))]
+async fn update_backfill_cursor(
+    db: &DbConnPool,
+    job_id: &str,
+    position: CursorPosition,
+) {
+    use actix_diesel::dsl::AsyncRunQueryDsl;
+    use diesel::{
+        ExpressionMethods,
+        QueryDsl,
+    };
+
+    use crate::schema::backfill_jobs::dsl;
+
+    let job = dsl::backfill_jobs.filter(dsl::job_id.eq(job_id.to_string()));
+    let replayed = position.replayed.clone();
+    if let Some(first) = replayed.as_ref().map(|range| *range.start()) {
+        diesel::update(job.clone().filter(dsl::first_timestamp.is_null()))
+            .set(dsl::first_timestamp.eq(Some(first)))
+            .execute_async(db)
+            .await
+            .handle_err(|e| {
+                crate::error!(
+                    "Failed to set first timestamp of backfill {}: {}",
+                    job_id,
+                    e
+                )
+            });
+    }
+
+    let finished_at = position.finished.then(|| chrono::Utc::now().naive_utc());
+    diesel::update(job)
+        .set((
+            dsl::synced_height.eq(Some(position.height as i64)),
+            dsl::synced_timestamp.eq(replayed.map(|range| *range.end())),
+            dsl::finished_at.eq(finished_at),
+        ))
+        .execute_async(db)
+        .await
+        .handle_err(|e| {
+            crate::error!(
+                "Failed to set synced height of backfill {} to {}: {}",
+                job_id,
+                position.height,
+                e
+            )
+        });
+}
+
 fn sanitize_event(
     event: (String, String, String, serde_json::Value),
 ) -> (String, String, String, serde_json::Value) {
@@ -531,3 +1110,37 @@ fn is_success(tx: &IndexerExecutionOutcomeWithReceipt) -> bool {
         ExecutionStatusView::Failure(_) | ExecutionStatusView::Unknown
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_block() {
+        let last = Some((100, "hash100"));
+
+        assert_eq!(link_block(None, 100, Some(99), "x"), BlockLink::Extends);
+        assert_eq!(
+            link_block(last, 101, Some(100), "hash100"),
+            BlockLink::Extends
+        );
+        // skipped heights still need to build on the last block
+        assert_eq!(
+            link_block(last, 103, Some(100), "hash100"),
+            BlockLink::Extends
+        );
+        assert_eq!(link_block(last, 110, Some(105), "x"), BlockLink::Gap);
+        assert_eq!(
+            link_block(last, 101, Some(100), "other"),
+            BlockLink::Fork { fork_height: 100 }
+        );
+        assert_eq!(
+            link_block(last, 100, Some(99), "hash99"),
+            BlockLink::Fork { fork_height: 99 }
+        );
+        assert_eq!(
+            link_block(last, 100, None, "hash99"),
+            BlockLink::Fork { fork_height: 99 }
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! Tables and columns added by the migrations of this crate (in `migrations`),
+//! on top of the schema of `minterop_data`.
+
+use diesel::sql_types::{
+    Array,
+    BigInt,
+    Nullable,
+    Text,
+    Timestamp,
+};
+
+table! {
+    // Same table as `minterop_data::schema::blocks`, with the columns for
+    // fork detection
+    blocks (synced_height) {
+        synced_height -> Int8,
+        synced_hash -> Nullable<Text>,
+        journal_height -> Nullable<Int8>,
+    }
+}
+
+table! {
+    block_hashes (height) {
+        height -> Int8,
+        hash -> Text,
+    }
+}
+
+table! {
+    // Rows are only ever written by triggers and `rollback_to_height`, hence
+    // the JSONB columns are left out
+    block_journal (id) {
+        id -> Int8,
+        height -> Int8,
+    }
+}
+
+// Reverts all journaled writes above `target`, returning their number
+sql_function! {
+    fn rollback_to_height(target: BigInt) -> BigInt;
+}
+
+table! {
+    backfill_jobs (job_id) {
+        job_id -> Text,
+        contracts -> Array<Text>,
+        start_height -> Int8,
+        stop_height -> Int8,
+        synced_height -> Nullable<Int8>,
+        first_timestamp -> Nullable<Timestamp>,
+        synced_timestamp -> Nullable<Timestamp>,
+        started_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    nft_ownership_changes (
+        nft_contract_id,
+        token_id,
+        receipt_id,
+        log_index,
+        event_index
+    ) {
+        nft_contract_id -> Text,
+        token_id -> Text,
+        receipt_id -> Text,
+        log_index -> Int4,
+        event_index -> Int4,
+        block_height -> Nullable<Int8>,
+        timestamp -> Timestamp,
+        owner -> Nullable<Text>,
+    }
+}
+
+table! {
+    // View over `nft_ownership_changes`
+    nft_token_ownerships (nft_contract_id, token_id, from_receipt_id) {
+        nft_contract_id -> Text,
+        token_id -> Text,
+        owner -> Text,
+        from_height -> Nullable<Int8>,
+        from_timestamp -> Timestamp,
+        from_receipt_id -> Text,
+        to_height -> Nullable<Int8>,
+        to_timestamp -> Nullable<Timestamp>,
+        to_receipt_id -> Nullable<Text>,
+    }
+}
+
+table! {
+    nft_collection_stats (nft_contract_id) {
+        nft_contract_id -> Text,
+        floor_prices -> Jsonb,
+        listings_count -> Int8,
+        sales_count -> Int8,
+        volume_total -> Jsonb,
+        owners_count -> Int8,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    nft_metadata_stats (nft_contract_id, metadata_id) {
+        nft_contract_id -> Text,
+        metadata_id -> Text,
+        floor_prices -> Jsonb,
+        listings_count -> Int8,
+        sales_count -> Int8,
+        volume_total -> Jsonb,
+        owners_count -> Int8,
+        updated_at -> Timestamp,
+    }
+}
+
+no_arg_sql_function!(
+    nft_sale_kind,
+    Text,
+    "Kind of sale activities, as known to the migrations"
+);
+
+// Recomputes the given parts of the statistics of a contract as of a block
+// timestamp, returning the number of written rows
+sql_function! {
+    fn refresh_nft_stats(
+        contract: Text,
+        as_of: Timestamp,
+        parts: Array<Text>
+    ) -> BigInt;
+}
+
+// Adds the counted sales of a receipt to the statistics once, returning the
+// number of added sales
+sql_function! {
+    fn add_nft_sale_stats(receipt: Text) -> BigInt;
+}
+
+// Adds owner changes of tokens of a contract to its owner counts, returning
+// the number of changed counts
+sql_function! {
+    fn add_nft_owner_stats(
+        contract: Text,
+        token_ids: Array<Text>,
+        old_owners: Array<Nullable<Text>>,
+        new_owners: Array<Nullable<Text>>,
+        as_of: Timestamp
+    ) -> BigInt;
+}
+
+table! {
+    nft_price_candles (nft_contract_id, currency, bucket, bucket_start) {
+        nft_contract_id -> Text,
+        currency -> Text,
+        bucket -> Text,
+        bucket_start -> Timestamp,
+        open -> Numeric,
+        high -> Numeric,
+        low -> Numeric,
+        close -> Numeric,
+        volume -> Numeric,
+        sales_count -> Int8,
+    }
+}
+
+table! {
+    // View over sale activities and sold external listings
+    nft_sales (nft_contract_id, token_id, receipt_id) {
+        nft_contract_id -> Text,
+        token_id -> Text,
+        receipt_id -> Text,
+        timestamp -> Timestamp,
+        currency -> Text,
+        price -> Numeric,
+    }
+}
+
+// Recomputes the hourly and daily price candles of a contract that contain a
+// timestamp, returning the number of written candles
+sql_function! {
+    fn refresh_nft_candles(contract: Text, at: Timestamp) -> BigInt;
+}
+
+table! {
+    currencies (currency) {
+        currency -> Text,
+        symbol -> Text,
+        decimals -> Int4,
+        source -> Text,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    currency_prices (currency, timestamp) {
+        currency -> Text,
+        timestamp -> Timestamp,
+        usd_price -> Numeric,
+        source -> Text,
+    }
+}
+
+// Revalues earnings and sales in a currency after importing its prices for a
+// time range, returning the number of revalued rows
+sql_function! {
+    fn revalue_in_usd(cur: Text, since: Timestamp, until: Timestamp) -> BigInt;
+}
+
+table! {
+    // View summing up `nft_earnings` per account, currency and role
+    nft_earnings_ledger (account_id, currency, role) {
+        account_id -> Text,
+        currency -> Text,
+        role -> Text,
+        amount -> Numeric,
+        amount_normalized -> Nullable<Numeric>,
+        usd_value -> Nullable<Numeric>,
+        earnings_count -> Int8,
+        first_earned_at -> Timestamp,
+        last_earned_at -> Timestamp,
+    }
+}
+
+table! {
+    nft_split_changes (nft_contract_id, token_id, receipt_id) {
+        nft_contract_id -> Text,
+        token_id -> Text,
+        receipt_id -> Text,
+        timestamp -> Timestamp,
+        royalties -> Nullable<Jsonb>,
+        royalties_percent -> Nullable<Int4>,
+        splits -> Nullable<Jsonb>,
+    }
+}
+
+// Records the seller, royalties and split owners that applied to the sale of
+// a receipt, returning the number of written rows
+sql_function! {
+    fn record_nft_sale_terms(receipt: Text) -> BigInt;
+}
+
+table! {
+    nft_payout_mismatches (receipt_id, receiver_id) {
+        receipt_id -> Text,
+        receiver_id -> Text,
+        nft_contract_id -> Text,
+        token_id -> Text,
+        timestamp -> Timestamp,
+        currency -> Text,
+        roles -> Text,
+        expected -> Numeric,
+        actual -> Numeric,
+    }
+}
+
+// Compares the payout of a sale with the royalties and splits of the token,
+// returning the number of mismatching receivers
+sql_function! {
+    fn verify_nft_payout(receipt: Text) -> BigInt;
+}
+
+table! {
+    // Same table as `minterop_data::schema::accounts`, with the account that
+    // created it
+    accounts (account_id, created_receipt_id) {
+        account_id -> Text,
+        created_at -> Timestamp,
+        created_receipt_id -> Text,
+        creator_id -> Nullable<Text>,
+    }
+}
+
+table! {
+    nft_sale_flags (receipt_id, token_id, reason) {
+        receipt_id -> Text,
+        nft_contract_id -> Text,
+        token_id -> Text,
+        timestamp -> Timestamp,
+        seller_id -> Text,
+        buyer_id -> Text,
+        reason -> Text,
+    }
+}
+
+// Flags the sales of a receipt that look like wash trades, returning the
+// number of flags
+sql_function! {
+    fn flag_nft_sale(receipt: Text) -> BigInt;
+}
+
+// Expires the active offers that ran out at or before the block timestamp,
+// returning the number of expired offers
+sql_function! {
+    fn expire_nft_offers(as_of: Timestamp) -> BigInt;
+}
@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use crate::LakeHandle;
+
+/// Time given to the lake framework to wind down after we stopped pulling
+/// blocks from it.
+const LAKE_JOIN_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Receiving end of the shutdown signal, flips to `true` once SIGTERM or
+/// SIGINT have been received.
+pub type ShutdownSignal = watch::Receiver<bool>;
+
+/// Installs handlers for SIGTERM and SIGINT. The returned signal can be passed
+/// to `MintlakeRuntime::handle_stream`, which will then stop after the
+/// in-flight block.
+pub fn listen_for_shutdown() -> std::io::Result<ShutdownSignal> {
+    use tokio::signal::unix::{
+        signal,
+        SignalKind,
+    };
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let (tx, rx) = watch::channel(false);
+
+    actix_rt::spawn(async move {
+        tokio::select! {
+            _ = sigterm.recv() => crate::info!("Received SIGTERM, shutting down"),
+            _ = sigint.recv() => crate::info!("Received SIGINT, shutting down"),
+        }
+        tx.send(true).ok();
+    });
+
+    Ok(rx)
+}
+
+/// A signal that never fires, for running without signal handling.
+pub fn no_shutdown() -> ShutdownSignal {
+    watch::channel(false).1
+}
+
+/// Waits for the lake framework task to finish, aborting it if it doesn't
+/// within a few seconds.
+pub async fn join_lake_handle(mut handle: LakeHandle) {
+    match tokio::time::timeout(LAKE_JOIN_TIMEOUT, &mut handle).await {
+        Ok(Ok(Ok(()))) => {}
+        Ok(Ok(Err(e))) => {
+            crate::error!("Failed to join lake handle: {:?}", e)
+        }
+        Ok(Err(e)) => crate::error!("Failed to join lake handle: {:?}", e),
+        Err(_) => {
+            crate::warn!(
+                "Lake handle did not finish within {:?}, aborting",
+                LAKE_JOIN_TIMEOUT
+            );
+            handle.abort();
+        }
+    }
+}
@@ -0,0 +1,438 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    ops::RangeInclusive,
+    path::Path,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use chrono::NaiveDateTime;
+
+/// Tables written by the indexer. Tables that are (also) written by other
+/// services, like the metadata resolver, would make snapshots depend on
+/// timing and are left out.
+pub const SNAPSHOT_TABLES: &[SnapshotTable] = &[
+    SnapshotTable::timestamps("nft_contracts", &["created_at"]),
+    SnapshotTable::timestamps("mb_store_minters", &["timestamp"]),
+    SnapshotTable::timestamps(
+        "nft_tokens",
+        &[
+            "minted_timestamp",
+            "last_transfer_timestamp",
+            "burned_timestamp",
+        ],
+    ),
+    SnapshotTable::timestamps("nft_activities", &["timestamp"]),
+    SnapshotTable::timestamps("nft_approvals", &["timestamp"]),
+    SnapshotTable::timestamps(
+        "nft_listings",
+        &["created_at", "unlisted_at", "accepted_at", "invalidated_at"],
+    ),
+    SnapshotTable::timestamps(
+        "nft_offers",
+        &[
+            "offered_at",
+            "withdrawn_at",
+            "accepted_at",
+            "invalidated_at",
+            "expired_at",
+        ],
+    ),
+    SnapshotTable::timestamps("nft_earnings", &["timestamp"]),
+    SnapshotTable::timestamps("nft_ownership_changes", &["timestamp"]),
+    SnapshotTable::timestamps("nft_payout_mismatches", &["timestamp"]),
+    SnapshotTable::timestamps("nft_sale_flags", &["timestamp"]),
+    SnapshotTable::timestamps("nft_sale_terms", &["timestamp"]),
+    SnapshotTable::timestamps("nft_split_changes", &["timestamp"]),
+    SnapshotTable::contracts("nft_collection_stats").ignoring(&["updated_at"]),
+    SnapshotTable::contracts("nft_metadata_stats").ignoring(&["updated_at"]),
+    SnapshotTable::contracts("nft_price_candles"),
+    SnapshotTable::unscoped("currencies").ignoring(&["updated_at"]),
+];
+
+/// A table in snapshots, with the columns that place its rows in the indexed
+/// block range.
+#[derive(Debug, Clone, Copy)]
+pub struct SnapshotTable {
+    pub name: &'static str,
+    scope: TableScope,
+    /// Columns that depend on when the indexer ran, not on the blocks
+    ignored: &'static [&'static str],
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TableScope {
+    /// Rows with any of these timestamps in the range
+    Timestamps(&'static [&'static str]),
+    /// Aggregates of contracts with activities in the range
+    Contracts,
+    /// Not tied to blocks, always dumped in full
+    Unscoped,
+}
+
+impl SnapshotTable {
+    const fn timestamps(
+        name: &'static str,
+        columns: &'static [&'static str],
+    ) -> Self {
+        Self {
+            name,
+            scope: TableScope::Timestamps(columns),
+            ignored: &[],
+        }
+    }
+
+    const fn contracts(name: &'static str) -> Self {
+        Self {
+            name,
+            scope: TableScope::Contracts,
+            ignored: &[],
+        }
+    }
+
+    const fn unscoped(name: &'static str) -> Self {
+        Self {
+            name,
+            scope: TableScope::Unscoped,
+            ignored: &[],
+        }
+    }
+
+    const fn ignoring(self, ignored: &'static [&'static str]) -> Self {
+        Self { ignored, ..self }
+    }
+
+    /// SQL condition on `t` that selects the rows within `range`
+    fn condition(&self, range: &RangeInclusive<NaiveDateTime>) -> String {
+        // whole seconds, rounding a literal to microseconds could otherwise
+        // leave out rows of the first block
+        let pattern = "%Y-%m-%d %H:%M:%S";
+        let since = range.start().format(pattern);
+        let until =
+            (*range.end() + chrono::Duration::seconds(1)).format(pattern);
+        let within = |column: &str| {
+            format!("{0} >= '{1}' AND {0} < '{2}'", column, since, until)
+        };
+        match self.scope {
+            TableScope::Timestamps(columns) => {
+                let conditions =
+                    columns.iter().map(|c| within(&format!("t.{}", c)));
+                format!("({})", conditions.collect::<Vec<_>>().join(" OR "))
+            }
+            TableScope::Contracts => format!(
+                "t.nft_contract_id IN \
+                 (SELECT a.nft_contract_id FROM nft_activities a WHERE {})",
+                within("a.timestamp")
+            ),
+            TableScope::Unscoped => "TRUE".to_string(),
+        }
+    }
+}
+
+/// Canonical dump of the indexed tables. Every row is serialized as a JSON
+/// object with sorted keys on its own line, and rows are sorted, such that
+/// two snapshots of the same data are byte-for-byte identical and diffs of
+/// the file show exactly which rows changed:
+///
+/// ```text
+/// # nft_tokens
+/// {"burned_receipt_id":null,...,"token_id":"1"}
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    tables: BTreeMap<String, Vec<String>>,
+}
+
+impl Snapshot {
+    /// Dumps `SNAPSHOT_TABLES` from the database. With a `range`, only rows
+    /// written by the blocks within it are included, such that data indexed
+    /// before does not end up in the snapshot.
+    pub fn dump(
+        pg_string: &str,
+        range: Option<&RangeInclusive<NaiveDateTime>>,
+    ) -> Result<Snapshot> {
+        use diesel::Connection;
+
+        let conn = diesel::PgConnection::establish(pg_string)?;
+        let mut snapshot = Snapshot::default();
+        for table in SNAPSHOT_TABLES {
+            let condition = range
+                .map(|range| table.condition(range))
+                .unwrap_or_else(|| "TRUE".to_string());
+            let rows = query_rows(&conn, table.name, &condition)
+                .with_context(|| format!("Failed to dump {}", table.name))?
+                .into_iter()
+                .map(|mut row| {
+                    if let Some(row) = row.as_object_mut() {
+                        for column in table.ignored {
+                            row.remove(*column);
+                        }
+                    }
+                    row
+                })
+                .collect();
+            snapshot.insert(table.name, rows);
+        }
+        Ok(snapshot)
+    }
+
+    pub fn load(path: &Path) -> Result<Snapshot> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        content
+            .parse()
+            .with_context(|| format!("Invalid snapshot {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_string())
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Inserts rows for a table, normalizing them into canonical form.
+    pub fn insert(&mut self, table: &str, rows: Vec<serde_json::Value>) {
+        let mut rows = rows
+            .into_iter()
+            .map(|row| sort_keys(row).to_string())
+            .collect::<Vec<_>>();
+        rows.sort();
+        self.tables.insert(table.to_string(), rows);
+    }
+
+    /// Rows that are only in `self` (removed) or only in `other` (added),
+    /// per table.
+    pub fn diff(&self, other: &Snapshot) -> SnapshotDiff {
+        let mut tables = BTreeMap::new();
+        let table_names = self.tables.keys().chain(other.tables.keys());
+        for table in table_names {
+            let old = self.tables.get(table).map(Vec::as_slice).unwrap_or(&[]);
+            let new = other.tables.get(table).map(Vec::as_slice).unwrap_or(&[]);
+            let removed = multiset_difference(old, new);
+            let added = multiset_difference(new, old);
+            if !removed.is_empty() || !added.is_empty() {
+                tables.insert(table.clone(), (removed, added));
+            }
+        }
+        SnapshotDiff { tables }
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (table, rows) in self.tables.iter() {
+            writeln!(f, "# {}", table)?;
+            for row in rows {
+                writeln!(f, "{}", row)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Snapshot {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut tables = BTreeMap::<String, Vec<serde_json::Value>>::new();
+        let mut current = None;
+        for (i, line) in s.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(table) = line.strip_prefix("# ") {
+                tables.entry(table.to_string()).or_default();
+                current = Some(table.to_string());
+                continue;
+            }
+            let table = current.as_ref().with_context(|| {
+                format!("Row without table at line {}", i + 1)
+            })?;
+            let row = serde_json::from_str(line)
+                .with_context(|| format!("Invalid row at line {}", i + 1))?;
+            tables.get_mut(table).unwrap().push(row);
+        }
+
+        let mut snapshot = Snapshot::default();
+        for (table, rows) in tables {
+            snapshot.insert(&table, rows);
+        }
+        Ok(snapshot)
+    }
+}
+
+/// Rows removed and added per table, empty if the snapshots are identical.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    tables: BTreeMap<String, (Vec<String>, Vec<String>)>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (table, (removed, added)) in self.tables.iter() {
+            writeln!(f, "# {}", table)?;
+            for row in removed {
+                writeln!(f, "- {}", row)?;
+            }
+            for row in added {
+                writeln!(f, "+ {}", row)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Rows of `a` that are not in `b`, both need to be sorted. Duplicates count,
+/// so that a row appearing twice instead of once shows up in the diff.
+fn multiset_difference(a: &[String], b: &[String]) -> Vec<String> {
+    let mut diff = Vec::new();
+    let mut b = b.iter().peekable();
+    for row in a {
+        while b.next_if(|other| *other < row).is_some() {}
+        if b.next_if(|other| *other == row).is_none() {
+            diff.push(row.clone());
+        }
+    }
+    diff
+}
+
+/// Objects keep their insertion order if any dependency enables the
+/// `preserve_order` feature of serde_json, so keys are sorted explicitly.
+fn sort_keys(value: serde_json::Value) -> serde_json::Value {
+    use serde_json::Value;
+
+    match value {
+        Value::Object(map) => {
+            let mut entries = map
+                .into_iter()
+                .map(|(key, value)| (key, sort_keys(value)))
+                .collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(entries.into_iter().collect())
+        }
+        Value::Array(values) => {
+            Value::Array(values.into_iter().map(sort_keys).collect())
+        }
+        value => value,
+    }
+}
+
+/// All rows of a table, as JSON objects
+pub fn table_rows(
+    conn: &diesel::PgConnection,
+    table: &str,
+) -> Result<Vec<serde_json::Value>> {
+    query_rows(conn, table, "TRUE")
+}
+
+fn query_rows(
+    conn: &diesel::PgConnection,
+    table: &str,
+    condition: &str,
+) -> Result<Vec<serde_json::Value>> {
+    use diesel::{
+        sql_types::{
+            Array,
+            Text,
+        },
+        RunQueryDsl,
+    };
+
+    let query = format!(
+        "(SELECT coalesce(array_agg(row_to_json(t)::text), '{{}}') \
+         FROM {} t WHERE {})",
+        table, condition
+    );
+    diesel::select(diesel::dsl::sql::<Array<Text>>(&query))
+        .get_result::<Vec<String>>(conn)?
+        .iter()
+        .map(|row| Ok(serde_json::from_str(row)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_snapshot_is_canonical() {
+        let mut a = Snapshot::default();
+        a.insert(
+            "nft_tokens",
+            vec![
+                json!({"token_id": "2", "owner": "bob.near"}),
+                json!({"owner": "alice.near", "token_id": "1"}),
+            ],
+        );
+        a.insert("nft_offers", vec![]);
+
+        let text = a.to_string();
+        assert_eq!(
+            text,
+            "# nft_offers\n\
+             # nft_tokens\n\
+             {\"owner\":\"alice.near\",\"token_id\":\"1\"}\n\
+             {\"owner\":\"bob.near\",\"token_id\":\"2\"}\n"
+        );
+        assert_eq!(text.parse::<Snapshot>().unwrap(), a);
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let golden: Snapshot = "# nft_tokens\n\
+             {\"owner\":\"alice.near\",\"token_id\":\"1\"}\n\
+             {\"owner\":\"alice.near\",\"token_id\":\"2\"}\n\
+             # nft_offers\n"
+            .parse()
+            .unwrap();
+        let actual: Snapshot = "# nft_tokens\n\
+             {\"owner\":\"alice.near\",\"token_id\":\"1\"}\n\
+             {\"owner\":\"bob.near\",\"token_id\":\"2\"}\n\
+             {\"owner\":\"bob.near\",\"token_id\":\"2\"}\n\
+             # nft_offers\n"
+            .parse()
+            .unwrap();
+
+        assert!(golden.diff(&golden).is_empty());
+        assert_eq!(
+            golden.diff(&actual).to_string(),
+            "# nft_tokens\n\
+             - {\"owner\":\"alice.near\",\"token_id\":\"2\"}\n\
+             + {\"owner\":\"bob.near\",\"token_id\":\"2\"}\n\
+             + {\"owner\":\"bob.near\",\"token_id\":\"2\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_table_condition() {
+        let at = |s| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S");
+        let range = at("2023-01-01 00:00:00").unwrap()
+            ..=at("2023-01-02 00:00:00").unwrap();
+
+        let offers = SnapshotTable::timestamps(
+            "nft_offers",
+            &["offered_at", "withdrawn_at"],
+        );
+        assert_eq!(
+            offers.condition(&range),
+            "(t.offered_at >= '2023-01-01 00:00:00' AND \
+             t.offered_at < '2023-01-02 00:00:01' OR \
+             t.withdrawn_at >= '2023-01-01 00:00:00' AND \
+             t.withdrawn_at < '2023-01-02 00:00:01')"
+        );
+        assert_eq!(
+            SnapshotTable::unscoped("currencies").condition(&range),
+            "TRUE"
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use anyhow::{
+    Context,
+    Result,
+};
+use chrono::{
+    NaiveDate,
+    NaiveDateTime,
+};
+use diesel::{
+    sql_types::{
+        BigInt,
+        Nullable,
+        Text,
+        Timestamp,
+    },
+    Connection,
+    PgConnection,
+    RunQueryDsl,
+};
+
+use crate::util::csv_field;
+
+/// Earnings of an account within a period, from `nft_earnings_roles`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub account_id: String,
+    /// Inclusive
+    pub from: NaiveDate,
+    /// Exclusive
+    pub to: NaiveDate,
+    /// Sorted by timestamp
+    pub lines: Vec<StatementLine>,
+    /// Per currency and role
+    pub totals: Vec<StatementTotal>,
+}
+
+/// A single earning. Amounts are decimal strings, normalized amounts and USD
+/// values are missing for unknown currencies and prices.
+#[derive(Debug, Clone, PartialEq, Eq, QueryableByName)]
+pub struct StatementLine {
+    #[sql_type = "Timestamp"]
+    pub timestamp: NaiveDateTime,
+    #[sql_type = "Text"]
+    pub receipt_id: String,
+    #[sql_type = "Text"]
+    pub nft_contract_id: String,
+    #[sql_type = "Text"]
+    pub token_id: String,
+    #[sql_type = "Text"]
+    pub market_id: String,
+    #[sql_type = "Text"]
+    pub role: String,
+    #[sql_type = "Text"]
+    pub currency: String,
+    #[sql_type = "Text"]
+    pub amount: String,
+    #[sql_type = "Nullable<Text>"]
+    pub amount_normalized: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub usd_value: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, QueryableByName)]
+pub struct StatementTotal {
+    #[sql_type = "Text"]
+    pub currency: String,
+    #[sql_type = "Text"]
+    pub role: String,
+    #[sql_type = "BigInt"]
+    pub earnings_count: i64,
+    #[sql_type = "Text"]
+    pub amount: String,
+    #[sql_type = "Nullable<Text>"]
+    pub amount_normalized: Option<String>,
+    /// Missing if any of the earnings has no USD value
+    #[sql_type = "Nullable<Text>"]
+    pub usd_value: Option<String>,
+}
+
+impl Statement {
+    pub(crate) fn query(
+        pg_string: &str,
+        account_id: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Statement> {
+        let conn = PgConnection::establish(pg_string)?;
+        let start = from.and_hms_opt(0, 0, 0).unwrap_or_default();
+        let end = to.and_hms_opt(0, 0, 0).unwrap_or_default();
+
+        let lines = diesel::sql_query(
+            "SELECT timestamp, receipt_id, nft_contract_id, token_id, \
+             market_id, role, currency, amount::TEXT AS amount, \
+             amount_normalized::TEXT AS amount_normalized, \
+             usd_value::TEXT AS usd_value \
+             FROM nft_earnings_roles \
+             WHERE receiver_id = $1 AND timestamp >= $2 AND timestamp < $3 \
+             ORDER BY timestamp, receipt_id, role",
+        )
+        .bind::<Text, _>(account_id)
+        .bind::<Timestamp, _>(start)
+        .bind::<Timestamp, _>(end)
+        .load::<StatementLine>(&conn)
+        .with_context(|| {
+            format!("Failed to query earnings of {}", account_id)
+        })?;
+
+        // `sum` skips NULLs, but a total is only known if all values are
+        let totals = diesel::sql_query(
+            "SELECT currency, role, count(*) AS earnings_count, \
+             sum(amount)::TEXT AS amount, \
+             CASE WHEN count(*) = count(amount_normalized) \
+             THEN sum(amount_normalized)::TEXT END AS amount_normalized, \
+             CASE WHEN count(*) = count(usd_value) \
+             THEN sum(usd_value)::TEXT END AS usd_value \
+             FROM nft_earnings_roles \
+             WHERE receiver_id = $1 AND timestamp >= $2 AND timestamp < $3 \
+             GROUP BY currency, role \
+             ORDER BY currency, role",
+        )
+        .bind::<Text, _>(account_id)
+        .bind::<Timestamp, _>(start)
+        .bind::<Timestamp, _>(end)
+        .load::<StatementTotal>(&conn)
+        .with_context(|| format!("Failed to query totals of {}", account_id))?;
+
+        Ok(Statement {
+            account_id: account_id.to_string(),
+            from,
+            to,
+            lines,
+            totals,
+        })
+    }
+
+    /// One row per earning
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "timestamp,receipt_id,nft_contract_id,token_id,market_id,role,\
+             currency,amount,amount_normalized,usd_value\n",
+        );
+        for line in &self.lines {
+            let fields = [
+                line.timestamp.format("%Y-%m-%dT%H:%M:%S").to_string(),
+                line.receipt_id.clone(),
+                line.nft_contract_id.clone(),
+                line.token_id.clone(),
+                line.market_id.clone(),
+                line.role.clone(),
+                line.currency.clone(),
+                line.amount.clone(),
+                line.amount_normalized.clone().unwrap_or_default(),
+                line.usd_value.clone().unwrap_or_default(),
+            ];
+            push_row(&mut csv, &fields);
+        }
+        csv
+    }
+
+    /// One row per currency and role
+    pub fn totals_to_csv(&self) -> String {
+        let mut csv = String::from(
+            "currency,role,earnings_count,amount,amount_normalized,usd_value\n",
+        );
+        for total in &self.totals {
+            let fields = [
+                total.currency.clone(),
+                total.role.clone(),
+                total.earnings_count.to_string(),
+                total.amount.clone(),
+                total.amount_normalized.clone().unwrap_or_default(),
+                total.usd_value.clone().unwrap_or_default(),
+            ];
+            push_row(&mut csv, &fields);
+        }
+        csv
+    }
+}
+
+fn push_row(csv: &mut String, fields: &[String]) {
+    let fields = fields.iter().map(|f| csv_field(f)).collect::<Vec<_>>();
+    csv.push_str(&fields.join(","));
+    csv.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statement_csv() {
+        let day = NaiveDate::from_ymd_opt(2022, 8, 1).unwrap();
+        let statement = Statement {
+            account_id: "alice.near".to_string(),
+            from: day,
+            to: day.succ_opt().unwrap(),
+            lines: vec![StatementLine {
+                timestamp: day.and_hms_opt(12, 3, 0).unwrap(),
+                receipt_id: "r1".to_string(),
+                nft_contract_id: "x.mintbase1.near".to_string(),
+                token_id: "1,2".to_string(),
+                market_id: "market.near".to_string(),
+                role: "seller".to_string(),
+                currency: "near".to_string(),
+                amount: "960000000000000000000000".to_string(),
+                amount_normalized: Some("0.96".to_string()),
+                usd_value: None,
+            }],
+            totals: vec![StatementTotal {
+                currency: "near".to_string(),
+                role: "seller".to_string(),
+                earnings_count: 1,
+                amount: "960000000000000000000000".to_string(),
+                amount_normalized: Some("0.96".to_string()),
+                usd_value: None,
+            }],
+        };
+
+        assert_eq!(
+            statement.to_csv().lines().nth(1),
+            Some(
+                "2022-08-01T12:03:00,r1,x.mintbase1.near,\"1,2\",market.near,\
+                 seller,near,960000000000000000000000,0.96,"
+            )
+        );
+        assert_eq!(
+            statement.totals_to_csv(),
+            "currency,role,earnings_count,amount,amount_normalized,\
+             usd_value\nnear,seller,1,960000000000000000000000,0.96,\n"
+        );
+    }
+}
@@ -0,0 +1,506 @@
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use actix_diesel::dsl::AsyncRunQueryDsl;
+use anyhow::{
+    Context,
+    Result,
+};
+use chrono::Timelike;
+use diesel::{
+    pg::Pg,
+    query_builder::QueryFragment,
+    query_dsl::LoadQuery,
+    PgConnection,
+    RunQueryDsl,
+};
+use futures::{
+    future::BoxFuture,
+    FutureExt,
+};
+use tracing::Instrument;
+
+use crate::{
+    database::DbConnPool,
+    dry_run::DryRun,
+};
+
+/// A change of the owner of a token, from `None` on mints and to `None` on
+/// burns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct OwnerChange {
+    pub(crate) token_id: String,
+    pub(crate) from: Option<String>,
+    pub(crate) to: Option<String>,
+}
+
+/// Statistics of a contract that changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StaleContract {
+    /// Latest timestamp the contract was touched at
+    pub(crate) as_of: chrono::NaiveDateTime,
+    /// Whether its active listings changed, whose floor prices and count are
+    /// recomputed
+    pub(crate) listings: bool,
+    /// Changes to add to its owner counts
+    pub(crate) owner_changes: Vec<OwnerChange>,
+}
+
+/// Rollups that need to be recomputed once the handlers of a block are done
+#[derive(Debug, Default)]
+pub(crate) struct Stale {
+    /// Timestamp of the latest processed live block, up to which offers
+    /// expire
+    pub(crate) block_timestamp: Option<chrono::NaiveDateTime>,
+    /// Contracts whose statistics changed
+    pub(crate) contracts: BTreeMap<String, StaleContract>,
+    /// Contracts and hours in which they had sales, whose price candles
+    /// (hourly and daily) changed
+    pub(crate) sale_hours: BTreeSet<(String, chrono::NaiveDateTime)>,
+    /// Receipts of sales, which need to be checked for wash trading (before
+    /// they are added to the statistics, which exclude flagged sales), whose
+    /// terms need to be recorded, and whose payouts need to be verified
+    /// against them
+    pub(crate) sales: BTreeSet<String>,
+}
+
+impl Stale {
+    fn contract(
+        &mut self,
+        nft_contract_id: &str,
+        timestamp: chrono::NaiveDateTime,
+    ) -> &mut StaleContract {
+        let contract = self
+            .contracts
+            .entry(nft_contract_id.to_string())
+            .or_insert_with(|| StaleContract {
+                as_of: timestamp,
+                listings: false,
+                owner_changes: Vec::new(),
+            });
+        contract.as_of = contract.as_of.max(timestamp);
+        contract
+    }
+}
+
+/// Rollups touched by the handlers: Listing statistics by the sale, list,
+/// unlist and invalidate handlers, sale statistics, price candles, wash-trade
+/// flags and payout verifications by the sale handlers, and owner statistics
+/// by mints, transfers and burns. Offer expiry only depends on the block
+/// timestamp, which the live runtime touches for every block.
+///
+/// Listing statistics only cover active listings and are recomputed, while
+/// sales and owner changes are added to the statistics, as recomputing those
+/// would go over all sales and tokens of the contract. The full recompute is
+/// left to `reconcile`.
+///
+/// Handlers of a block run concurrently, so refreshing has to wait until all
+/// of them are done, which the runtime takes care of before committing the
+/// block. In bulk mode, it happens along with the flush instead.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StaleStats(Arc<Mutex<Stale>>);
+
+impl StaleStats {
+    pub(crate) fn touch_listings(
+        &self,
+        nft_contract_id: &str,
+        timestamp: chrono::NaiveDateTime,
+    ) {
+        self.lock().contract(nft_contract_id, timestamp).listings = true;
+    }
+
+    /// Owners of `token_ids` changed from `from` to `to`
+    pub(crate) fn touch_owners(
+        &self,
+        nft_contract_id: &str,
+        timestamp: chrono::NaiveDateTime,
+        token_ids: &[String],
+        from: Option<&str>,
+        to: Option<&str>,
+    ) {
+        let mut stale = self.lock();
+        let contract = stale.contract(nft_contract_id, timestamp);
+        contract
+            .owner_changes
+            .extend(token_ids.iter().map(|token_id| OwnerChange {
+                token_id: token_id.clone(),
+                from: from.map(str::to_string),
+                to: to.map(str::to_string),
+            }));
+    }
+
+    pub(crate) fn touch_sale(
+        &self,
+        nft_contract_id: &str,
+        timestamp: chrono::NaiveDateTime,
+    ) {
+        let hour = timestamp
+            .date()
+            .and_hms_opt(timestamp.hour(), 0, 0)
+            .unwrap_or(timestamp);
+        self.lock()
+            .sale_hours
+            .insert((nft_contract_id.to_string(), hour));
+    }
+
+    pub(crate) fn touch_block(&self, timestamp: chrono::NaiveDateTime) {
+        let mut stale = self.lock();
+        stale.block_timestamp = stale.block_timestamp.max(Some(timestamp));
+    }
+
+    pub(crate) fn touch_sale_receipt(&self, receipt_id: &str) {
+        self.lock().sales.insert(receipt_id.to_string());
+    }
+
+    pub(crate) fn take(&self) -> Stale {
+        std::mem::take(&mut *self.lock())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Stale> {
+        // touching never panics while holding the lock
+        self.0.lock().expect("Stale stats are poisoned")
+    }
+}
+
+/// A statement of a refresh, which returns a count
+struct Rollup {
+    /// Names the statement in dry runs, spans and logs
+    label: &'static str,
+    /// What the statement is about, following the label in logs
+    subject: String,
+    /// Logs a nonzero count
+    report: Option<fn(&str, i64)>,
+}
+
+impl Rollup {
+    fn new(label: &'static str, subject: String) -> Rollup {
+        Rollup {
+            label,
+            subject,
+            report: None,
+        }
+    }
+
+    fn report(self, report: fn(&str, i64)) -> Rollup {
+        Rollup {
+            report: Some(report),
+            ..self
+        }
+    }
+
+    fn log(&self, count: i64) {
+        if let (Some(report), true) = (self.report, count != 0) {
+            report(&self.subject, count);
+        }
+    }
+}
+
+/// Statements that can be run as rollups, both on a pool and on the
+/// connection of a bulk flush
+trait RollupQuery:
+    RunQueryDsl<PgConnection>
+    + AsyncRunQueryDsl<PgConnection>
+    + LoadQuery<PgConnection, i64>
+    + QueryFragment<Pg>
+    + Send
+    + 'static
+{
+}
+
+impl<Q> RollupQuery for Q where
+    Q: RunQueryDsl<PgConnection>
+        + AsyncRunQueryDsl<PgConnection>
+        + LoadQuery<PgConnection, i64>
+        + QueryFragment<Pg>
+        + Send
+        + 'static
+{
+}
+
+/// Runs the statements of a refresh, as listed by `rollups`
+trait RollupRunner {
+    fn run<Q: RollupQuery>(&mut self, rollup: Rollup, query: Q);
+}
+
+/// Expires offers that ran out by the latest block, flags touched sales that
+/// look like wash trades, adds them to the statistics, refreshes the listing
+/// statistics and adds the owner changes of all touched contracts, refreshes
+/// their candles, and records the terms and verifies the payouts of touched
+/// sales, in that order.
+fn rollups(stale: Stale, runner: &mut impl RollupRunner) {
+    use crate::schema::{
+        add_nft_owner_stats,
+        add_nft_sale_stats,
+        expire_nft_offers,
+        flag_nft_sale,
+        record_nft_sale_terms,
+        refresh_nft_candles,
+        refresh_nft_stats,
+        verify_nft_payout,
+    };
+
+    if let Some(block_timestamp) = stale.block_timestamp {
+        runner.run(
+            Rollup::new("expire offers", format!("as of {}", block_timestamp))
+                .report(|_, expired| {
+                    crate::info!("Expired {} offers", expired)
+                }),
+            diesel::select(expire_nft_offers(block_timestamp)),
+        );
+    }
+    // before the statistics, which exclude flagged sales
+    for receipt_id in &stale.sales {
+        runner.run(
+            Rollup::new("flag sale", receipt_id.clone()).report(
+                |receipt_id, flags| {
+                    crate::info!(
+                        "Sale {} flagged for {} reasons",
+                        receipt_id,
+                        flags
+                    )
+                },
+            ),
+            diesel::select(flag_nft_sale(receipt_id.clone())),
+        );
+    }
+    for receipt_id in &stale.sales {
+        runner.run(
+            Rollup::new("add sale stats", format!("of {}", receipt_id)),
+            diesel::select(add_nft_sale_stats(receipt_id.clone())),
+        );
+    }
+    for (nft_contract_id, contract) in &stale.contracts {
+        if contract.listings {
+            runner.run(
+                Rollup::new(
+                    "refresh collection stats",
+                    format!("of {}", nft_contract_id),
+                ),
+                diesel::select(refresh_nft_stats(
+                    nft_contract_id.clone(),
+                    contract.as_of,
+                    vec!["listings".to_string()],
+                )),
+            );
+        }
+        if !contract.owner_changes.is_empty() {
+            let changes = &contract.owner_changes;
+            runner.run(
+                Rollup::new(
+                    "add owner stats",
+                    format!("of {}", nft_contract_id),
+                ),
+                diesel::select(add_nft_owner_stats(
+                    nft_contract_id.clone(),
+                    changes
+                        .iter()
+                        .map(|c| c.token_id.clone())
+                        .collect::<Vec<_>>(),
+                    changes.iter().map(|c| c.from.clone()).collect::<Vec<_>>(),
+                    changes.iter().map(|c| c.to.clone()).collect::<Vec<_>>(),
+                    contract.as_of,
+                )),
+            );
+        }
+    }
+    for (nft_contract_id, hour) in &stale.sale_hours {
+        runner.run(
+            Rollup::new(
+                "refresh price candles",
+                format!("of {} at {}", nft_contract_id, hour),
+            ),
+            diesel::select(refresh_nft_candles(nft_contract_id.clone(), *hour)),
+        );
+    }
+    for receipt_id in &stale.sales {
+        runner.run(
+            Rollup::new("record sale terms", format!("of {}", receipt_id)),
+            diesel::select(record_nft_sale_terms(receipt_id.clone())),
+        );
+    }
+    for receipt_id in &stale.sales {
+        runner.run(
+            Rollup::new("verify payout", format!("of {}", receipt_id)).report(
+                |subject, mismatches| {
+                    crate::warn!(
+                        "Payout {} deviates for {} receivers",
+                        subject,
+                        mismatches
+                    )
+                },
+            ),
+            diesel::select(verify_nft_payout(receipt_id.clone())),
+        );
+    }
+}
+
+/// Collects the rollups to run them on the pool one after another, or only
+/// records them in dry runs
+struct AsyncRunner<'a> {
+    db: &'a DbConnPool,
+    dry_run: Option<&'a DryRun>,
+    pending: Vec<(Rollup, BoxFuture<'static, Result<i64>>)>,
+}
+
+impl RollupRunner for AsyncRunner<'_> {
+    fn run<Q: RollupQuery>(&mut self, rollup: Rollup, query: Q) {
+        if let Some(dry_run) = self.dry_run {
+            dry_run.record_statement(
+                rollup.label,
+                diesel::debug_query::<Pg, _>(&query).to_string(),
+            );
+            return;
+        }
+        let db = self.db.clone();
+        let result = async move {
+            query
+                .get_result_async::<i64>(&db)
+                .await
+                .map_err(|e| anyhow::anyhow!("{}", e))
+        };
+        self.pending.push((rollup, result.boxed()));
+    }
+}
+
+/// Runs the rollups on the connection of a bulk flush, stopping at the first
+/// failure
+struct SyncRunner<'a> {
+    conn: &'a PgConnection,
+    result: Result<()>,
+}
+
+impl RollupRunner for SyncRunner<'_> {
+    fn run<Q: RollupQuery>(&mut self, rollup: Rollup, query: Q) {
+        if self.result.is_err() {
+            return;
+        }
+        self.result = query
+            .get_result::<i64>(self.conn)
+            .map(|count| rollup.log(count))
+            .with_context(|| {
+                format!("Failed to {} {}", rollup.label, rollup.subject)
+            });
+    }
+}
+
+/// Brings the rollups touched by the handlers up to date, see `rollups`.
+/// Failures are logged. Recomputed rollups are brought up to date again by the
+/// next refresh, while failed additions to the statistics are only made up
+/// for by the full recompute of `reconcile`.
+pub(crate) async fn refresh(
+    stale: &StaleStats,
+    db: &DbConnPool,
+    dry_run: Option<&DryRun>,
+) {
+    let mut runner = AsyncRunner {
+        db,
+        dry_run,
+        pending: Vec::new(),
+    };
+    rollups(stale.take(), &mut runner);
+
+    for (rollup, result) in runner.pending {
+        let span = tracing::info_span!(
+            target: "minterop",
+            "refresh_rollup",
+            rollup = rollup.label,
+            subject = %rollup.subject
+        );
+        match result.instrument(span).await {
+            Ok(count) => rollup.log(count),
+            Err(e) => crate::error!(
+                "Failed to {} {}: {}",
+                rollup.label,
+                rollup.subject,
+                e
+            ),
+        }
+    }
+}
+
+/// Same as `refresh`, on the connection of a bulk flush
+pub(crate) fn refresh_sync(conn: &PgConnection, stale: Stale) -> Result<()> {
+    let mut runner = SyncRunner {
+        conn,
+        result: Ok(()),
+    };
+    rollups(stale, &mut runner);
+    runner.result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(h: u32, m: u32) -> chrono::NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2022, 8, 1)
+            .unwrap()
+            .and_hms_opt(h, m, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_touch_keeps_latest_timestamp() {
+        let stale = StaleStats::default();
+        let tokens = vec!["1".to_string(), "2".to_string()];
+        stale.touch_owners("a.near", at(12, 0), &tokens, None, Some("bob"));
+        stale.touch_listings("a.near", at(10, 0));
+        stale.touch_owners(
+            "b.near",
+            at(11, 0),
+            &tokens[..1],
+            Some("bob"),
+            None,
+        );
+
+        let touched = stale.take().contracts;
+        assert_eq!(touched["a.near"].as_of, at(12, 0));
+        assert!(touched["a.near"].listings);
+        assert_eq!(touched["a.near"].owner_changes.len(), 2);
+        assert_eq!(touched["b.near"].as_of, at(11, 0));
+        assert!(!touched["b.near"].listings);
+        assert_eq!(
+            touched["b.near"].owner_changes,
+            vec![OwnerChange {
+                token_id: "1".to_string(),
+                from: Some("bob".to_string()),
+                to: None,
+            }]
+        );
+        assert!(stale.take().contracts.is_empty());
+    }
+
+    #[test]
+    fn test_touch_sale_truncates_to_hour() {
+        let stale = StaleStats::default();
+        stale.touch_sale("a.near", at(12, 1));
+        stale.touch_sale("a.near", at(12, 59));
+        stale.touch_sale("a.near", at(13, 0));
+
+        let hours = stale.take().sale_hours.into_iter().collect::<Vec<_>>();
+        assert_eq!(
+            hours,
+            vec![
+                ("a.near".to_string(), at(12, 0)),
+                ("a.near".to_string(), at(13, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_touch_block_keeps_latest_timestamp() {
+        let stale = StaleStats::default();
+        assert_eq!(stale.take().block_timestamp, None);
+        stale.touch_block(at(12, 1));
+        stale.touch_block(at(12, 0));
+        assert_eq!(stale.take().block_timestamp, Some(at(12, 1)));
+    }
+}
@@ -0,0 +1,62 @@
+use anyhow::Result;
+use opentelemetry::{
+    sdk::{
+        propagation::TraceContextPropagator,
+        trace::{
+            self,
+            Tracer,
+        },
+        Resource,
+    },
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+const DEFAULT_SERVICE_NAME: &str = "minterop-producer";
+
+/// Sets up the OTLP exporter and returns the tracer that is to be plugged into
+/// the `tracing` subscriber. Spans are exported in batches on a separate
+/// thread, so this doesn't interfere with the actix runtime.
+pub(crate) fn init_tracer(
+    endpoint: &str,
+    service_name: Option<&str>,
+) -> Result<Tracer> {
+    opentelemetry::global::set_text_map_propagator(
+        TraceContextPropagator::new(),
+    );
+
+    let service_name = service_name.unwrap_or(DEFAULT_SERVICE_NAME);
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(trace::config().with_resource(Resource::new(vec![
+            KeyValue::new("service.name", service_name.to_string()),
+        ])))
+        .install_batch(opentelemetry::runtime::TokioCurrentThread)?;
+
+    Ok(tracer)
+}
+
+/// Writes the context of the current span into the request headers
+/// (`traceparent`/`tracestate`), such that the consumer can continue the
+/// trace. Without an initialized tracer, this doesn't add any headers.
+pub(crate) fn inject_trace_context(req: &mut hyper::Request<hyper::Body>) {
+    let cx = tracing::Span::current().context();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(
+            &cx,
+            &mut opentelemetry_http::HeaderInjector(req.headers_mut()),
+        )
+    });
+}
+
+/// Flushes all pending spans to the collector. Should be called before the
+/// process exits, otherwise the last batch of spans will be lost.
+pub fn shutdown_tracer() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -19,6 +19,16 @@ pub(crate) fn map_fractions_to_u16(
         .collect()
 }
 
+/// Quotes a CSV field if necessary. Account IDs never need quoting, but token
+/// IDs might, and this is no place to rely on either.
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
@@ -51,4 +61,10 @@ mod tests {
             get_u16_map()
         )
     }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("alice.near"), "alice.near");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
 }
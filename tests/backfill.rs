@@ -0,0 +1,136 @@
+//! Backfills and replays a fixture next to a (pretend) live indexer. Requires
+//! `POSTGRES` like the handler tests.
+
+mod harness;
+
+use diesel::RunQueryDsl;
+
+const LIVE_HEIGHT: u64 = 80000000;
+
+#[actix_rt::test]
+async fn backfill_keeps_live_cursor() {
+    let fixture = harness::Fixture::load("nft_mint");
+    let db = harness::TestDb::create("backfill_nft_mint");
+    let source = harness::jsonl_source("backfill_nft_mint", &fixture);
+    let cfg = harness::fixture_config(&db, &fixture, source);
+    db.connection()
+        .execute(&format!(
+            "UPDATE blocks SET synced_height = {}",
+            LIVE_HEIGHT
+        ))
+        .unwrap();
+
+    let contracts = vec!["fixture.mintbase1.near".to_string()];
+    let (start, stop) = (fixture.start_height(), fixture.stop_height());
+    let job = cfg
+        .register_backfill("mint", &contracts, start, stop)
+        .unwrap();
+    assert_eq!(job.resume_height(), start);
+
+    let rt = cfg.get_backfill_runtime(&job).unwrap();
+    let (handle, streamer) = cfg
+        .block_source()
+        .stream(job.resume_height(), Some(job.stop_height));
+    rt.handle_stream(streamer, minterop_indexer::no_shutdown())
+        .await;
+    minterop_indexer::join_lake_handle(handle).await;
+
+    let blocks = db.rows("blocks");
+    assert_eq!(blocks[0]["synced_height"], LIVE_HEIGHT);
+    let job = cfg.backfill_job("mint").unwrap();
+    assert_eq!(job.synced_height, Some(stop));
+    assert!(job.is_finished());
+
+    // resuming needs the same job definition
+    assert!(cfg
+        .register_backfill("mint", &contracts, start, stop)
+        .is_ok());
+    assert!(cfg
+        .register_backfill("mint", &["other.near".to_string()], start, stop)
+        .is_err());
+
+    harness::assert_fixture(&fixture, db);
+}
+
+#[actix_rt::test]
+async fn replay_keeps_live_cursor() {
+    let fixture = harness::Fixture::load("nft_mint");
+    let db = harness::TestDb::create("replay_nft_mint");
+    let source = harness::jsonl_source("replay_nft_mint", &fixture);
+    let cfg = harness::fixture_config(&db, &fixture, source);
+    db.connection()
+        .execute(&format!(
+            "UPDATE blocks SET synced_height = {}, journal_height = {}",
+            LIVE_HEIGHT, LIVE_HEIGHT
+        ))
+        .unwrap();
+
+    let rt = cfg.get_replay_runtime().unwrap();
+    let (handle, streamer) = cfg.connect_blocks();
+    rt.handle_stream(streamer, minterop_indexer::no_shutdown())
+        .await;
+    minterop_indexer::join_lake_handle(handle).await;
+
+    // neither the cursor nor the journal of the live indexer moved
+    let blocks = db.rows("blocks");
+    assert_eq!(blocks[0]["synced_height"], LIVE_HEIGHT);
+    assert_eq!(blocks[0]["journal_height"], LIVE_HEIGHT);
+
+    harness::assert_fixture(&fixture, db);
+}
+
+#[actix_rt::test]
+async fn bulk_backfill_matches_fixture() {
+    let fixture = harness::Fixture::load("market_v02_nft_sale_030");
+    let db = harness::TestDb::create("bulk_market_v02_nft_sale_030");
+    let source = minterop_indexer::ConfigOverrides {
+        bulk_flush_blocks: Some(2),
+        ..harness::jsonl_source("bulk_market_v02_nft_sale_030", &fixture)
+    };
+    let cfg = harness::fixture_config(&db, &fixture, source);
+
+    let (start, stop) = (fixture.start_height(), fixture.stop_height());
+    let job = cfg.register_backfill("bulk", &[], start, stop).unwrap();
+    let rt = cfg.get_backfill_runtime(&job).unwrap();
+    let (handle, streamer) = cfg
+        .block_source()
+        .stream(job.resume_height(), Some(job.stop_height));
+    rt.handle_stream(streamer, minterop_indexer::no_shutdown())
+        .await;
+    minterop_indexer::join_lake_handle(handle).await;
+
+    // the last flush marks the job as finished
+    let job = cfg.backfill_job("bulk").unwrap();
+    assert!(job.is_finished());
+
+    harness::assert_fixture(&fixture, db);
+}
+
+#[actix_rt::test]
+async fn backfill_leaves_offer_expiry_to_live() {
+    let fixture = harness::Fixture::load("market_v01_offer_expiry");
+    let db = harness::TestDb::create("backfill_market_v01_offer_expiry");
+    let source =
+        harness::jsonl_source("backfill_market_v01_offer_expiry", &fixture);
+    let cfg = harness::fixture_config(&db, &fixture, source);
+
+    let (start, stop) = (fixture.start_height(), fixture.stop_height());
+    let job = cfg.register_backfill("expiry", &[], start, stop).unwrap();
+    let rt = cfg.get_backfill_runtime(&job).unwrap();
+    let (handle, streamer) = cfg
+        .block_source()
+        .stream(job.resume_height(), Some(job.stop_height));
+    rt.handle_stream(streamer, minterop_indexer::no_shutdown())
+        .await;
+    minterop_indexer::join_lake_handle(handle).await;
+
+    // the offer ran out by the last block, but only live blocks sweep
+    let offers = db.rows("nft_offers");
+    assert_eq!(offers.len(), 1);
+    assert!(offers[0]["expired_at"].is_null());
+    assert!(db
+        .rows("nft_activities")
+        .iter()
+        .all(|activity| activity["kind"] != "expire_offer"));
+    db.drop_db();
+}
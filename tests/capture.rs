@@ -0,0 +1,53 @@
+//! Captures fixture blocks for a single contract and reads them back through
+//! the directory block source. Doesn't require a database.
+
+mod harness;
+
+use minterop_indexer::BlockSource;
+
+#[actix_rt::test]
+async fn captures_only_blocks_of_contracts() {
+    let fixture = harness::Fixture::load("market_v01_nft_sold");
+    // unique per test and process, such that concurrent runs don't collide
+    let dir = std::env::temp_dir().join(format!(
+        "minterop_capture_only_blocks_of_contracts_{}",
+        std::process::id()
+    ));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+    let blocks_path = dir.join("blocks.jsonl");
+    std::fs::write(&blocks_path, fixture.to_jsonl()).unwrap();
+    let out = dir.join("captured");
+
+    let (handle, streamer) =
+        BlockSource::Jsonl(blocks_path).stream(fixture.start_height(), None);
+    let summary = minterop_indexer::capture(
+        streamer,
+        fixture.stop_height(),
+        &["fixture.mintbase1.near".to_string()],
+        &out,
+        minterop_indexer::no_shutdown(),
+    )
+    .await
+    .unwrap();
+    minterop_indexer::join_lake_handle(handle).await;
+
+    // only the mint is executed by the store, the rest by the market
+    assert_eq!(summary.blocks, 1);
+    assert_eq!(summary.receipts, 1);
+
+    let (handle, mut streamer) = BlockSource::Directory(out).stream(0, None);
+    let msg = streamer.recv().await.expect("No captured block");
+    assert_eq!(msg.block.header.height, fixture.start_height());
+    let receivers = msg
+        .shards
+        .iter()
+        .flat_map(|shard| shard.receipt_execution_outcomes.iter())
+        .map(|outcome| outcome.receipt.receiver_id.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(receivers, vec!["fixture.mintbase1.near"]);
+    assert!(streamer.recv().await.is_none());
+    minterop_indexer::join_lake_handle(handle).await;
+
+    std::fs::remove_dir_all(&dir).ok();
+}
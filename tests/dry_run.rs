@@ -0,0 +1,37 @@
+//! Dry runs a fixture, which must only be reported and not written. Requires
+//! `POSTGRES` like the handler tests.
+
+mod harness;
+
+#[actix_rt::test]
+async fn dry_run_reports_without_writing() {
+    let fixture = harness::Fixture::load("nft_mint");
+    let db = harness::TestDb::create("dry_run_nft_mint");
+    let source = harness::jsonl_source("dry_run_nft_mint", &fixture);
+    let cfg = harness::fixture_config(&db, &fixture, source);
+
+    let (rt, dry_run) = cfg.get_dry_run_runtime().unwrap();
+    let (handle, streamer) = cfg.connect_blocks();
+    rt.handle_stream(streamer, minterop_indexer::no_shutdown())
+        .await;
+    minterop_indexer::join_lake_handle(handle).await;
+
+    let report = dry_run.report();
+    assert!(report.blocks > 0);
+    assert_eq!(report.events.values().sum::<u64>(), 1);
+    assert!(report.events.keys().all(|key| key.ends_with("/nft_mint")));
+    let tokens = &report.statements["insert token on mint"];
+    assert_eq!(tokens.count, 1);
+    assert!(tokens.samples[0]
+        .as_str()
+        .unwrap()
+        .starts_with("INSERT INTO \"nft_tokens\""));
+    assert!(report.rpc_messages.contains_key("token"));
+
+    for table in fixture.expected_tables() {
+        assert!(db.rows(&table).is_empty(), "{} has been written", table);
+    }
+    let blocks = db.rows("blocks");
+    assert_ne!(blocks[0]["synced_height"], fixture.stop_height());
+    db.drop_db();
+}
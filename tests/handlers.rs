@@ -0,0 +1,63 @@
+//! End-to-end tests for the event handlers, one fixture per arm of
+//! `handle_log`. Requires `POSTGRES` to point to a server on which databases
+//! can be created.
+
+mod harness;
+
+macro_rules! fixture_tests {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[actix_rt::test]
+            async fn $name() {
+                harness::run_fixture(stringify!($name)).await;
+            }
+        )*
+    };
+}
+
+// --------------------------------- nep171 --------------------------------- //
+fixture_tests!(
+    nft_mint,
+    nft_transfer,
+    nft_transfer_within_receipt,
+    nft_burn,
+    contract_metadata_update,
+    nft_metadata_update,
+);
+
+// -------------------------------- mb_store -------------------------------- //
+fixture_tests!(
+    create_metadata,
+    nft_approve,
+    nft_revoke,
+    nft_revoke_all,
+    nft_set_split_owners,
+    mb_store_deploy,
+    mb_store_change_setting,
+);
+
+// ------------------------------- mb_market -------------------------------- //
+fixture_tests!(
+    market_v01_nft_list,
+    market_v01_nft_unlist,
+    market_v01_nft_update_list,
+    market_v01_nft_sold,
+    market_v01_nft_make_offer,
+    market_v01_nft_withdraw_offer,
+    market_v01_offer_expiry,
+    market_v02_nft_list,
+    market_v02_nft_unlist,
+    market_v02_nft_sale_022,
+    market_v02_nft_sale_030,
+    market_v02_nft_make_offer_021,
+    market_v02_nft_make_offer_030,
+    market_v02_payout_mismatch,
+    market_v02_payout_after_transfer,
+    market_v02_wash_trade,
+);
+
+// --------------------------------- paras ---------------------------------- //
+fixture_tests!(paras_resolve_purchase);
+
+// --------------------------------- forks ---------------------------------- //
+fixture_tests!(fork_rollback);
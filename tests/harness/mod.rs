@@ -0,0 +1,551 @@
+//! Feeds block fixtures through `MintlakeRuntime` into a throwaway database
+//! and compares the resulting rows against the expectation of the fixture.
+//!
+//! Fixtures live in `tests/fixtures/<name>.json` and use a compact format that
+//! only contains what the indexer actually looks at:
+//!
+//! ```json
+//! {
+//!   "blocks": [{
+//!     "height": 70000000,
+//!     "timestamp": "2022-08-01T12:00:00",
+//!     "receipts": [{
+//!       "id": "<base58 receipt ID>",
+//!       "predecessor": "alice.near",
+//!       "receiver": "store.mintbase1.near",
+//!       "logs": [{ "standard": "nep171", "version": "1.0.0", ... }]
+//!     }]
+//!   }],
+//!   "expected": {
+//!     "nft_tokens": [{ "token_id": "1", "owner": "alice.near" }]
+//!   }
+//! }
+//! ```
+//!
+//! Logs given as objects are serialized with the `EVENT_JSON:` prefix, strings
+//! are used verbatim. Expected rows only need to contain the columns that the
+//! fixture cares about (missing ones in a partially filled row are expected to
+//! be null), but the number of rows has to match exactly. Tables in
+//! `CHECKED_TABLES` are always compared, a missing entry means that the table
+//! has to be empty.
+//!
+//! Each block builds on the last preceding block of lower height, so forks are
+//! expressed by repeating heights, with a `fork` label to get distinct hashes.
+//! `finality` is passed on to the indexer and defaults to `final`.
+
+// shared between test crates, which each only use parts of it
+#![allow(dead_code)]
+
+mod rpc;
+
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet,
+    },
+    path::PathBuf,
+};
+
+use diesel::{
+    Connection,
+    PgConnection,
+    RunQueryDsl,
+};
+use minterop_indexer::{
+    Config,
+    ConfigOverrides,
+};
+use near_lake_framework::near_indexer_primitives::CryptoHash;
+use serde_json::{
+    json,
+    Value,
+};
+
+pub use self::rpc::MockRpc;
+
+/// Tables that need to match the expectation for every fixture
+pub const CHECKED_TABLES: &[&str] = &[
+    "nft_tokens",
+    "nft_activities",
+    "nft_listings",
+    "nft_offers",
+    "nft_earnings",
+];
+
+const DEFAULT_PUBLIC_KEY: &str =
+    "ed25519:B4Htg9sN8gYS8HJyPitjckFZiyDsfQXPNXmSGMDnsdT6";
+/// Unreachable, RPC dispatches fail immediately and are only logged
+const RPC_URL: &str = "http://127.0.0.1:9";
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fixture {
+    finality: Option<String>,
+    blocks: Vec<FixtureBlock>,
+    #[serde(default)]
+    expected: BTreeMap<String, Vec<Value>>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FixtureBlock {
+    height: u64,
+    /// Distinguishes blocks of the same height on different forks
+    fork: Option<String>,
+    /// `%Y-%m-%dT%H:%M:%S`, in UTC
+    timestamp: String,
+    #[serde(default)]
+    receipts: Vec<FixtureReceipt>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FixtureReceipt {
+    id: String,
+    predecessor: String,
+    receiver: String,
+    /// Defaults to the predecessor
+    signer: Option<String>,
+    public_key: Option<String>,
+    #[serde(default)]
+    logs: Vec<Value>,
+    /// `ActionView`s, defaults to a single function call
+    actions: Option<Vec<Value>>,
+}
+
+impl Fixture {
+    pub fn load(name: &str) -> Fixture {
+        let path = fixture_path(name);
+        let content = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+            panic!("Failed to read fixture {}: {}", path.display(), e)
+        });
+        serde_json::from_str(&content).unwrap_or_else(|e| {
+            panic!("Invalid fixture {}: {}", path.display(), e)
+        })
+    }
+
+    pub fn start_height(&self) -> u64 {
+        self.blocks.iter().map(|b| b.height).min().unwrap_or(0)
+    }
+
+    pub fn stop_height(&self) -> u64 {
+        self.blocks.iter().map(|b| b.height).max().unwrap_or(0)
+    }
+
+    /// Expands the fixture into `StreamerMessage`s, serialized one per line.
+    pub fn to_jsonl(&self) -> String {
+        let mut lines = Vec::new();
+        for (i, block) in self.blocks.iter().enumerate() {
+            let (prev_height, prev_hash) = self.blocks[..i]
+                .iter()
+                .rev()
+                .find(|prev| prev.height < block.height)
+                .map(|prev| (prev.height, prev.hash()))
+                .unwrap_or_else(|| {
+                    (block.height - 1, block_hash(block.height - 1, None))
+                });
+            let msg = block.to_streamer_message(
+                &block.hash(),
+                prev_height,
+                &prev_hash,
+            );
+            lines.push(msg.to_string());
+        }
+        lines.join("\n")
+    }
+
+    pub fn expected_tables(&self) -> BTreeSet<String> {
+        CHECKED_TABLES
+            .iter()
+            .map(|t| t.to_string())
+            .chain(self.expected.keys().cloned())
+            .collect()
+    }
+
+    pub fn expected_rows(&self, table: &str) -> &[Value] {
+        self.expected
+            .get(table)
+            .map(|v| v.as_slice())
+            .unwrap_or(&[])
+    }
+}
+
+impl FixtureBlock {
+    fn hash(&self) -> String {
+        block_hash(self.height, self.fork.as_deref())
+    }
+    fn timestamp_nanosec(&self) -> u64 {
+        let timestamp = chrono::NaiveDateTime::parse_from_str(
+            &self.timestamp,
+            "%Y-%m-%dT%H:%M:%S",
+        )
+        .unwrap_or_else(|e| {
+            panic!("Invalid timestamp {:?}: {}", self.timestamp, e)
+        });
+        timestamp.timestamp_nanos() as u64
+    }
+
+    fn to_streamer_message(
+        &self,
+        hash: &str,
+        prev_height: u64,
+        prev_hash: &str,
+    ) -> Value {
+        let nanos = self.timestamp_nanosec();
+        let chunk_header = json!({
+            "chunk_hash": hash,
+            "prev_block_hash": prev_hash,
+            "outcome_root": zero_hash(),
+            "prev_state_root": zero_hash(),
+            "encoded_merkle_root": zero_hash(),
+            "encoded_length": 0,
+            "height_created": self.height,
+            "height_included": self.height,
+            "shard_id": 0,
+            "gas_used": 0,
+            "gas_limit": 0,
+            "rent_paid": "0",
+            "validator_reward": "0",
+            "balance_burnt": "0",
+            "outgoing_receipts_root": zero_hash(),
+            "tx_root": zero_hash(),
+            "validator_proposals": [],
+            "signature": zero_signature(),
+        });
+        let outcomes = self
+            .receipts
+            .iter()
+            .map(|receipt| receipt.to_outcome_with_receipt(hash))
+            .collect::<Vec<_>>();
+
+        json!({
+            "block": {
+                "author": "fixture.near",
+                "header": {
+                    "height": self.height,
+                    "prev_height": prev_height,
+                    "epoch_id": zero_hash(),
+                    "next_epoch_id": zero_hash(),
+                    "hash": hash,
+                    "prev_hash": prev_hash,
+                    "prev_state_root": zero_hash(),
+                    "chunk_receipts_root": zero_hash(),
+                    "chunk_headers_root": zero_hash(),
+                    "chunk_tx_root": zero_hash(),
+                    "outcome_root": zero_hash(),
+                    "chunks_included": 1,
+                    "challenges_root": zero_hash(),
+                    "timestamp": nanos,
+                    "timestamp_nanosec": nanos.to_string(),
+                    "random_value": zero_hash(),
+                    "validator_proposals": [],
+                    "chunk_mask": [true],
+                    "gas_price": "0",
+                    "block_ordinal": null,
+                    "rent_paid": "0",
+                    "validator_reward": "0",
+                    "total_supply": "0",
+                    "challenges_result": [],
+                    "last_final_block": prev_hash,
+                    "last_ds_final_block": prev_hash,
+                    "next_bp_hash": zero_hash(),
+                    "block_merkle_root": zero_hash(),
+                    "epoch_sync_data_hash": null,
+                    "approvals": [],
+                    "signature": zero_signature(),
+                    "latest_protocol_version": 59,
+                },
+                "chunks": [chunk_header],
+            },
+            "shards": [{
+                "shard_id": 0,
+                "chunk": {
+                    "author": "fixture.near",
+                    "header": chunk_header,
+                    "transactions": [],
+                    "receipts": [],
+                },
+                "receipt_execution_outcomes": outcomes,
+                "state_changes": [],
+            }],
+        })
+    }
+}
+
+impl FixtureReceipt {
+    fn to_outcome_with_receipt(&self, block_hash: &str) -> Value {
+        let logs = self
+            .logs
+            .iter()
+            .map(|log| match log {
+                Value::String(log) => log.clone(),
+                event => format!("EVENT_JSON:{}", event),
+            })
+            .collect::<Vec<_>>();
+        let actions = self.actions.clone().unwrap_or_else(|| {
+            vec![json!({
+                "FunctionCall": {
+                    "method_name": "fixture",
+                    "args": "e30=",
+                    "gas": 0,
+                    "deposit": "0",
+                }
+            })]
+        });
+
+        json!({
+            "execution_outcome": {
+                "proof": [],
+                "block_hash": block_hash,
+                "id": self.id,
+                "outcome": {
+                    "logs": logs,
+                    "receipt_ids": [],
+                    "gas_burnt": 0,
+                    "tokens_burnt": "0",
+                    "executor_id": self.receiver,
+                    "status": { "SuccessValue": "" },
+                    "metadata": { "version": 1, "gas_profile": null },
+                },
+            },
+            "receipt": {
+                "predecessor_id": self.predecessor,
+                "receiver_id": self.receiver,
+                "receipt_id": self.id,
+                "receipt": {
+                    "Action": {
+                        "signer_id": self
+                            .signer
+                            .as_ref()
+                            .unwrap_or(&self.predecessor),
+                        "signer_public_key": self
+                            .public_key
+                            .as_deref()
+                            .unwrap_or(DEFAULT_PUBLIC_KEY),
+                        "gas_price": "0",
+                        "output_data_receivers": [],
+                        "input_data_ids": [],
+                        "actions": actions,
+                    }
+                },
+            },
+        })
+    }
+}
+
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(format!("{}.json", name))
+}
+
+fn zero_hash() -> String {
+    CryptoHash::default().to_string()
+}
+
+fn zero_signature() -> String {
+    format!("ed25519:{}", "1".repeat(64))
+}
+
+/// Deterministic block hashes, such that each block references its parent
+fn block_hash(height: u64, fork: Option<&str>) -> String {
+    let mut bytes = height.to_le_bytes().to_vec();
+    bytes.extend(fork.unwrap_or_default().as_bytes());
+    CryptoHash::hash_bytes(&bytes).to_string()
+}
+
+/// A database that only lives for the duration of a single fixture. It is
+/// only dropped if the fixture passes, to allow inspecting failures.
+pub struct TestDb {
+    admin_url: String,
+    name: String,
+}
+
+impl TestDb {
+    /// Creates the database on the server that `POSTGRES` points to.
+    pub fn create(fixture: &str) -> TestDb {
+        let admin_url = std::env::var("POSTGRES")
+            .expect("Environment variable POSTGRES needs to be defined!");
+        let db = TestDb {
+            admin_url,
+            name: format!("minterop_fixture_{}", fixture),
+        };
+        db.drop_db();
+        db.admin_connection()
+            .execute(&format!(r#"CREATE DATABASE "{}""#, db.name))
+            .expect("Failed to create fixture database");
+        db
+    }
+
+    pub fn url(&self) -> String {
+        match self.admin_url.rsplit_once('/') {
+            Some((server, _)) => format!("{}/{}", server, self.name),
+            None => panic!("Invalid POSTGRES url: {}", self.admin_url),
+        }
+    }
+
+    pub fn connection(&self) -> PgConnection {
+        PgConnection::establish(&self.url())
+            .expect("Failed to connect to fixture database")
+    }
+
+    /// All rows of a table, as JSON objects
+    pub fn rows(&self, table: &str) -> Vec<Value> {
+        minterop_indexer::table_rows(&self.connection(), table)
+            .unwrap_or_else(|e| panic!("Failed to query {}: {}", table, e))
+    }
+
+    pub fn drop_db(&self) {
+        self.admin_connection()
+            .execute(&format!(
+                r#"DROP DATABASE IF EXISTS "{}" WITH (FORCE)"#,
+                self.name
+            ))
+            .expect("Failed to drop fixture database");
+    }
+
+    fn admin_connection(&self) -> PgConnection {
+        PgConnection::establish(&self.admin_url)
+            .unwrap_or_else(|e| panic!("Failed to connect to postgres: {}", e))
+    }
+}
+
+/// Indexes the fixture into a fresh database, which is returned for
+/// assertions.
+pub async fn index_fixture(name: &str, fixture: &Fixture) -> TestDb {
+    index_from_source(name, fixture, jsonl_source(name, fixture)).await
+}
+
+/// Same as `index_fixture`, but blocks are polled from a `MockRpc` serving
+/// the fixture.
+pub async fn index_fixture_via_rpc(name: &str, fixture: &Fixture) -> TestDb {
+    let rpc = MockRpc::start(fixture);
+    let source = ConfigOverrides {
+        blocks_rpc_url: Some(rpc.url()),
+        ..Default::default()
+    };
+    index_from_source(&format!("rpc_{}", name), fixture, source).await
+}
+
+/// Writes the blocks of the fixture to a JSONL file, and returns the config
+/// overrides to read them from there.
+pub fn jsonl_source(name: &str, fixture: &Fixture) -> ConfigOverrides {
+    let blocks_path =
+        std::env::temp_dir().join(format!("minterop_fixture_{}.jsonl", name));
+    std::fs::write(&blocks_path, fixture.to_jsonl())
+        .expect("Failed to write fixture blocks");
+
+    ConfigOverrides {
+        blocks_path: Some(blocks_path.display().to_string()),
+        ..Default::default()
+    }
+}
+
+/// Config for indexing the fixture from the block source configured in
+/// `source` into `db`, which is migrated.
+pub fn fixture_config(
+    db: &TestDb,
+    fixture: &Fixture,
+    source: ConfigOverrides,
+) -> Config {
+    let overrides = ConfigOverrides {
+        network: Some("mainnet".to_string()),
+        postgres: Some(db.url()),
+        start_block_height: Some(fixture.start_height()),
+        stop_block_height: Some(fixture.stop_height()),
+        rpc_url: Some(RPC_URL.to_string()),
+        db_pool_size: Some(4),
+        finality: fixture.finality.clone(),
+        ..source
+    };
+    let cfg = Config::from_env_with(&overrides).expect("Invalid config");
+    cfg.migrate_db()
+        .expect("Failed to migrate fixture database");
+    cfg
+}
+
+async fn index_from_source(
+    name: &str,
+    fixture: &Fixture,
+    source: ConfigOverrides,
+) -> TestDb {
+    let db = TestDb::create(name);
+    let cfg = fixture_config(&db, fixture, source);
+    let rt = cfg.get_runtime().expect("Failed to initialize runtime");
+
+    let (handle, streamer) = cfg.connect_blocks();
+    rt.handle_stream(streamer, minterop_indexer::no_shutdown())
+        .await;
+    minterop_indexer::join_lake_handle(handle).await;
+
+    db
+}
+
+/// Runs a fixture and asserts that all checked tables match exactly.
+pub async fn run_fixture(name: &str) {
+    let fixture = Fixture::load(name);
+    let db = index_fixture(name, &fixture).await;
+    assert_fixture(&fixture, db);
+}
+
+/// Same as `run_fixture`, with blocks being polled from a `MockRpc`.
+pub async fn run_fixture_via_rpc(name: &str) {
+    let fixture = Fixture::load(name);
+    let db = index_fixture_via_rpc(name, &fixture).await;
+    assert_fixture(&fixture, db);
+}
+
+/// Asserts that all checked tables match exactly, and drops the database.
+pub fn assert_fixture(fixture: &Fixture, db: TestDb) {
+    for table in fixture.expected_tables() {
+        assert_rows(&table, fixture.expected_rows(&table), db.rows(&table));
+    }
+
+    db.drop_db();
+}
+
+/// Compares rows regardless of their order, and only on the columns that
+/// appear in the expectation. Columns that are missing from some expected rows
+/// are expected to be null for those.
+pub fn assert_rows(table: &str, expected: &[Value], actual: Vec<Value>) {
+    let columns = expected
+        .iter()
+        .filter_map(|row| row.as_object())
+        .flat_map(|row| row.keys().cloned())
+        .collect::<BTreeSet<_>>();
+
+    let mut actual = actual
+        .iter()
+        .map(|row| project(row, &columns))
+        .collect::<Vec<_>>();
+    let mut expected = expected
+        .iter()
+        .map(|row| project(row, &columns))
+        .collect::<Vec<_>>();
+    actual.sort_by_key(|row| row.to_string());
+    expected.sort_by_key(|row| row.to_string());
+
+    assert_eq!(
+        actual,
+        expected,
+        "Rows in {} don't match\n actual: {}\n expected: {}",
+        table,
+        serde_json::to_string_pretty(&actual).unwrap(),
+        serde_json::to_string_pretty(&expected).unwrap(),
+    );
+}
+
+fn project(row: &Value, columns: &BTreeSet<String>) -> Value {
+    Value::Object(
+        columns
+            .iter()
+            .map(|column| {
+                (
+                    column.clone(),
+                    row.get(column).cloned().unwrap_or(Value::Null),
+                )
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,226 @@
+//! Minimal NEAR JSON-RPC node serving the blocks of a fixture, for the methods
+//! that the RPC block source calls.
+//!
+//! Each fixture receipt is the only receipt of its own transaction, which is
+//! included in the chunk of the block that executes the receipt. Receipts with
+//! the signer as predecessor are served as the receipt that the transaction
+//! has been converted to, others are served via the `receipts` field.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use hyper::{
+    service::{
+        make_service_fn,
+        service_fn,
+    },
+    Body,
+    Request,
+    Response,
+};
+use near_lake_framework::near_indexer_primitives::CryptoHash;
+use serde_json::{
+    json,
+    Value,
+};
+
+use super::Fixture;
+
+pub struct MockRpc {
+    addr: SocketAddr,
+}
+
+impl MockRpc {
+    /// Starts serving on a random local port. The server lives as long as the
+    /// runtime it has been started on.
+    pub fn start(fixture: &Fixture) -> MockRpc {
+        let chain = Arc::new(MockChain::new(fixture));
+        let make_service = make_service_fn(move |_| {
+            let chain = chain.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    handle_request(chain.clone(), req)
+                }))
+            }
+        });
+        let server =
+            hyper::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+                .serve(make_service);
+        let addr = server.local_addr();
+        actix_rt::spawn(server);
+        MockRpc { addr }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+}
+
+#[derive(Default)]
+struct MockChain {
+    head: Value,
+    /// The last block of each height, i.e. orphaned blocks are only
+    /// available by hash
+    blocks_by_height: HashMap<u64, Value>,
+    blocks_by_hash: HashMap<String, Value>,
+    chunks: HashMap<String, Value>,
+    txs: HashMap<String, Value>,
+}
+
+impl MockChain {
+    fn new(fixture: &Fixture) -> MockChain {
+        let mut chain = MockChain::default();
+        for line in fixture.to_jsonl().lines() {
+            let msg: Value = serde_json::from_str(line).unwrap();
+            chain.add_block(&msg);
+        }
+        chain
+    }
+
+    fn add_block(&mut self, msg: &Value) {
+        let block = &msg["block"];
+        let height = block["header"]["height"].as_u64().unwrap();
+        let hash = block["header"]["hash"].as_str().unwrap().to_string();
+
+        for shard in msg["shards"].as_array().unwrap() {
+            let mut chunk = shard["chunk"].clone();
+            if chunk.is_null() {
+                continue;
+            }
+            let transactions = shard["receipt_execution_outcomes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|outcome| self.add_tx(&hash, outcome))
+                .collect::<Vec<_>>();
+            chunk["transactions"] = Value::Array(transactions);
+            let chunk_hash = chunk["header"]["chunk_hash"].as_str().unwrap();
+            self.chunks.insert(chunk_hash.to_string(), chunk);
+        }
+
+        if self.head["header"]["height"].as_u64().unwrap_or(0) <= height {
+            self.head = block.clone();
+        }
+        self.blocks_by_height.insert(height, block.clone());
+        self.blocks_by_hash.insert(hash, block.clone());
+    }
+
+    /// Registers the final status of a transaction that results in the given
+    /// receipt outcome, and returns the transaction.
+    fn add_tx(&mut self, block_hash: &str, outcome: &Value) -> Value {
+        let receipt = &outcome["receipt"];
+        let action = &receipt["receipt"]["Action"];
+        let receipt_id = receipt["receipt_id"].as_str().unwrap();
+        let tx_hash = mock_hash(&format!("tx{}", receipt_id));
+
+        let is_converted = receipt["predecessor_id"] == action["signer_id"];
+        let (converted_id, receipts) = if is_converted {
+            (receipt_id.to_string(), vec![])
+        } else {
+            // has no outcome, hence doesn't show up in the stream
+            (
+                mock_hash(&format!("converted{}", receipt_id)),
+                vec![receipt.clone()],
+            )
+        };
+
+        let transaction = json!({
+            "signer_id": action["signer_id"],
+            "public_key": action["signer_public_key"],
+            "nonce": 0,
+            "receiver_id": if is_converted {
+                receipt["receiver_id"].clone()
+            } else {
+                receipt["predecessor_id"].clone()
+            },
+            "actions": action["actions"],
+            "signature": format!("ed25519:{}", "1".repeat(64)),
+            "hash": tx_hash,
+        });
+        let status = json!({
+            "status": { "SuccessValue": "" },
+            "transaction": transaction,
+            "transaction_outcome": {
+                "proof": [],
+                "block_hash": block_hash,
+                "id": tx_hash,
+                "outcome": {
+                    "logs": [],
+                    "receipt_ids": [converted_id],
+                    "gas_burnt": 0,
+                    "tokens_burnt": "0",
+                    "executor_id": action["signer_id"],
+                    "status": { "SuccessReceiptId": converted_id },
+                    "metadata": { "version": 1, "gas_profile": null },
+                },
+            },
+            "receipts_outcome": [outcome["execution_outcome"]],
+            "receipts": receipts,
+        });
+        self.txs.insert(tx_hash, status);
+        transaction
+    }
+
+    fn call(&self, method: &str, params: &Value) -> Result<Value, Value> {
+        let found = match method {
+            "block" => {
+                if params.get("finality").is_some() {
+                    Some(&self.head)
+                } else {
+                    match &params["block_id"] {
+                        Value::String(hash) => self.blocks_by_hash.get(hash),
+                        id => id
+                            .as_u64()
+                            .and_then(|h| self.blocks_by_height.get(&h)),
+                    }
+                }
+            }
+            "chunk" => params["chunk_id"]
+                .as_str()
+                .and_then(|hash| self.chunks.get(hash)),
+            "EXPERIMENTAL_tx_status" => {
+                params[0].as_str().and_then(|hash| self.txs.get(hash))
+            }
+            _ => {
+                return Err(json!({
+                    "name": "REQUEST_VALIDATION_ERROR",
+                    "cause": { "name": "METHOD_NOT_FOUND" },
+                }))
+            }
+        };
+        found.cloned().ok_or_else(|| {
+            let cause = match method {
+                "block" => "UNKNOWN_BLOCK",
+                "chunk" => "UNKNOWN_CHUNK",
+                _ => "UNKNOWN_TRANSACTION",
+            };
+            json!({ "name": "HANDLER_ERROR", "cause": { "name": cause } })
+        })
+    }
+}
+
+async fn handle_request(
+    chain: Arc<MockChain>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let bytes = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let req: Value = serde_json::from_slice(&bytes).unwrap();
+    let method = req["method"].as_str().unwrap_or_default();
+    let res = match chain.call(method, &req["params"]) {
+        Ok(result) => {
+            json!({ "jsonrpc": "2.0", "id": req["id"], "result": result })
+        }
+        Err(error) => {
+            json!({ "jsonrpc": "2.0", "id": req["id"], "error": error })
+        }
+    };
+    Ok(Response::new(Body::from(res.to_string())))
+}
+
+fn mock_hash(seed: &str) -> String {
+    CryptoHash::hash_bytes(seed.as_bytes()).to_string()
+}
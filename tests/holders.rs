@@ -0,0 +1,99 @@
+//! Holder snapshots at heights within a fixture. Requires `POSTGRES` like the
+//! handler tests.
+
+mod harness;
+
+use diesel::Connection;
+use minterop_indexer::Holder;
+
+#[actix_rt::test]
+async fn holders_follow_transfers() {
+    let fixture = harness::Fixture::load("reindex_out_of_order");
+    let db = harness::index_fixture("holders", &fixture).await;
+    let source = harness::jsonl_source("holders", &fixture);
+    let cfg = harness::fixture_config(&db, &fixture, source);
+    let contracts = vec!["fixture.mintbase1.near".to_string()];
+
+    let holders =
+        |height: u64| cfg.holders(&contracts, height).unwrap().holders;
+    let holder = |owner: &str| Holder {
+        owner: owner.to_string(),
+        tokens: 1,
+    };
+
+    assert!(holders(fixture.start_height() - 1).is_empty());
+    assert_eq!(holders(70000000), vec![holder("alice.near")]);
+    // the listing doesn't change the owner
+    assert_eq!(holders(70000002), vec![holder("bob.near")]);
+    assert_eq!(holders(70000003), vec![holder("carol.near")]);
+
+    let csv = cfg.holders(&contracts, 70000001).unwrap().to_csv();
+    assert_eq!(csv, "owner,tokens\nbob.near,1\n");
+    db.drop_db();
+}
+
+#[actix_rt::test]
+async fn holders_follow_transfers_within_a_receipt() {
+    let fixture = harness::Fixture::load("nft_transfer_within_receipt");
+    let db = harness::index_fixture("holders_within_receipt", &fixture).await;
+    let source = harness::jsonl_source("holders_within_receipt", &fixture);
+    let cfg = harness::fixture_config(&db, &fixture, source);
+    let contracts = vec!["fixture.mintbase1.near".to_string()];
+
+    // bob held the token only in between the two transfers of the receipt
+    let holders = cfg.holders(&contracts, 70000001).unwrap().holders;
+    assert_eq!(
+        holders,
+        vec![Holder {
+            owner: "carol.near".to_string(),
+            tokens: 1,
+        }]
+    );
+    db.drop_db();
+}
+
+#[actix_rt::test]
+async fn burned_tokens_have_no_holder() {
+    let fixture = harness::Fixture::load("nft_burn");
+    let db = harness::index_fixture("holders_nft_burn", &fixture).await;
+    let source = harness::jsonl_source("holders_nft_burn", &fixture);
+    let cfg = harness::fixture_config(&db, &fixture, source);
+    let contracts = vec!["fixture.mintbase1.near".to_string()];
+
+    let at_mint = cfg.holders(&contracts, 70000000).unwrap();
+    assert_eq!(at_mint.holders.len(), 1);
+    let at_burn = cfg.holders(&contracts, 70000001).unwrap();
+    assert!(at_burn.holders.is_empty());
+    db.drop_db();
+}
+
+#[actix_rt::test]
+async fn holders_need_a_known_height() {
+    let fixture = harness::Fixture::load("nft_burn");
+    let db = harness::index_fixture("holders_known_height", &fixture).await;
+    let source = harness::jsonl_source("holders_known_height", &fixture);
+    let cfg = harness::fixture_config(&db, &fixture, source);
+    let contracts = vec!["fixture.mintbase1.near".to_string()];
+
+    // not synced yet
+    assert!(cfg.holders(&contracts, fixture.stop_height() + 1).is_err());
+
+    // seeded in between the mint and the burn, i.e. after 70000000
+    db.connection()
+        .execute(
+            "INSERT INTO nft_ownership_changes VALUES (
+               'fixture.mintbase1.near', '2', 'seeded', 0, 0, NULL,
+               '2022-08-01T12:00:30', 'bob.near'
+             )",
+        )
+        .unwrap();
+    assert!(cfg.holders(&contracts, 70000000).is_err());
+    assert_eq!(
+        cfg.holders(&contracts, 70000001).unwrap().holders,
+        vec![Holder {
+            owner: "bob.near".to_string(),
+            tokens: 1,
+        }]
+    );
+    db.drop_db();
+}
@@ -0,0 +1,121 @@
+//! USD valuation of the earnings and sales of a fixture, with prices imported
+//! after and before indexing. Requires `POSTGRES` like the handler tests.
+
+mod harness;
+
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    net::SocketAddr,
+};
+
+use hyper::{
+    service::{
+        make_service_fn,
+        service_fn,
+    },
+    Body,
+    Response,
+};
+use minterop_indexer::PriceSource;
+
+const FIXTURE: &str = "market_v02_nft_sale_030";
+
+/// The sale happens at 12:03, so the price of 12:00 applies
+const PRICES_CSV: &str = "currency,timestamp,usd_price\n\
+                          near,2022-08-01T12:00:00,2.5\n\
+                          near,2022-08-01T12:05:00,3\n";
+
+const PRICES_JSON: &str = r#"[
+    {"currency": "near", "timestamp": "2022-08-01T12:00:00Z", "usd_price": 2.5},
+    {"currency": "near", "timestamp": "2022-08-01T12:05:00Z", "usd_price": 3}
+]"#;
+
+#[actix_rt::test]
+async fn imported_prices_revalue_indexed_sales() {
+    let fixture = harness::Fixture::load(FIXTURE);
+    let db = harness::index_fixture("prices_csv", &fixture).await;
+    let source = harness::jsonl_source("prices_csv", &fixture);
+    let cfg = harness::fixture_config(&db, &fixture, source);
+    assert!(earnings_in_usd(&db).values().all(|v| v == "null"));
+
+    let csv = std::env::temp_dir().join("minterop_prices.csv");
+    std::fs::write(&csv, PRICES_CSV).unwrap();
+    let imported = cfg.import_prices(&PriceSource::Csv(csv)).await.unwrap();
+    assert_eq!(imported.prices, 2);
+    // three earnings and the sale
+    assert_eq!(imported.revalued, 4);
+
+    assert_eq!(earnings_in_usd(&db), expected_earnings());
+    assert_eq!(sales_in_usd(&db), vec!["2.5"]);
+    db.drop_db();
+}
+
+#[actix_rt::test]
+async fn sales_are_valued_with_imported_prices() {
+    let fixture = harness::Fixture::load(FIXTURE);
+    let db = harness::TestDb::create("prices_http");
+    let source = harness::jsonl_source("prices_http", &fixture);
+    let cfg = harness::fixture_config(&db, &fixture, source);
+
+    let feed = PriceSource::Http(serve_prices(PRICES_JSON));
+    let imported = cfg.import_prices(&feed).await.unwrap();
+    assert_eq!(imported.prices, 2);
+    assert_eq!(imported.revalued, 0);
+
+    let rt = cfg.get_runtime().unwrap();
+    let (handle, streamer) = cfg.connect_blocks();
+    rt.handle_stream(streamer, minterop_indexer::no_shutdown())
+        .await;
+    minterop_indexer::join_lake_handle(handle).await;
+
+    assert_eq!(earnings_in_usd(&db), expected_earnings());
+    assert_eq!(sales_in_usd(&db), vec!["2.5"]);
+    db.drop_db();
+}
+
+fn expected_earnings() -> BTreeMap<String, String> {
+    BTreeMap::from([
+        ("alice.near".to_string(), "2.4".to_string()),
+        ("carol.near".to_string(), "0.025".to_string()),
+        (
+            "simple.market.mintbase1.near".to_string(),
+            "0.075".to_string(),
+        ),
+    ])
+}
+
+/// USD value of the earnings by receiver
+fn earnings_in_usd(db: &harness::TestDb) -> BTreeMap<String, String> {
+    db.rows("nft_earnings")
+        .iter()
+        .map(|row| {
+            (
+                row["receiver_id"].as_str().unwrap().to_string(),
+                row["usd_value"].to_string(),
+            )
+        })
+        .collect()
+}
+
+fn sales_in_usd(db: &harness::TestDb) -> Vec<String> {
+    db.rows("nft_activities")
+        .iter()
+        .filter(|row| row["kind"] == "sale")
+        .map(|row| row["usd_value"].to_string())
+        .collect()
+}
+
+/// Serves `body` on a random local port, standing in for a price feed
+fn serve_prices(body: &'static str) -> String {
+    let make_service = make_service_fn(move |_| async move {
+        Ok::<_, Infallible>(service_fn(move |_| async move {
+            Ok::<_, Infallible>(Response::new(Body::from(body)))
+        }))
+    });
+    let server = hyper::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+        .serve(make_service);
+    let url = format!("http://{}/prices", server.local_addr());
+    actix_rt::spawn(server);
+    url
+}
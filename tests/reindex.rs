@@ -0,0 +1,40 @@
+//! Reindexes a fixture with several workers, such that later blocks may be
+//! processed before earlier ones. Requires `POSTGRES` like the handler tests.
+
+mod harness;
+
+#[actix_rt::test]
+async fn reindex_reconciles_out_of_order_blocks() {
+    let fixture = harness::Fixture::load("reindex_out_of_order");
+    let db = harness::TestDb::create("reindex_out_of_order");
+    let source = harness::jsonl_source("reindex_out_of_order", &fixture);
+    let cfg = harness::fixture_config(&db, &fixture, source);
+
+    let (start, stop) = (fixture.start_height(), fixture.stop_height());
+    let jobs = minterop_indexer::reindex(
+        &cfg,
+        start,
+        stop,
+        2,
+        minterop_indexer::no_shutdown(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(jobs.len(), 2);
+    assert!(jobs.iter().all(|job| job.is_finished()));
+
+    // the live cursor is left alone
+    let blocks = db.rows("blocks");
+    assert_ne!(blocks[0]["synced_height"], stop);
+
+    cfg.reconcile(&jobs).unwrap();
+    // reconciling is idempotent
+    let fixed = cfg.reconcile(&jobs).unwrap();
+    assert_eq!(
+        fixed.owners + fixed.transfers + fixed.listings + fixed.offers,
+        0
+    );
+    assert_eq!(fixed.expired_offers, 0);
+
+    harness::assert_fixture(&fixture, db);
+}
@@ -0,0 +1,19 @@
+//! Runs handler fixtures through the RPC block source, with a mock node
+//! serving the fixture blocks. Requires `POSTGRES` like the handler tests.
+
+mod harness;
+
+macro_rules! rpc_fixture_tests {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[actix_rt::test]
+            async fn $name() {
+                harness::run_fixture_via_rpc(stringify!($name)).await;
+            }
+        )*
+    };
+}
+
+// covers receipts converted from transactions (mint by the store owner) and
+// cross-contract receipts (transfer by the market)
+rpc_fixture_tests!(nft_mint, market_v01_nft_sold, market_v02_nft_sale_030);
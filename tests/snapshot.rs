@@ -0,0 +1,50 @@
+//! Compares a snapshot of an indexed fixture against its golden file in
+//! `tests/golden`. Requires `POSTGRES` like the handler tests, and
+//! `UPDATE_GOLDEN=1` rewrites the golden file instead of comparing.
+
+mod harness;
+
+use std::path::PathBuf;
+
+use minterop_indexer::Snapshot;
+
+async fn assert_golden(name: &str) {
+    let fixture = harness::Fixture::load(name);
+    let db = harness::TestDb::create(&format!("snapshot_{}", name));
+    let source = harness::jsonl_source(&format!("snapshot_{}", name), &fixture);
+    let cfg = harness::fixture_config(&db, &fixture, source);
+
+    let rt = cfg.get_runtime().unwrap();
+    let (handle, streamer) = cfg.connect_blocks();
+    rt.handle_stream(streamer, minterop_indexer::no_shutdown())
+        .await;
+    minterop_indexer::join_lake_handle(handle).await;
+
+    let range = rt.replayed_range();
+    assert!(range.is_some(), "No blocks have been indexed");
+    let snapshot = cfg.snapshot(range.as_ref()).unwrap();
+    db.drop_db();
+
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let golden = dir.join(format!("{}.snap", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(&dir).unwrap();
+        snapshot.save(&golden).unwrap();
+        return;
+    }
+
+    let diff = Snapshot::load(&golden)
+        .unwrap_or_else(|e| panic!("{:?}, create it with UPDATE_GOLDEN=1", e))
+        .diff(&snapshot);
+    assert!(diff.is_empty(), "Snapshot differs:\n{}", diff);
+}
+
+#[actix_rt::test]
+async fn market_v02_nft_sale_030() {
+    assert_golden("market_v02_nft_sale_030").await;
+}
+
+#[actix_rt::test]
+async fn market_v02_wash_trade() {
+    assert_golden("market_v02_wash_trade").await;
+}
@@ -0,0 +1,62 @@
+//! Earnings ledger and statements of the accounts paid in a fixture sale.
+//! Requires `POSTGRES` like the handler tests.
+
+mod harness;
+
+use chrono::NaiveDate;
+use serde_json::json;
+
+#[actix_rt::test]
+async fn earnings_are_attributed_to_roles() {
+    let fixture = harness::Fixture::load("market_v02_nft_sale_030");
+    let db = harness::index_fixture("statements", &fixture).await;
+    let source = harness::jsonl_source("statements", &fixture);
+    let cfg = harness::fixture_config(&db, &fixture, source);
+
+    harness::assert_rows(
+        "nft_earnings_ledger",
+        &[
+            json!({
+                "account_id": "alice.near",
+                "currency": "near",
+                "role": "seller",
+                "earnings_count": 1,
+            }),
+            json!({
+                "account_id": "carol.near",
+                "currency": "near",
+                "role": "affiliate",
+                "earnings_count": 1,
+            }),
+            json!({
+                "account_id": "simple.market.mintbase1.near",
+                "currency": "near",
+                "role": "mintbase",
+                "earnings_count": 1,
+            }),
+        ],
+        db.rows("nft_earnings_ledger"),
+    );
+
+    let day = NaiveDate::from_ymd_opt(2022, 8, 1).unwrap();
+    let statement = cfg
+        .statement("alice.near", day, day.succ_opt().unwrap())
+        .unwrap();
+    assert_eq!(statement.lines.len(), 1);
+    assert_eq!(
+        statement.totals_to_csv(),
+        "currency,role,earnings_count,amount,amount_normalized,usd_value\n\
+         near,seller,1,960000000000000000000000,0.96,\n"
+    );
+
+    // the sale happened on the first day
+    let next_day = cfg
+        .statement(
+            "alice.near",
+            day.succ_opt().unwrap(),
+            day + chrono::Duration::days(2),
+        )
+        .unwrap();
+    assert!(next_day.lines.is_empty());
+    db.drop_db();
+}